@@ -61,6 +61,13 @@ Create the name of the service account to use
 {{- end }}
 {{- end }}
 
+{{/*
+Create the name of the secret holding the webhook certificate
+*/}}
+{{- define "cloudflare-tunnels-operator.webhookCertSecretName" -}}
+{{- default (printf "%s-webhook-tls" (include "cloudflare-tunnels-operator.fullname" .)) .Values.webhook.certSecretName }}
+{{- end }}
+
 {{/*
 Create the name of the ingress class to use
 */}}
@@ -0,0 +1,16 @@
+#![no_main]
+
+use cloudflare_tunnels_operator::cloudflare::TunnelConfig;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(yaml) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    // whatever parses has to survive being written back to the config map
+    if let Ok(config) = TunnelConfig::from_yaml(yaml) {
+        let yaml = config.to_yaml().unwrap();
+        TunnelConfig::from_yaml(&yaml).unwrap();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use cloudflare_tunnels_operator::cloudflare::TunnelCredentials;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<TunnelCredentials>(data);
+
+    if let Ok(token) = std::str::from_utf8(data) {
+        let _ = TunnelCredentials::from_token(token);
+    }
+});
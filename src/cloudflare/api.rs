@@ -0,0 +1,214 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use cloudflare::endpoints::dns::{DnsContent, DnsRecord};
+
+use super::{
+    AccessApplication, LoadBalancer, LoadBalancerPool, TunnelConfig, TunnelConnection,
+    TunnelCredentials, TunnelRoute,
+};
+use crate::Error;
+
+// Operations the controllers need from Cloudflare, so tests can swap in a
+// double for the HTTP client.
+#[async_trait]
+pub trait CloudflareApi: Send + Sync {
+    // remote tunnels get their config from the api instead of a local file
+    async fn create_tunnel(
+        &self,
+        tunnel_name: &str,
+        remote_config: bool,
+    ) -> Result<TunnelCredentials, Error>;
+
+    async fn find_tunnel(&self, tunnel_name: &str) -> Result<Option<String>, Error>;
+
+    async fn get_tunnel(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error>;
+
+    async fn get_tunnel_token(&self, tunnel_id: &str) -> Result<String, Error>;
+
+    async fn delete_tunnel(&self, tunnel_id: &str) -> Result<(), Error>;
+
+    async fn rotate_tunnel_secret(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error>;
+
+    async fn put_tunnel_configuration(
+        &self,
+        tunnel_id: &str,
+        config: &TunnelConfig,
+    ) -> Result<(), Error>;
+
+    async fn get_tunnel_connections(&self, tunnel_id: &str)
+        -> Result<Vec<TunnelConnection>, Error>;
+
+    async fn list_tunnel_routes(&self, tunnel_id: &str) -> Result<Vec<TunnelRoute>, Error>;
+
+    async fn add_tunnel_route(&self, tunnel_id: &str, network: &str) -> Result<(), Error>;
+
+    async fn delete_tunnel_route(&self, route_id: &str) -> Result<(), Error>;
+
+    async fn create_dns_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: DnsContent,
+    ) -> Result<(), Error>;
+
+    async fn create_a_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: Ipv4Addr,
+    ) -> Result<(), Error> {
+        self.create_dns_record(zone_id, hostname, DnsContent::A { content })
+            .await
+    }
+
+    async fn create_aaaa_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: Ipv6Addr,
+    ) -> Result<(), Error> {
+        self.create_dns_record(zone_id, hostname, DnsContent::AAAA { content })
+            .await
+    }
+
+    async fn create_txt_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: &str,
+    ) -> Result<(), Error> {
+        let content = content.to_string();
+        self.create_dns_record(zone_id, hostname, DnsContent::TXT { content })
+            .await
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error>;
+
+    // content defaults to the tunnel's CNAME
+    async fn update_dns_record(
+        &self,
+        zone_id: &str,
+        domain_id: &str,
+        hostname: &str,
+        tunnel_id: &str,
+        content: Option<DnsContent>,
+    ) -> Result<(), Error>;
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        domain_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error>;
+
+    // every record at the name, whatever its type
+    async fn list_dns_records(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Vec<Arc<DnsRecord>>, Error>;
+
+    // the first record at the name, for names that only hold the tunnel's CNAME
+    async fn find_dns_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Option<Arc<DnsRecord>>, Error> {
+        Ok(self
+            .list_dns_records(zone_id, hostname)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn find_zone_id(&self, hostname: &str) -> Result<Option<String>, Error>;
+
+    // looks the zone up by the apex domain of the hostname instead of listing
+    // every zone of the account
+    async fn find_zone_id_for_hostname(&self, hostname: &str) -> Result<Option<String>, Error> {
+        self.find_zone_id(hostname).await
+    }
+
+    // false when the credentials can't read the zone
+    async fn has_zone_access(&self, zone_id: &str) -> Result<bool, Error>;
+
+    async fn delete_dns_record(&self, zone_id: &str, domain_id: &str) -> Result<(), Error>;
+
+    async fn find_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Option<AccessApplication>, Error>;
+
+    async fn create_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<AccessApplication, Error>;
+
+    async fn create_access_policy(
+        &self,
+        zone_id: &str,
+        app_id: &str,
+        policy_name: &str,
+        include: &[serde_json::Value],
+    ) -> Result<(), Error>;
+
+    async fn delete_access_application(&self, zone_id: &str, app_id: &str) -> Result<(), Error>;
+
+    async fn get_load_balancer_pool(
+        &self,
+        pool_id: &str,
+    ) -> Result<Option<LoadBalancerPool>, Error>;
+
+    async fn create_load_balancer_pool(
+        &self,
+        name: &str,
+        origin: &str,
+    ) -> Result<LoadBalancerPool, Error>;
+
+    // None detaches the monitor of the pool
+    async fn set_load_balancer_pool_monitor(
+        &self,
+        pool_id: &str,
+        monitor_id: Option<&str>,
+    ) -> Result<(), Error>;
+
+    async fn delete_load_balancer_pool(&self, pool_id: &str) -> Result<(), Error>;
+
+    async fn list_load_balancers(&self, zone_id: &str) -> Result<Vec<LoadBalancer>, Error>;
+
+    async fn create_load_balancer(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        pool_id: &str,
+    ) -> Result<LoadBalancer, Error>;
+
+    async fn delete_load_balancer(
+        &self,
+        zone_id: &str,
+        load_balancer_id: &str,
+    ) -> Result<(), Error>;
+
+    async fn set_zone_setting(
+        &self,
+        zone_id: &str,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Result<(), Error>;
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use cloudflare::endpoints::dns::DnsRecord;
+
+const DNS_RECORD_TTL: Duration = Duration::from_secs(60);
+const TUNNEL_TTL: Duration = Duration::from_secs(300);
+
+struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((value, inserted)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: K, value: V) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (value, Instant::now()));
+    }
+
+    fn invalidate(&self, f: impl Fn(&K) -> bool) {
+        self.entries.lock().unwrap().retain(|key, _| !f(key));
+    }
+}
+
+// Shared across clients so lookups are cached between reconciliations.
+#[derive(Clone)]
+pub struct ApiCache {
+    dns_records: Arc<TtlCache<(String, String), Vec<Arc<DnsRecord>>>>,
+    tunnels: Arc<TtlCache<(String, String), Option<String>>>,
+}
+
+impl Default for ApiCache {
+    fn default() -> Self {
+        Self {
+            dns_records: Arc::new(TtlCache::new(DNS_RECORD_TTL)),
+            tunnels: Arc::new(TtlCache::new(TUNNEL_TTL)),
+        }
+    }
+}
+
+impl ApiCache {
+    pub(super) fn dns_records(&self, zone_id: &str, hostname: &str) -> Option<Vec<Arc<DnsRecord>>> {
+        self.dns_records
+            .get(&(zone_id.to_string(), hostname.to_string()))
+    }
+
+    pub(super) fn set_dns_records(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        records: Vec<Arc<DnsRecord>>,
+    ) {
+        self.dns_records
+            .insert((zone_id.to_string(), hostname.to_string()), records);
+    }
+
+    pub(super) fn invalidate_dns_records(&self, zone_id: &str) {
+        self.dns_records.invalidate(|(zone, _)| zone == zone_id);
+    }
+
+    pub(super) fn tunnel(&self, account_id: &str, tunnel_name: &str) -> Option<Option<String>> {
+        self.tunnels
+            .get(&(account_id.to_string(), tunnel_name.to_string()))
+    }
+
+    pub(super) fn set_tunnel(
+        &self,
+        account_id: &str,
+        tunnel_name: &str,
+        tunnel_id: Option<String>,
+    ) {
+        self.tunnels
+            .insert((account_id.to_string(), tunnel_name.to_string()), tunnel_id);
+    }
+
+    pub(super) fn invalidate_tunnels(&self, account_id: &str) {
+        self.tunnels
+            .invalidate(|(account, _)| account == account_id);
+    }
+}
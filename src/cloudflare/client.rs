@@ -1,44 +1,415 @@
-use crate::Error;
+use crate::{metrics::Metrics, Error};
+use async_trait::async_trait;
 use base64::{prelude::BASE64_STANDARD, Engine};
-use cloudflare::endpoints::dns::DnsRecord;
+use cloudflare::{
+    endpoints::dns::{DnsContent, DnsRecord},
+    framework::{
+        auth::AuthClient,
+        endpoint::{Endpoint, Method},
+        response::{ApiErrors, ApiFailure, ApiResponse, ApiResult, ApiSuccess},
+        Environment,
+    },
+};
+use opentelemetry::{global, trace::TraceContextExt};
 use rand::RngCore;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Serialize;
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use tracing::{info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use super::TunnelCredentials;
+use super::{
+    correlation_id, endpoint, AccessApplication, ApiCache, CloudflareApi, LoadBalancer,
+    LoadBalancerPool, TunnelConfig, TunnelConnection, TunnelCredentials, TunnelRoute,
+};
 pub use cloudflare::framework::auth::Credentials;
 
+const PER_PAGE: u32 = 50;
+const TUNNEL_ALREADY_EXISTS: u16 = 1107;
+const CORRELATION_ID_HEADER: &'static str = "x-correlation-id";
+
+// Wildcard records are named relative to their zone, e.g. `*.example.com`
+// in zone `example.com` becomes `*`.
+fn wildcard_record_name(hostname: &str, zone_name: &str) -> Option<String> {
+    let domain = hostname.strip_prefix("*.")?;
+    if domain == zone_name {
+        return Some("*".to_string());
+    }
+
+    domain
+        .strip_suffix(&format!(".{zone_name}"))
+        .map(|subdomain| format!("*.{subdomain}"))
+}
+
+// The last two labels, e.g. `example.com` for `app.eu.example.com`.
+fn apex_domain(hostname: &str) -> &str {
+    let hostname = hostname.trim_end_matches('.');
+    match hostname.rmatch_indices('.').nth(1) {
+        Some((index, _)) => &hostname[index + 1..],
+        None => hostname,
+    }
+}
+
+// only address records can be proxied through cloudflare
+fn proxiable(content: &DnsContent) -> bool {
+    matches!(
+        content,
+        DnsContent::A { .. } | DnsContent::AAAA { .. } | DnsContent::CNAME { .. }
+    )
+}
+
+const ORIGIN_REQUEST_DURATIONS: [&'static str; 4] = [
+    "tlsTimeout",
+    "connectTimeout",
+    "keepAliveTimeout",
+    "tcpKeepAlive",
+];
+
+// The api takes the same config as the yaml file, minus the tunnel and
+// credentials which cloudflared already knows, and timeouts in seconds.
+fn remote_config(config: &TunnelConfig) -> Result<serde_json::Value, Error> {
+    let mut value = serde_json::to_value(config)
+        .map_err(|err| anyhow::anyhow!("failed to serialize tunnel config: {err}"))?;
+    let Some(fields) = value.as_object_mut() else {
+        return Ok(value);
+    };
+    fields.remove("tunnel");
+    fields.remove("credentials-file");
+
+    let origin_requests = fields
+        .get_mut("ingress")
+        .and_then(|ingress| ingress.as_array_mut())
+        .into_iter()
+        .flatten()
+        .filter_map(|ingress| ingress.get_mut("originRequest"));
+    for origin_request in origin_requests.chain(fields.get_mut("originRequest")) {
+        for key in ORIGIN_REQUEST_DURATIONS {
+            let Some(duration) = origin_request.get_mut(key) else {
+                continue;
+            };
+            if let Some(seconds) = duration
+                .as_str()
+                .and_then(|duration| humantime::parse_duration(duration).ok())
+            {
+                *duration = seconds.as_secs().into();
+            }
+        }
+    }
+
+    Ok(value)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CloudflareEnvironment {
+    #[default]
+    Production,
+    // talks to the api at the configured base url instead
+    Staging,
+}
+
+impl FromStr for CloudflareEnvironment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "production" => Ok(CloudflareEnvironment::Production),
+            "staging" => Ok(CloudflareEnvironment::Staging),
+            _ => Err(anyhow::anyhow!("unsupported cloudflare environment {s}").into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClientConfig {
+    pub timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+        }
+    }
+}
+
+fn http_client(
+    config: &ClientConfig,
+    default_headers: HeaderMap,
+) -> Result<reqwest::Client, Error> {
+    reqwest::Client::builder()
+        .default_headers(default_headers)
+        .timeout(config.timeout)
+        .build()
+        .map_err(|err| anyhow::anyhow!("failed to build the http client: {err}").into())
+}
+
+// The traceparent of a sampled span, nothing for unsampled ones and every
+// request without trace export.
+fn trace_headers(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let context = span.context();
+    if context.span().span_context().is_sampled() {
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut headers)
+        });
+    }
+
+    headers
+}
+
+// a request that timed out may still have been applied, so only idempotent
+// ones are sent again
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::PUT | Method::DELETE)
+}
+
 pub struct Client {
     account_id: String,
-    client: cloudflare::framework::async_api::Client,
+    client: reqwest::Client,
+    credentials: Credentials,
+    environment: Environment,
+    metrics: Metrics,
+    config: ClientConfig,
+    cache: Option<ApiCache>,
 }
 
 impl Client {
-    pub fn new(account_id: String, credentials: Credentials) -> Result<Self, Error> {
-        let client = cloudflare::framework::async_api::Client::new(
+    pub fn new(
+        account_id: String,
+        credentials: Credentials,
+        metrics: Metrics,
+        config: ClientConfig,
+        environment: Environment,
+    ) -> Result<Self, Error> {
+        let mut default_headers = HeaderMap::new();
+        if let Some(value) = correlation_id().and_then(|id| HeaderValue::from_str(&id).ok()) {
+            default_headers.insert(CORRELATION_ID_HEADER, value);
+        }
+
+        let client = http_client(&config, default_headers)?;
+
+        Ok(Self {
+            account_id,
+            client,
             credentials,
-            cloudflare::framework::HttpApiClientConfig::default(),
-            cloudflare::framework::Environment::Production,
-        )?;
+            environment,
+            metrics,
+            config,
+            cache: None,
+        })
+    }
 
-        Ok(Self { account_id, client })
+    pub fn with_cache(
+        account_id: String,
+        credentials: Credentials,
+        metrics: Metrics,
+        config: ClientConfig,
+        environment: Environment,
+        cache: ApiCache,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            cache: Some(cache),
+            ..Self::new(account_id, credentials, metrics, config, environment)?
+        })
     }
 
-    pub async fn create_tunnel(&self, tunnel_name: &str) -> Result<TunnelCredentials, Error> {
+    // Sends the endpoint's request the way the cloudflare client does, plus
+    // the trace headers of its span.
+    async fn send<ResultType, QueryType, BodyType>(
+        &self,
+        endpoint: &(dyn Endpoint<ResultType, QueryType, BodyType> + Send + Sync),
+        trace_headers: HashMap<String, String>,
+    ) -> ApiResponse<ResultType>
+    where
+        ResultType: ApiResult,
+        QueryType: Serialize,
+        BodyType: Serialize,
+    {
+        let mut request = self
+            .client
+            .request(endpoint.method(), endpoint.url(&self.environment))
+            .query(&endpoint.query())
+            .auth(&self.credentials);
+        if let Some(body) = endpoint.body() {
+            request = request.json(&body);
+        }
+        for (name, value) in trace_headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(ApiFailure::Invalid)?;
+        let status = response.status();
+        if !status.is_success() {
+            let errors = response.json::<ApiErrors>().await.unwrap_or_default();
+            return Err(ApiFailure::Error(status, errors));
+        }
+
+        response
+            .json::<ApiSuccess<ResultType>>()
+            .await
+            .map_err(ApiFailure::Invalid)
+    }
+
+    async fn request<ResultType, QueryType, BodyType>(
+        &self,
+        name: &str,
+        endpoint: &(dyn Endpoint<ResultType, QueryType, BodyType> + Send + Sync),
+    ) -> ApiResponse<ResultType>
+    where
+        ResultType: ApiResult,
+        QueryType: Serialize,
+        BodyType: Serialize,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let response = {
+                let _timer = self
+                    .metrics
+                    .cloudflare_api_request_duration_seconds
+                    .with_label_values(&[name])
+                    .start_timer();
+
+                let span = info_span!(
+                    "cloudflare.request",
+                    endpoint = name,
+                    attempt,
+                    cloudflare.account_id = %self.account_id,
+                );
+                let trace_headers = trace_headers(&span);
+
+                self.send(endpoint, trace_headers).instrument(span).await
+            };
+
+            // authentication and validation errors won't succeed on retry
+            let retryable = match &response {
+                Err(ApiFailure::Error(status, _)) => status.is_server_error(),
+                Err(ApiFailure::Invalid(err)) => {
+                    if err.is_timeout() {
+                        self.metrics
+                            .cloudflare_api_timeout_total
+                            .with_label_values(&[name])
+                            .inc();
+                    }
+                    err.is_timeout() || err.is_connect()
+                }
+                Ok(_) => false,
+            };
+
+            if !retryable
+                || !is_idempotent(&endpoint.method())
+                || attempt >= self.config.max_retries
+            {
+                return response;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500 * (1 << attempt))).await;
+            attempt += 1;
+        }
+    }
+
+    async fn paginate_all<R, QueryType, BodyType, E, F>(
+        &self,
+        name: &str,
+        endpoint_factory: F,
+    ) -> Result<Vec<R>, Error>
+    where
+        Vec<R>: ApiResult,
+        QueryType: Serialize,
+        BodyType: Serialize,
+        E: Endpoint<Vec<R>, QueryType, BodyType> + Send + Sync,
+        F: Fn(u32) -> E,
+    {
+        let mut results = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let endpoint = endpoint_factory(page);
+            let response = self.request(name, &endpoint).await?;
+
+            let fetched = response.result.len();
+            results.extend(response.result);
+
+            let total_pages = response
+                .result_info
+                .as_ref()
+                .and_then(|info| info.get("total_pages"))
+                .and_then(|total_pages| total_pages.as_u64());
+
+            let has_next = match total_pages {
+                Some(total_pages) => (page as u64) < total_pages,
+                None => fetched as u32 >= PER_PAGE,
+            };
+
+            if !has_next {
+                break;
+            }
+
+            page += 1;
+        }
+
+        Ok(results)
+    }
+
+    async fn record_name(&self, zone_id: &str, hostname: &str) -> Result<String, Error> {
+        if !hostname.starts_with("*.") {
+            return Ok(hostname.to_string());
+        }
+
+        let endpoint = cloudflare::endpoints::zone::ZoneDetails {
+            identifier: zone_id,
+        };
+        let zone = self.request("zone_details", &endpoint).await?.result;
+
+        Ok(wildcard_record_name(hostname, &zone.name).unwrap_or_else(|| hostname.to_string()))
+    }
+}
+
+#[async_trait]
+impl CloudflareApi for Client {
+    async fn create_tunnel(
+        &self,
+        tunnel_name: &str,
+        remote_config: bool,
+    ) -> Result<TunnelCredentials, Error> {
         let mut tunnel_secret = [0u8; 32];
         rand::thread_rng().fill_bytes(&mut tunnel_secret);
 
         let tunnel_secret = tunnel_secret.to_vec();
+        let config_src = if remote_config {
+            cloudflare::endpoints::cfd_tunnel::ConfigurationSrc::Cloudflare
+        } else {
+            cloudflare::endpoints::cfd_tunnel::ConfigurationSrc::Local
+        };
 
         let endpoint = cloudflare::endpoints::cfd_tunnel::create_tunnel::CreateTunnel {
             account_identifier: &self.account_id,
             params: cloudflare::endpoints::cfd_tunnel::create_tunnel::Params {
                 name: &tunnel_name,
                 tunnel_secret: &tunnel_secret,
-                config_src: &cloudflare::endpoints::cfd_tunnel::ConfigurationSrc::Local,
+                config_src: &config_src,
                 metadata: None,
             },
         };
 
-        let response = self.client.request(&endpoint).await?;
+        let response = self.request("create_tunnel", &endpoint).await;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_tunnels(&self.account_id);
+        }
+
+        let response = match response {
+            Err(ApiFailure::Error(_, errors))
+                if errors
+                    .errors
+                    .iter()
+                    .any(|err| err.code == TUNNEL_ALREADY_EXISTS) =>
+            {
+                return Err(Error::TunnelAlreadyExists(tunnel_name.to_string()));
+            }
+            response => response?,
+        };
 
         let tunnel_credentials = TunnelCredentials {
             account_tag: self.account_id.to_owned(),
@@ -49,108 +420,703 @@ impl Client {
         Ok(tunnel_credentials)
     }
 
-    pub async fn find_tunnel(&self, tunnel_name: &str) -> Result<Option<String>, Error> {
-        let endpoint = cloudflare::endpoints::cfd_tunnel::list_tunnels::ListTunnels {
+    async fn find_tunnel(&self, tunnel_name: &str) -> Result<Option<String>, Error> {
+        if let Some(tunnel_id) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.tunnel(&self.account_id, tunnel_name))
+        {
+            return Ok(tunnel_id);
+        }
+
+        let tunnels = self
+            .paginate_all("find_tunnel", |page| {
+                cloudflare::endpoints::cfd_tunnel::list_tunnels::ListTunnels {
+                    account_identifier: &self.account_id,
+                    params: cloudflare::endpoints::cfd_tunnel::list_tunnels::Params {
+                        name: Some(tunnel_name.to_owned()),
+                        is_deleted: Some(false),
+                        page: Some(page),
+                        per_page: Some(PER_PAGE),
+                        ..cloudflare::endpoints::cfd_tunnel::list_tunnels::Params::default()
+                    },
+                }
+            })
+            .await?;
+
+        let tunnel_id = tunnels.first().map(|tunnel| tunnel.id.to_string());
+        if let Some(cache) = &self.cache {
+            cache.set_tunnel(&self.account_id, tunnel_name, tunnel_id.clone());
+        }
+
+        Ok(tunnel_id)
+    }
+
+    async fn get_tunnel(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        let token = self.get_tunnel_token(tunnel_id).await?;
+
+        TunnelCredentials::from_token(&token)
+    }
+
+    async fn get_tunnel_token(&self, tunnel_id: &str) -> Result<String, Error> {
+        let endpoint = endpoint::GetTunnelToken {
             account_identifier: &self.account_id,
-            params: cloudflare::endpoints::cfd_tunnel::list_tunnels::Params {
-                name: Some(tunnel_name.to_owned()),
-                is_deleted: Some(false),
-                ..cloudflare::endpoints::cfd_tunnel::list_tunnels::Params::default()
-            },
+            tunnel_id,
         };
 
-        let response = self.client.request(&endpoint).await?;
+        let response = self.request("get_tunnel_token", &endpoint).await?;
 
-        Ok(response.result.first().map(|tunnel| tunnel.id.to_string()))
+        Ok(response.result.0)
     }
 
-    pub async fn delete_tunnel(&self, tunnel_id: &str) -> Result<(), Error> {
+    async fn delete_tunnel(&self, tunnel_id: &str) -> Result<(), Error> {
         let endpoint = cloudflare::endpoints::cfd_tunnel::delete_tunnel::DeleteTunnel {
             account_identifier: &self.account_id,
             tunnel_id,
             params: cloudflare::endpoints::cfd_tunnel::delete_tunnel::Params { cascade: true },
         };
 
-        self.client.request(&endpoint).await?;
+        self.request("delete_tunnel", &endpoint).await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_tunnels(&self.account_id);
+        }
+
+        Ok(())
+    }
+
+    async fn rotate_tunnel_secret(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        let mut tunnel_secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut tunnel_secret);
+
+        let tunnel_secret = BASE64_STANDARD.encode(tunnel_secret);
+
+        let endpoint = endpoint::UpdateTunnelSecret {
+            account_identifier: &self.account_id,
+            tunnel_id,
+            params: endpoint::UpdateTunnelSecretParams {
+                tunnel_secret: &tunnel_secret,
+            },
+        };
+
+        let response = self.request("rotate_tunnel_secret", &endpoint).await?;
+
+        Ok(TunnelCredentials {
+            account_tag: self.account_id.to_owned(),
+            tunnel_secret,
+            tunnel_id: response.result.id,
+        })
+    }
+
+    async fn put_tunnel_configuration(
+        &self,
+        tunnel_id: &str,
+        config: &TunnelConfig,
+    ) -> Result<(), Error> {
+        let endpoint = endpoint::PutTunnelConfiguration {
+            account_identifier: &self.account_id,
+            tunnel_id,
+            config: remote_config(config)?,
+        };
+
+        self.request("put_tunnel_configuration", &endpoint).await?;
+
+        Ok(())
+    }
+
+    async fn get_tunnel_connections(
+        &self,
+        tunnel_id: &str,
+    ) -> Result<Vec<TunnelConnection>, Error> {
+        let endpoint = endpoint::GetTunnelConnections {
+            account_identifier: &self.account_id,
+            tunnel_id,
+        };
+
+        let response = self.request("get_tunnel_connections", &endpoint).await?;
+
+        Ok(response.result)
+    }
+
+    async fn list_tunnel_routes(&self, tunnel_id: &str) -> Result<Vec<TunnelRoute>, Error> {
+        self.paginate_all("list_tunnel_routes", |page| endpoint::ListTunnelRoutes {
+            account_identifier: &self.account_id,
+            params: endpoint::ListTunnelRoutesParams {
+                tunnel_id: tunnel_id.to_string(),
+                is_deleted: false,
+                page,
+                per_page: PER_PAGE,
+            },
+        })
+        .await
+    }
+
+    async fn add_tunnel_route(&self, tunnel_id: &str, network: &str) -> Result<(), Error> {
+        let endpoint = endpoint::CreateTunnelRoute {
+            account_identifier: &self.account_id,
+            params: endpoint::CreateTunnelRouteParams {
+                network,
+                tunnel_id,
+                comment: Some(endpoint::MANAGED_ROUTE_COMMENT),
+            },
+        };
+
+        self.request("add_tunnel_route", &endpoint).await?;
 
         Ok(())
     }
 
-    pub async fn create_dns_record(
+    async fn delete_tunnel_route(&self, route_id: &str) -> Result<(), Error> {
+        let endpoint = endpoint::DeleteTunnelRoute {
+            account_identifier: &self.account_id,
+            route_id,
+        };
+
+        self.request("delete_tunnel_route", &endpoint).await?;
+
+        Ok(())
+    }
+
+    async fn create_dns_record(
         &self,
         zone_id: &str,
         hostname: &str,
-        content: &str,
+        content: DnsContent,
     ) -> Result<(), Error> {
+        let proxied = proxiable(&content);
+        self.create_record(zone_id, hostname, content, None, Some(proxied))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        let name = self.record_name(zone_id, hostname).await?;
         let endpoint = cloudflare::endpoints::dns::CreateDnsRecord {
             zone_identifier: zone_id,
             params: cloudflare::endpoints::dns::CreateDnsRecordParams {
-                proxied: Some(true),
-                name: hostname,
-                content: cloudflare::endpoints::dns::DnsContent::CNAME {
-                    content: content.to_string(),
-                },
-                ttl: None,
+                proxied,
+                name: &name,
+                content,
+                ttl,
                 priority: None,
             },
         };
 
-        self.client.request(&endpoint).await?;
+        let response = self.request("create_dns_record", &endpoint).await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_dns_records(zone_id);
+        }
 
-        Ok(())
+        Ok(response.result)
     }
 
-    pub async fn update_dns_record(
+    async fn update_dns_record(
         &self,
         zone_id: &str,
         domain_id: &str,
         hostname: &str,
         tunnel_id: &str,
+        content: Option<DnsContent>,
     ) -> Result<(), Error> {
+        let content = content.unwrap_or_else(|| DnsContent::CNAME {
+            content: format!("{tunnel_id}.cfargotunnel.com"),
+        });
+        let proxied = proxiable(&content);
+        self.update_record(zone_id, domain_id, hostname, content, None, Some(proxied))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        domain_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        let name = self.record_name(zone_id, hostname).await?;
         let endpoint = cloudflare::endpoints::dns::UpdateDnsRecord {
             zone_identifier: zone_id,
             identifier: domain_id,
             params: cloudflare::endpoints::dns::UpdateDnsRecordParams {
-                proxied: Some(true),
+                proxied,
+                name: &name,
+                content,
+                ttl,
+            },
+        };
+
+        let response = self.request("update_dns_record", &endpoint).await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_dns_records(zone_id);
+        }
+
+        Ok(response.result)
+    }
+
+    async fn list_dns_records(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Vec<Arc<DnsRecord>>, Error> {
+        if let Some(records) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.dns_records(zone_id, hostname))
+        {
+            return Ok(records);
+        }
+
+        let records = self
+            .paginate_all("list_dns_records", |page| {
+                cloudflare::endpoints::dns::ListDnsRecords {
+                    zone_identifier: zone_id,
+                    params: cloudflare::endpoints::dns::ListDnsRecordsParams {
+                        name: Some(hostname.to_string()),
+                        page: Some(page),
+                        per_page: Some(PER_PAGE),
+                        ..cloudflare::endpoints::dns::ListDnsRecordsParams::default()
+                    },
+                }
+            })
+            .await?;
+
+        // records are listed by their fully qualified name, so a wildcard
+        // filter must only match the literal wildcard record
+        let records: Vec<_> = records
+            .into_iter()
+            .filter(|record| !hostname.starts_with("*.") || record.name == hostname)
+            .map(Arc::new)
+            .collect();
+        if let Some(cache) = &self.cache {
+            cache.set_dns_records(zone_id, hostname, records.clone());
+        }
+
+        Ok(records)
+    }
+
+    async fn find_zone_id(&self, hostname: &str) -> Result<Option<String>, Error> {
+        let zones = self
+            .paginate_all("find_zone_id", |page| {
+                cloudflare::endpoints::zone::ListZones {
+                    params: cloudflare::endpoints::zone::ListZonesParams {
+                        page: Some(page),
+                        per_page: Some(PER_PAGE),
+                        ..cloudflare::endpoints::zone::ListZonesParams::default()
+                    },
+                }
+            })
+            .await?;
+
+        Ok(zones
+            .into_iter()
+            .filter(|zone| hostname == zone.name || hostname.ends_with(&format!(".{}", zone.name)))
+            .max_by_key(|zone| zone.name.len())
+            .map(|zone| zone.id))
+    }
+
+    async fn find_zone_id_for_hostname(&self, hostname: &str) -> Result<Option<String>, Error> {
+        let apex = apex_domain(hostname);
+        let zones = self
+            .paginate_all("find_zone_id_for_hostname", |page| {
+                cloudflare::endpoints::zone::ListZones {
+                    params: cloudflare::endpoints::zone::ListZonesParams {
+                        name: Some(apex.to_string()),
+                        page: Some(page),
+                        per_page: Some(PER_PAGE),
+                        ..cloudflare::endpoints::zone::ListZonesParams::default()
+                    },
+                }
+            })
+            .await?;
+
+        match zones.as_slice() {
+            // the apex may be a public suffix like co.uk, fall back to matching every zone
+            [] => self.find_zone_id(hostname).await,
+            [zone] => Ok(Some(zone.id.clone())),
+            _ => Err(anyhow::anyhow!(
+                "{} zones named {apex} are accessible, set spec.cloudflare.zoneId to pick one",
+                zones.len()
+            )
+            .into()),
+        }
+    }
+
+    async fn has_zone_access(&self, zone_id: &str) -> Result<bool, Error> {
+        let endpoint = cloudflare::endpoints::zone::ZoneDetails {
+            identifier: zone_id,
+        };
+
+        match self.request("zone_details", &endpoint).await {
+            Ok(_) => Ok(true),
+            Err(ApiFailure::Error(status, _)) if matches!(status.as_u16(), 403 | 404) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete_dns_record(&self, zone_id: &str, domain_id: &str) -> Result<(), Error> {
+        let endpoint = cloudflare::endpoints::dns::DeleteDnsRecord {
+            zone_identifier: zone_id,
+            identifier: domain_id,
+        };
+
+        self.request("delete_dns_record", &endpoint).await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_dns_records(zone_id);
+        }
+
+        Ok(())
+    }
+
+    async fn find_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Option<AccessApplication>, Error> {
+        let apps = self
+            .paginate_all("list_access_applications", |page| {
+                endpoint::ListAccessApplications {
+                    zone_identifier: zone_id,
+                    params: endpoint::ListAccessApplicationsParams {
+                        page,
+                        per_page: PER_PAGE,
+                    },
+                }
+            })
+            .await?;
+
+        Ok(apps.into_iter().find(|app| app.domain == hostname))
+    }
+
+    async fn create_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<AccessApplication, Error> {
+        let endpoint = endpoint::CreateAccessApplication {
+            zone_identifier: zone_id,
+            params: endpoint::CreateAccessApplicationParams {
                 name: hostname,
-                content: cloudflare::endpoints::dns::DnsContent::CNAME {
-                    content: format!("{tunnel_id}.cfargotunnel.com"),
-                },
-                ttl: None,
+                domain: hostname,
+                app_type: "self_hosted",
+            },
+        };
+
+        let app = self.request("create_access_application", &endpoint).await?;
+
+        Ok(app.result)
+    }
+
+    async fn create_access_policy(
+        &self,
+        zone_id: &str,
+        app_id: &str,
+        policy_name: &str,
+        include: &[serde_json::Value],
+    ) -> Result<(), Error> {
+        let endpoint = endpoint::CreateAccessPolicy {
+            zone_identifier: zone_id,
+            app_id,
+            params: endpoint::CreateAccessPolicyParams {
+                name: policy_name,
+                decision: "allow",
+                include: serde_json::Value::Array(include.to_vec()),
+            },
+        };
+
+        self.request("create_access_policy", &endpoint).await?;
+
+        Ok(())
+    }
+
+    async fn delete_access_application(&self, zone_id: &str, app_id: &str) -> Result<(), Error> {
+        let endpoint = endpoint::DeleteAccessApplication {
+            zone_identifier: zone_id,
+            app_id,
+        };
+
+        // already gone, e.g. deleted by hand
+        match self.request("delete_access_application", &endpoint).await {
+            Ok(_) => Ok(()),
+            Err(ApiFailure::Error(status, _)) if status.as_u16() == 404 => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn get_load_balancer_pool(
+        &self,
+        pool_id: &str,
+    ) -> Result<Option<LoadBalancerPool>, Error> {
+        let endpoint = endpoint::GetLoadBalancerPool {
+            account_identifier: &self.account_id,
+            pool_id,
+        };
+
+        match self.request("get_load_balancer_pool", &endpoint).await {
+            Ok(pool) => Ok(Some(pool.result)),
+            Err(ApiFailure::Error(status, _)) if status.as_u16() == 404 => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn create_load_balancer_pool(
+        &self,
+        name: &str,
+        origin: &str,
+    ) -> Result<LoadBalancerPool, Error> {
+        let endpoint = endpoint::CreateLoadBalancerPool {
+            account_identifier: &self.account_id,
+            params: endpoint::CreateLoadBalancerPoolParams {
+                name,
+                origins: vec![endpoint::LoadBalancerOrigin {
+                    name,
+                    address: origin,
+                    enabled: true,
+                }],
             },
         };
 
-        self.client.request(&endpoint).await?;
+        let pool = self.request("create_load_balancer_pool", &endpoint).await?;
+
+        Ok(pool.result)
+    }
+
+    async fn set_load_balancer_pool_monitor(
+        &self,
+        pool_id: &str,
+        monitor_id: Option<&str>,
+    ) -> Result<(), Error> {
+        let endpoint = endpoint::UpdateLoadBalancerPool {
+            account_identifier: &self.account_id,
+            pool_id,
+            params: endpoint::UpdateLoadBalancerPoolParams {
+                monitor: monitor_id,
+            },
+        };
+
+        self.request("update_load_balancer_pool", &endpoint).await?;
 
         Ok(())
     }
 
-    pub async fn find_dns_record(
+    async fn delete_load_balancer_pool(&self, pool_id: &str) -> Result<(), Error> {
+        let endpoint = endpoint::DeleteLoadBalancerPool {
+            account_identifier: &self.account_id,
+            pool_id,
+        };
+
+        self.request("delete_load_balancer_pool", &endpoint).await?;
+
+        Ok(())
+    }
+
+    async fn list_load_balancers(&self, zone_id: &str) -> Result<Vec<LoadBalancer>, Error> {
+        let endpoint = endpoint::ListLoadBalancers {
+            zone_identifier: zone_id,
+        };
+
+        let load_balancers = self.request("list_load_balancers", &endpoint).await?;
+
+        Ok(load_balancers.result)
+    }
+
+    async fn create_load_balancer(
         &self,
         zone_id: &str,
         hostname: &str,
-    ) -> Result<Option<DnsRecord>, Error> {
-        let endpoint = cloudflare::endpoints::dns::ListDnsRecords {
+        pool_id: &str,
+    ) -> Result<LoadBalancer, Error> {
+        let endpoint = endpoint::CreateLoadBalancer {
             zone_identifier: zone_id,
-            params: cloudflare::endpoints::dns::ListDnsRecordsParams {
-                name: Some(hostname.to_string()),
-                ..cloudflare::endpoints::dns::ListDnsRecordsParams::default()
+            params: endpoint::CreateLoadBalancerParams {
+                name: hostname,
+                default_pools: vec![pool_id],
+                fallback_pool: pool_id,
+                proxied: true,
             },
         };
 
-        let response = self.client.request(&endpoint).await?;
+        let load_balancer = self.request("create_load_balancer", &endpoint).await?;
 
-        Ok(response.result.into_iter().find(|rec| rec.name == hostname))
+        Ok(load_balancer.result)
     }
 
-    pub async fn delete_dns_record(&self, zone_id: &str, domain_id: &str) -> Result<(), Error> {
-        let endpoint = cloudflare::endpoints::dns::DeleteDnsRecord {
+    async fn delete_load_balancer(
+        &self,
+        zone_id: &str,
+        load_balancer_id: &str,
+    ) -> Result<(), Error> {
+        let endpoint = endpoint::DeleteLoadBalancer {
             zone_identifier: zone_id,
-            identifier: domain_id,
+            load_balancer_id,
+        };
+
+        self.request("delete_load_balancer", &endpoint).await?;
+
+        Ok(())
+    }
+
+    async fn set_zone_setting(
+        &self,
+        zone_id: &str,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Result<(), Error> {
+        let endpoint = endpoint::UpdateZoneSetting {
+            zone_identifier: zone_id,
+            setting_id: name,
+            params: endpoint::UpdateZoneSettingParams { value },
         };
 
-        self.client.request(&endpoint).await?;
+        self.request("update_zone_setting", &endpoint).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{apex_domain, is_idempotent, remote_config, trace_headers, wildcard_record_name};
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use cloudflare::framework::endpoint::Method;
+
+    use crate::cloudflare::{
+        OriginRequest, TunnelConfig, TunnelCredentials, TunnelIngress, TunnelRoute,
+    };
+
+    #[test]
+    fn only_operator_routes_are_managed() {
+        let routes: Vec<TunnelRoute> = serde_json::from_str(
+            r#"[
+                {
+                    "id": "1",
+                    "network": "10.0.0.0/8",
+                    "tunnel_id": "t",
+                    "comment": "managed by cloudflare-tunnels-operator"
+                },
+                {"id": "2", "network": "10.1.0.0/16", "tunnel_id": "t", "comment": "office"},
+                {"id": "3", "network": "10.2.0.0/16", "tunnel_id": "t"}
+            ]"#,
+        )
+        .unwrap();
+
+        let managed: Vec<&str> = routes
+            .iter()
+            .filter(|route| route.is_managed())
+            .map(|route| route.id.as_str())
+            .collect();
+        assert_eq!(managed, ["1"]);
+    }
+
+    #[test]
+    fn retries_only_idempotent_requests() {
+        assert!(is_idempotent(&Method::GET));
+        assert!(is_idempotent(&Method::PUT));
+        assert!(is_idempotent(&Method::DELETE));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+
+    #[test]
+    fn wildcard_at_zone_apex() {
+        assert_eq!(
+            wildcard_record_name("*.example.com", "example.com").as_deref(),
+            Some("*")
+        );
+    }
+
+    #[test]
+    fn wildcard_in_subdomain() {
+        assert_eq!(
+            wildcard_record_name("*.dev.example.com", "example.com").as_deref(),
+            Some("*.dev")
+        );
+    }
+
+    #[test]
+    fn non_wildcard_or_foreign_zone() {
+        assert_eq!(wildcard_record_name("app.example.com", "example.com"), None);
+        assert_eq!(wildcard_record_name("*.example.org", "example.com"), None);
+    }
+
+    #[test]
+    fn remote_config_without_local_fields() {
+        let config = TunnelConfig {
+            tunnel: "tunnel-id".to_string(),
+            credentials_file: "/credentials/credentials.json".to_string(),
+            ingress: vec![
+                TunnelIngress {
+                    hostname: Some("app.example.com".to_string()),
+                    service: "http://app.default.svc:80".to_string(),
+                    origin_request: Some(OriginRequest {
+                        connect_timeout: Some(Duration::from_secs(30)),
+                        ..OriginRequest::default()
+                    }),
+                    ..TunnelIngress::default()
+                },
+                TunnelIngress {
+                    service: "http_status:404".to_string(),
+                    ..TunnelIngress::default()
+                },
+            ],
+            ..TunnelConfig::default()
+        };
+
+        let value = remote_config(&config).unwrap();
+        assert!(value.get("tunnel").is_none());
+        assert!(value.get("credentials-file").is_none());
+        assert_eq!(value["ingress"][0]["originRequest"]["connectTimeout"], 30);
+        assert_eq!(value["ingress"][1]["service"], "http_status:404");
+    }
+
+    #[test]
+    fn apex_of_hostname() {
+        assert_eq!(apex_domain("app.eu.example.com"), "example.com");
+        assert_eq!(apex_domain("example.com."), "example.com");
+        assert_eq!(apex_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn credentials_json_round_trip() {
+        let secret = BASE64_STANDARD.encode([7u8; 32]);
+        let json =
+            format!(r#"{{"AccountTag":"account","TunnelSecret":"{secret}","TunnelID":"tunnel"}}"#);
+
+        let creds = TunnelCredentials::from_json(json.as_bytes()).unwrap();
+        assert_eq!(creds.tunnel_secret, secret);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&serde_json::to_string(&creds).unwrap())
+                .unwrap(),
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        );
+    }
+
+    #[test]
+    fn credentials_json_double_encoded_secret() {
+        let secret = BASE64_STANDARD.encode([7u8; 32]);
+        let json = format!(
+            r#"{{"AccountTag":"account","TunnelSecret":"{}","TunnelID":"tunnel"}}"#,
+            BASE64_STANDARD.encode(&secret)
+        );
+
+        let creds = TunnelCredentials::from_json(json.as_bytes()).unwrap();
+        assert_eq!(creds.tunnel_secret, secret);
+    }
+
+    #[test]
+    fn unsampled_requests_carry_no_traceparent() {
+        assert!(trace_headers(&tracing::Span::none()).is_empty());
+    }
+}
@@ -0,0 +1,21 @@
+use std::future::Future;
+
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+// Tags the Cloudflare api requests of one reconcile with the same id, so the
+// operator logs can be matched with Cloudflare's audit logs.
+pub async fn with_correlation_id<F: Future>(fut: F) -> F::Output {
+    let id = Uuid::new_v4().to_string();
+    let span = info_span!("correlation", correlation_id = %id);
+
+    CORRELATION_ID.scope(id, fut.instrument(span)).await
+}
+
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cloudflare::endpoints::dns::{DnsContent, DnsRecord};
+use k8s_openapi::chrono::Utc;
+use tracing::info;
+
+use super::{
+    AccessApplication, CloudflareApi, LoadBalancer, LoadBalancerPool, TunnelConfig,
+    TunnelConnection, TunnelCredentials, TunnelRoute,
+};
+use crate::Error;
+
+const DRY_RUN_ID: &'static str = "dry-run";
+
+// Passes lookups through to the wrapped client and only logs writes, so the
+// operator can be exercised against a real account without changing it.
+pub struct DryRunClient {
+    account_id: String,
+    inner: Arc<dyn CloudflareApi>,
+}
+
+impl DryRunClient {
+    pub fn new(account_id: String, inner: Arc<dyn CloudflareApi>) -> Self {
+        Self { account_id, inner }
+    }
+}
+
+// ids handed out for writes that never happened, nothing may be stored in
+// the cluster that refers to them
+pub fn is_dry_run_id(id: &str) -> bool {
+    id.starts_with(DRY_RUN_ID)
+}
+
+fn dry_run_record(
+    zone_id: &str,
+    record_id: &str,
+    hostname: &str,
+    content: DnsContent,
+    ttl: Option<u32>,
+    proxied: Option<bool>,
+) -> Result<DnsRecord, Error> {
+    let mut record = serde_json::to_value(&content)
+        .map_err(|err| anyhow!("failed to serialize dns content: {err}"))?;
+    let now = Utc::now().to_rfc3339();
+    if let Some(fields) = record.as_object_mut() {
+        fields.extend([
+            ("id".to_string(), record_id.into()),
+            ("name".to_string(), hostname.into()),
+            ("zone_id".to_string(), zone_id.into()),
+            ("zone_name".to_string(), hostname.into()),
+            ("ttl".to_string(), ttl.unwrap_or(1).into()),
+            ("proxied".to_string(), proxied.unwrap_or(false).into()),
+            ("proxiable".to_string(), true.into()),
+            ("locked".to_string(), false.into()),
+            (
+                "meta".to_string(),
+                serde_json::json!({ "auto_added": false }),
+            ),
+            ("created_on".to_string(), now.clone().into()),
+            ("modified_on".to_string(), now.into()),
+        ]);
+    }
+
+    serde_json::from_value(record)
+        .map_err(|err| anyhow!("failed to build dry run dns record: {err}").into())
+}
+
+#[async_trait]
+impl CloudflareApi for DryRunClient {
+    async fn create_tunnel(
+        &self,
+        tunnel_name: &str,
+        remote_config: bool,
+    ) -> Result<TunnelCredentials, Error> {
+        info!("dry run: would create tunnel {tunnel_name} (remote config: {remote_config})");
+
+        Ok(TunnelCredentials {
+            account_tag: self.account_id.clone(),
+            tunnel_secret: String::new(),
+            tunnel_id: format!("{DRY_RUN_ID}-{tunnel_name}"),
+        })
+    }
+
+    async fn find_tunnel(&self, tunnel_name: &str) -> Result<Option<String>, Error> {
+        self.inner.find_tunnel(tunnel_name).await
+    }
+
+    async fn get_tunnel(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        self.inner.get_tunnel(tunnel_id).await
+    }
+
+    async fn get_tunnel_token(&self, tunnel_id: &str) -> Result<String, Error> {
+        self.inner.get_tunnel_token(tunnel_id).await
+    }
+
+    async fn delete_tunnel(&self, tunnel_id: &str) -> Result<(), Error> {
+        info!("dry run: would delete tunnel {tunnel_id}");
+
+        Ok(())
+    }
+
+    async fn rotate_tunnel_secret(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        info!("dry run: would rotate the secret of tunnel {tunnel_id}");
+
+        self.inner.get_tunnel(tunnel_id).await
+    }
+
+    async fn put_tunnel_configuration(
+        &self,
+        tunnel_id: &str,
+        config: &TunnelConfig,
+    ) -> Result<(), Error> {
+        info!(
+            "dry run: would configure tunnel {tunnel_id} with {} ingress rules",
+            config.ingress.len()
+        );
+
+        Ok(())
+    }
+
+    async fn get_tunnel_connections(
+        &self,
+        tunnel_id: &str,
+    ) -> Result<Vec<TunnelConnection>, Error> {
+        self.inner.get_tunnel_connections(tunnel_id).await
+    }
+
+    async fn list_tunnel_routes(&self, tunnel_id: &str) -> Result<Vec<TunnelRoute>, Error> {
+        self.inner.list_tunnel_routes(tunnel_id).await
+    }
+
+    async fn add_tunnel_route(&self, tunnel_id: &str, network: &str) -> Result<(), Error> {
+        info!("dry run: would route {network} through tunnel {tunnel_id}");
+
+        Ok(())
+    }
+
+    async fn delete_tunnel_route(&self, route_id: &str) -> Result<(), Error> {
+        info!("dry run: would delete tunnel route {route_id}");
+
+        Ok(())
+    }
+
+    async fn create_dns_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: DnsContent,
+    ) -> Result<(), Error> {
+        info!("dry run: would create dns record {hostname} in zone {zone_id}: {content:?}");
+
+        Ok(())
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        info!("dry run: would create dns record {hostname} in zone {zone_id}: {content:?}");
+
+        dry_run_record(zone_id, DRY_RUN_ID, hostname, content, ttl, proxied)
+    }
+
+    async fn update_dns_record(
+        &self,
+        zone_id: &str,
+        domain_id: &str,
+        hostname: &str,
+        tunnel_id: &str,
+        content: Option<DnsContent>,
+    ) -> Result<(), Error> {
+        info!(
+            "dry run: would update dns record {hostname} ({domain_id}) in zone {zone_id} for tunnel {tunnel_id}: {content:?}"
+        );
+
+        Ok(())
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        domain_id: &str,
+        hostname: &str,
+        content: DnsContent,
+        ttl: Option<u32>,
+        proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        info!(
+            "dry run: would update dns record {hostname} ({domain_id}) in zone {zone_id}: {content:?}"
+        );
+
+        dry_run_record(zone_id, domain_id, hostname, content, ttl, proxied)
+    }
+
+    async fn list_dns_records(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Vec<Arc<DnsRecord>>, Error> {
+        self.inner.list_dns_records(zone_id, hostname).await
+    }
+
+    async fn find_zone_id(&self, hostname: &str) -> Result<Option<String>, Error> {
+        self.inner.find_zone_id(hostname).await
+    }
+
+    async fn find_zone_id_for_hostname(&self, hostname: &str) -> Result<Option<String>, Error> {
+        self.inner.find_zone_id_for_hostname(hostname).await
+    }
+
+    async fn has_zone_access(&self, zone_id: &str) -> Result<bool, Error> {
+        self.inner.has_zone_access(zone_id).await
+    }
+
+    async fn delete_dns_record(&self, zone_id: &str, domain_id: &str) -> Result<(), Error> {
+        info!("dry run: would delete dns record {domain_id} in zone {zone_id}");
+
+        Ok(())
+    }
+
+    async fn find_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<Option<AccessApplication>, Error> {
+        self.inner.find_access_application(zone_id, hostname).await
+    }
+
+    async fn create_access_application(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+    ) -> Result<AccessApplication, Error> {
+        info!("dry run: would create access application {hostname} in zone {zone_id}");
+
+        Ok(AccessApplication {
+            id: DRY_RUN_ID.to_string(),
+            domain: hostname.to_string(),
+            aud: DRY_RUN_ID.to_string(),
+        })
+    }
+
+    async fn create_access_policy(
+        &self,
+        zone_id: &str,
+        app_id: &str,
+        policy_name: &str,
+        _include: &[serde_json::Value],
+    ) -> Result<(), Error> {
+        info!(
+            "dry run: would create access policy {policy_name} for application {app_id} in zone {zone_id}"
+        );
+
+        Ok(())
+    }
+
+    async fn delete_access_application(&self, zone_id: &str, app_id: &str) -> Result<(), Error> {
+        info!("dry run: would delete access application {app_id} in zone {zone_id}");
+
+        Ok(())
+    }
+
+    async fn get_load_balancer_pool(
+        &self,
+        pool_id: &str,
+    ) -> Result<Option<LoadBalancerPool>, Error> {
+        self.inner.get_load_balancer_pool(pool_id).await
+    }
+
+    async fn create_load_balancer_pool(
+        &self,
+        name: &str,
+        origin: &str,
+    ) -> Result<LoadBalancerPool, Error> {
+        info!("dry run: would create load balancer pool {name} with origin {origin}");
+
+        Ok(LoadBalancerPool {
+            id: DRY_RUN_ID.to_string(),
+            name: name.to_string(),
+            monitor: None,
+        })
+    }
+
+    async fn set_load_balancer_pool_monitor(
+        &self,
+        pool_id: &str,
+        monitor_id: Option<&str>,
+    ) -> Result<(), Error> {
+        match monitor_id {
+            Some(monitor_id) => {
+                info!("dry run: would attach monitor {monitor_id} to load balancer pool {pool_id}")
+            }
+            None => info!("dry run: would detach the monitor of load balancer pool {pool_id}"),
+        }
+
+        Ok(())
+    }
+
+    async fn delete_load_balancer_pool(&self, pool_id: &str) -> Result<(), Error> {
+        info!("dry run: would delete load balancer pool {pool_id}");
+
+        Ok(())
+    }
+
+    async fn list_load_balancers(&self, zone_id: &str) -> Result<Vec<LoadBalancer>, Error> {
+        self.inner.list_load_balancers(zone_id).await
+    }
+
+    async fn create_load_balancer(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        pool_id: &str,
+    ) -> Result<LoadBalancer, Error> {
+        info!(
+            "dry run: would create load balancer {hostname} for pool {pool_id} in zone {zone_id}"
+        );
+
+        Ok(LoadBalancer {
+            id: DRY_RUN_ID.to_string(),
+            name: hostname.to_string(),
+            default_pools: vec![pool_id.to_string()],
+        })
+    }
+
+    async fn delete_load_balancer(
+        &self,
+        zone_id: &str,
+        load_balancer_id: &str,
+    ) -> Result<(), Error> {
+        info!("dry run: would delete load balancer {load_balancer_id} in zone {zone_id}");
+
+        Ok(())
+    }
+
+    async fn set_zone_setting(
+        &self,
+        zone_id: &str,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Result<(), Error> {
+        info!("dry run: would set zone setting {name} to {value} in zone {zone_id}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,600 @@
+use cloudflare::framework::{
+    endpoint::{Endpoint, Method},
+    response::ApiResult,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct TunnelToken(pub String);
+
+impl ApiResult for TunnelToken {}
+
+pub struct GetTunnelToken<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: &'a str,
+}
+
+impl<'a> Endpoint<TunnelToken> for GetTunnelToken<'a> {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/token",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TunnelId {
+    pub id: String,
+}
+
+impl ApiResult for TunnelId {}
+
+#[derive(Serialize)]
+pub struct UpdateTunnelSecretParams<'a> {
+    pub tunnel_secret: &'a str,
+}
+
+pub struct UpdateTunnelSecret<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: &'a str,
+    pub params: UpdateTunnelSecretParams<'a>,
+}
+
+impl<'a> Endpoint<TunnelId, (), UpdateTunnelSecretParams<'a>> for UpdateTunnelSecret<'a> {
+    fn method(&self) -> Method {
+        Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+
+    fn body(&self) -> Option<UpdateTunnelSecretParams<'a>> {
+        Some(UpdateTunnelSecretParams {
+            tunnel_secret: self.params.tunnel_secret,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TunnelConfiguration {
+    pub tunnel_id: String,
+}
+
+impl ApiResult for TunnelConfiguration {}
+
+pub struct PutTunnelConfiguration<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: &'a str,
+    pub config: serde_json::Value,
+}
+
+impl<'a> Endpoint<TunnelConfiguration, (), serde_json::Value> for PutTunnelConfiguration<'a> {
+    fn method(&self) -> Method {
+        Method::PUT
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/configurations",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+
+    fn body(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "config": self.config }))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TunnelConnection {
+    pub id: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub conns: Vec<TunnelConnectionDetail>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TunnelConnectionDetail {
+    pub colo_name: String,
+    #[serde(default)]
+    pub is_pending_reconnect: bool,
+}
+
+impl ApiResult for Vec<TunnelConnection> {}
+
+pub struct GetTunnelConnections<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: &'a str,
+}
+
+impl<'a> Endpoint<Vec<TunnelConnection>> for GetTunnelConnections<'a> {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/connections",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+}
+
+// routes added through the dashboard or by hand carry other comments
+pub const MANAGED_ROUTE_COMMENT: &'static str = "managed by cloudflare-tunnels-operator";
+
+#[derive(Debug, Deserialize)]
+pub struct TunnelRoute {
+    pub id: String,
+    pub network: String,
+    pub tunnel_id: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl TunnelRoute {
+    pub fn is_managed(&self) -> bool {
+        self.comment.as_deref() == Some(MANAGED_ROUTE_COMMENT)
+    }
+}
+
+impl ApiResult for TunnelRoute {}
+impl ApiResult for Vec<TunnelRoute> {}
+
+#[derive(Serialize)]
+pub struct CreateTunnelRouteParams<'a> {
+    pub network: &'a str,
+    pub tunnel_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+}
+
+pub struct CreateTunnelRoute<'a> {
+    pub account_identifier: &'a str,
+    pub params: CreateTunnelRouteParams<'a>,
+}
+
+impl<'a> Endpoint<TunnelRoute, (), CreateTunnelRouteParams<'a>> for CreateTunnelRoute<'a> {
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("accounts/{}/teamnet/routes", self.account_identifier)
+    }
+
+    fn body(&self) -> Option<CreateTunnelRouteParams<'a>> {
+        Some(CreateTunnelRouteParams {
+            network: self.params.network,
+            tunnel_id: self.params.tunnel_id,
+            comment: self.params.comment,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ListTunnelRoutesParams {
+    pub tunnel_id: String,
+    pub is_deleted: bool,
+    pub page: u32,
+    pub per_page: u32,
+}
+
+pub struct ListTunnelRoutes<'a> {
+    pub account_identifier: &'a str,
+    pub params: ListTunnelRoutesParams,
+}
+
+impl<'a> Endpoint<Vec<TunnelRoute>, ListTunnelRoutesParams> for ListTunnelRoutes<'a> {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!("accounts/{}/teamnet/routes", self.account_identifier)
+    }
+
+    fn query(&self) -> Option<ListTunnelRoutesParams> {
+        Some(ListTunnelRoutesParams {
+            tunnel_id: self.params.tunnel_id.clone(),
+            is_deleted: self.params.is_deleted,
+            page: self.params.page,
+            per_page: self.params.per_page,
+        })
+    }
+}
+
+pub struct DeleteTunnelRoute<'a> {
+    pub account_identifier: &'a str,
+    pub route_id: &'a str,
+}
+
+impl<'a> Endpoint<TunnelRoute> for DeleteTunnelRoute<'a> {
+    fn method(&self) -> Method {
+        Method::DELETE
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/teamnet/routes/{}",
+            self.account_identifier, self.route_id
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessApplication {
+    pub id: String,
+    pub domain: String,
+    // the audience tag cloudflared checks the Access jwt against
+    #[serde(default)]
+    pub aud: String,
+}
+
+impl ApiResult for AccessApplication {}
+impl ApiResult for Vec<AccessApplication> {}
+
+#[derive(Serialize)]
+pub struct ListAccessApplicationsParams {
+    pub page: u32,
+    pub per_page: u32,
+}
+
+pub struct ListAccessApplications<'a> {
+    pub zone_identifier: &'a str,
+    pub params: ListAccessApplicationsParams,
+}
+
+impl<'a> Endpoint<Vec<AccessApplication>, ListAccessApplicationsParams>
+    for ListAccessApplications<'a>
+{
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/access/apps", self.zone_identifier)
+    }
+
+    fn query(&self) -> Option<ListAccessApplicationsParams> {
+        Some(ListAccessApplicationsParams {
+            page: self.params.page,
+            per_page: self.params.per_page,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CreateAccessApplicationParams<'a> {
+    pub name: &'a str,
+    pub domain: &'a str,
+    #[serde(rename = "type")]
+    pub app_type: &'a str,
+}
+
+pub struct CreateAccessApplication<'a> {
+    pub zone_identifier: &'a str,
+    pub params: CreateAccessApplicationParams<'a>,
+}
+
+impl<'a> Endpoint<AccessApplication, (), CreateAccessApplicationParams<'a>>
+    for CreateAccessApplication<'a>
+{
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/access/apps", self.zone_identifier)
+    }
+
+    fn body(&self) -> Option<CreateAccessApplicationParams<'a>> {
+        Some(CreateAccessApplicationParams {
+            name: self.params.name,
+            domain: self.params.domain,
+            app_type: self.params.app_type,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessApplicationId {
+    pub id: String,
+}
+
+impl ApiResult for AccessApplicationId {}
+
+pub struct DeleteAccessApplication<'a> {
+    pub zone_identifier: &'a str,
+    pub app_id: &'a str,
+}
+
+impl<'a> Endpoint<AccessApplicationId> for DeleteAccessApplication<'a> {
+    fn method(&self) -> Method {
+        Method::DELETE
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/access/apps/{}", self.zone_identifier, self.app_id)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessPolicy {
+    pub id: String,
+    pub name: String,
+}
+
+impl ApiResult for AccessPolicy {}
+
+#[derive(Serialize)]
+pub struct CreateAccessPolicyParams<'a> {
+    pub name: &'a str,
+    pub decision: &'a str,
+    pub include: serde_json::Value,
+}
+
+pub struct CreateAccessPolicy<'a> {
+    pub zone_identifier: &'a str,
+    pub app_id: &'a str,
+    pub params: CreateAccessPolicyParams<'a>,
+}
+
+impl<'a> Endpoint<AccessPolicy, (), CreateAccessPolicyParams<'a>> for CreateAccessPolicy<'a> {
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/access/apps/{}/policies",
+            self.zone_identifier, self.app_id
+        )
+    }
+
+    fn body(&self) -> Option<CreateAccessPolicyParams<'a>> {
+        Some(CreateAccessPolicyParams {
+            name: self.params.name,
+            decision: self.params.decision,
+            include: self.params.include.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoadBalancingId {
+    pub id: String,
+}
+
+impl ApiResult for LoadBalancingId {}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoadBalancerPool {
+    pub id: String,
+    pub name: String,
+    pub monitor: Option<String>,
+}
+
+impl ApiResult for LoadBalancerPool {}
+
+pub struct GetLoadBalancerPool<'a> {
+    pub account_identifier: &'a str,
+    pub pool_id: &'a str,
+}
+
+impl<'a> Endpoint<LoadBalancerPool> for GetLoadBalancerPool<'a> {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/load_balancers/pools/{}",
+            self.account_identifier, self.pool_id
+        )
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct LoadBalancerOrigin<'a> {
+    pub name: &'a str,
+    pub address: &'a str,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CreateLoadBalancerPoolParams<'a> {
+    pub name: &'a str,
+    pub origins: Vec<LoadBalancerOrigin<'a>>,
+}
+
+pub struct CreateLoadBalancerPool<'a> {
+    pub account_identifier: &'a str,
+    pub params: CreateLoadBalancerPoolParams<'a>,
+}
+
+impl<'a> Endpoint<LoadBalancerPool, (), CreateLoadBalancerPoolParams<'a>>
+    for CreateLoadBalancerPool<'a>
+{
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("accounts/{}/load_balancers/pools", self.account_identifier)
+    }
+
+    fn body(&self) -> Option<CreateLoadBalancerPoolParams<'a>> {
+        Some(self.params.clone())
+    }
+}
+
+// a null monitor detaches the current one
+#[derive(Serialize)]
+pub struct UpdateLoadBalancerPoolParams<'a> {
+    pub monitor: Option<&'a str>,
+}
+
+pub struct UpdateLoadBalancerPool<'a> {
+    pub account_identifier: &'a str,
+    pub pool_id: &'a str,
+    pub params: UpdateLoadBalancerPoolParams<'a>,
+}
+
+impl<'a> Endpoint<LoadBalancerPool, (), UpdateLoadBalancerPoolParams<'a>>
+    for UpdateLoadBalancerPool<'a>
+{
+    fn method(&self) -> Method {
+        Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/load_balancers/pools/{}",
+            self.account_identifier, self.pool_id
+        )
+    }
+
+    fn body(&self) -> Option<UpdateLoadBalancerPoolParams<'a>> {
+        Some(UpdateLoadBalancerPoolParams {
+            monitor: self.params.monitor,
+        })
+    }
+}
+
+pub struct DeleteLoadBalancerPool<'a> {
+    pub account_identifier: &'a str,
+    pub pool_id: &'a str,
+}
+
+impl<'a> Endpoint<LoadBalancingId> for DeleteLoadBalancerPool<'a> {
+    fn method(&self) -> Method {
+        Method::DELETE
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/load_balancers/pools/{}",
+            self.account_identifier, self.pool_id
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoadBalancer {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub default_pools: Vec<String>,
+}
+
+impl ApiResult for LoadBalancer {}
+impl ApiResult for Vec<LoadBalancer> {}
+
+pub struct ListLoadBalancers<'a> {
+    pub zone_identifier: &'a str,
+}
+
+impl<'a> Endpoint<Vec<LoadBalancer>> for ListLoadBalancers<'a> {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/load_balancers", self.zone_identifier)
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct CreateLoadBalancerParams<'a> {
+    pub name: &'a str,
+    pub default_pools: Vec<&'a str>,
+    pub fallback_pool: &'a str,
+    pub proxied: bool,
+}
+
+pub struct CreateLoadBalancer<'a> {
+    pub zone_identifier: &'a str,
+    pub params: CreateLoadBalancerParams<'a>,
+}
+
+impl<'a> Endpoint<LoadBalancer, (), CreateLoadBalancerParams<'a>> for CreateLoadBalancer<'a> {
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path(&self) -> String {
+        format!("zones/{}/load_balancers", self.zone_identifier)
+    }
+
+    fn body(&self) -> Option<CreateLoadBalancerParams<'a>> {
+        Some(self.params.clone())
+    }
+}
+
+pub struct DeleteLoadBalancer<'a> {
+    pub zone_identifier: &'a str,
+    pub load_balancer_id: &'a str,
+}
+
+impl<'a> Endpoint<LoadBalancingId> for DeleteLoadBalancer<'a> {
+    fn method(&self) -> Method {
+        Method::DELETE
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/load_balancers/{}",
+            self.zone_identifier, self.load_balancer_id
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ZoneSetting {
+    pub id: String,
+    pub value: serde_json::Value,
+}
+
+impl ApiResult for ZoneSetting {}
+
+#[derive(Serialize)]
+pub struct UpdateZoneSettingParams {
+    pub value: serde_json::Value,
+}
+
+pub struct UpdateZoneSetting<'a> {
+    pub zone_identifier: &'a str,
+    pub setting_id: &'a str,
+    pub params: UpdateZoneSettingParams,
+}
+
+impl<'a> Endpoint<ZoneSetting, (), UpdateZoneSettingParams> for UpdateZoneSetting<'a> {
+    fn method(&self) -> Method {
+        Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/settings/{}",
+            self.zone_identifier, self.setting_id
+        )
+    }
+
+    fn body(&self) -> Option<UpdateZoneSettingParams> {
+        Some(UpdateZoneSettingParams {
+            value: self.params.value.clone(),
+        })
+    }
+}
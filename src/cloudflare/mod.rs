@@ -1,21 +1,145 @@
 use std::time::Duration;
 
+pub use api::CloudflareApi;
+mod api;
+
 pub use client::*;
 mod client;
 
+pub use correlation::{correlation_id, with_correlation_id};
+mod correlation;
+
+pub use dryrun::{is_dry_run_id, DryRunClient};
+mod dryrun;
+
+mod endpoint;
+pub use endpoint::{
+    AccessApplication, LoadBalancer, LoadBalancerPool, TunnelConnection, TunnelConnectionDetail,
+    TunnelRoute,
+};
+
+pub use cache::ApiCache;
+mod cache;
+
 pub use cloudflare::endpoints::*;
 
+use anyhow::anyhow;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::Error;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct TunnelCredentials {
     pub account_tag: String,
     pub tunnel_secret: String,
+    // cloudflared writes TunnelID, older operator versions wrote TunnelId
+    #[serde(rename = "TunnelID", alias = "TunnelId")]
     pub tunnel_id: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Deserialize)]
+struct TunnelToken {
+    #[serde(rename = "a")]
+    account_tag: String,
+    #[serde(rename = "t")]
+    tunnel_id: String,
+    #[serde(rename = "s")]
+    tunnel_secret: String,
+}
+
+impl TunnelCredentials {
+    pub fn from_token(token: &str) -> Result<Self, Error> {
+        let decoded = BASE64_STANDARD
+            .decode(token.trim())
+            .map_err(|err| anyhow!("failed to decode tunnel token: {err}"))?;
+        let token: TunnelToken = serde_json::from_slice(&decoded)
+            .map_err(|err| anyhow!("failed to deserialize tunnel token: {err}"))?;
+
+        Ok(Self {
+            account_tag: token.account_tag,
+            tunnel_secret: token.tunnel_secret,
+            tunnel_id: token.tunnel_id,
+        })
+    }
+
+    // credentials.json as written by cloudflared or the operator, the secret
+    // is base64 of the raw 32 bytes, some tooling encodes it a second time
+    pub fn from_json(json: &[u8]) -> Result<Self, Error> {
+        let mut creds: Self = serde_json::from_slice(json)
+            .map_err(|err| anyhow!("failed to deserialize credentials: {err}"))?;
+
+        let decoded = BASE64_STANDARD
+            .decode(creds.tunnel_secret.trim())
+            .map_err(|err| anyhow!("tunnel secret is not base64: {err}"))?;
+        if decoded.len() != 32 {
+            if let Ok(inner) = std::str::from_utf8(&decoded) {
+                if BASE64_STANDARD
+                    .decode(inner.trim())
+                    .is_ok_and(|raw| raw.len() == 32)
+                {
+                    creds.tunnel_secret = inner.trim().to_string();
+                }
+            }
+        }
+
+        Ok(creds)
+    }
+}
+
+// cloudflared parses the timeouts with go's time.ParseDuration, which rejects
+// the spaces humantime writes between units, so 90s is written as 1m30s
+mod go_duration {
+    use std::time::Duration;
+
+    use serde::{Deserializer, Serializer};
+
+    const UNITS: [(&str, u128); 6] = [
+        ("h", 3_600_000_000_000),
+        ("m", 60_000_000_000),
+        ("s", 1_000_000_000),
+        ("ms", 1_000_000),
+        ("us", 1_000),
+        ("ns", 1),
+    ];
+
+    pub fn to_string(duration: Duration) -> String {
+        let mut nanos = duration.as_nanos();
+        if nanos == 0 {
+            return "0s".to_string();
+        }
+
+        let mut value = String::new();
+        for (unit, size) in UNITS {
+            if nanos >= size {
+                value.push_str(&format!("{}{unit}", nanos / size));
+                nanos %= size;
+            }
+        }
+
+        value
+    }
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_str(&to_string(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        humantime_serde::deserialize(deserializer)
+    }
+}
+
+#[derive(Default, Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OriginRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -24,15 +148,21 @@ pub struct OriginRequest {
     pub ca_pool: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub no_tls_verify: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "go_duration", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub tls_timeout: Option<Duration>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub http_2_origin: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub http_host_header: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub disable_chunjed_encoding: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "disableChunkedEncoding",
+        alias = "disableChunjedEncoding",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub disable_chunked_encoding: Option<bool>,
+    #[serde(default, with = "go_duration", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub connect_timeout: Option<Duration>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub no_happy_eyeball: Option<bool>,
@@ -42,12 +172,113 @@ pub struct OriginRequest {
     pub proxy_address: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy_port: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "go_duration", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub keep_alive_timeout: Option<Duration>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_alive_connection: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "go_duration", skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
     pub tcp_keep_alive: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessConfig>,
+}
+
+// cloudflared rejects requests without a valid Access jwt for the team
+#[derive(Default, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessConfig {
+    pub required: bool,
+    pub team_name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aud_tag: Vec<String>,
+}
+
+impl OriginRequest {
+    pub fn merge(&self, other: &OriginRequest) -> OriginRequest {
+        OriginRequest {
+            origin_server_name: other
+                .origin_server_name
+                .clone()
+                .or_else(|| self.origin_server_name.clone()),
+            ca_pool: other.ca_pool.clone().or_else(|| self.ca_pool.clone()),
+            no_tls_verify: other.no_tls_verify.or(self.no_tls_verify),
+            tls_timeout: other.tls_timeout.or(self.tls_timeout),
+            http_2_origin: other.http_2_origin.or(self.http_2_origin),
+            http_host_header: other
+                .http_host_header
+                .clone()
+                .or_else(|| self.http_host_header.clone()),
+            disable_chunked_encoding: other
+                .disable_chunked_encoding
+                .or(self.disable_chunked_encoding),
+            connect_timeout: other.connect_timeout.or(self.connect_timeout),
+            no_happy_eyeball: other.no_happy_eyeball.or(self.no_happy_eyeball),
+            proxy_type: other.proxy_type.clone().or_else(|| self.proxy_type.clone()),
+            proxy_address: other
+                .proxy_address
+                .clone()
+                .or_else(|| self.proxy_address.clone()),
+            proxy_port: other.proxy_port.or(self.proxy_port),
+            keep_alive_timeout: other.keep_alive_timeout.or(self.keep_alive_timeout),
+            keep_alive_connection: other.keep_alive_connection.or(self.keep_alive_connection),
+            tcp_keep_alive: other.tcp_keep_alive.or(self.tcp_keep_alive),
+            access: other.access.clone().or_else(|| self.access.clone()),
+        }
+    }
+}
+
+const SERVICE_SCHEMES: [&'static str; 6] = ["http", "https", "tcp", "udp", "ssh", "rdp"];
+
+// cloudflared refuses to start on a service it can't parse, which would take
+// down every route of the tunnel
+pub fn validate_service_url(service: &str) -> Result<(), Error> {
+    if let Some(status) = service.strip_prefix("http_status:") {
+        return match status.parse::<u16>() {
+            Ok(100..=599) => Ok(()),
+            _ => Err(anyhow!("invalid status code in service {service}").into()),
+        };
+    }
+
+    if service == "hello_world" || service == "bastion" {
+        return Ok(());
+    }
+
+    if let Some(socket) = service
+        .strip_prefix("unix+tls:")
+        .or_else(|| service.strip_prefix("unix:"))
+    {
+        if socket.trim_start_matches('/').is_empty() {
+            return Err(anyhow!("missing socket path in service {service}").into());
+        }
+
+        return Ok(());
+    }
+
+    let Some((scheme, rest)) = service.split_once("://") else {
+        return Err(anyhow!("invalid service {service}, expected <scheme>://<host>[:port]").into());
+    };
+
+    if !SERVICE_SCHEMES.contains(&scheme) {
+        return Err(anyhow!("unsupported scheme {scheme} in service {service}").into());
+    }
+
+    let authority = rest.split('/').next().unwrap_or_default();
+    let (host, port) = match authority.rsplit_once(':') {
+        // the colons of a bracketed ipv6 address don't start a port
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (authority, None),
+    };
+
+    if host.is_empty() || service.chars().any(char::is_whitespace) {
+        return Err(anyhow!("invalid host in service {service}").into());
+    }
+
+    if port.is_some_and(|port| port.parse::<u16>().is_err()) {
+        return Err(anyhow!("invalid port in service {service}").into());
+    }
+
+    Ok(())
 }
 
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
@@ -62,13 +293,157 @@ pub struct TunnelIngress {
     pub origin_request: Option<OriginRequest>,
 }
 
+#[derive(Default, Clone, Debug, Deserialize, Serialize)]
+pub struct WarpRouting {
+    pub enabled: bool,
+}
+
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TunnelConfig {
     pub tunnel: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub origin_request: Option<OriginRequest>,
+    #[serde(rename = "warp-routing", skip_serializing_if = "Option::is_none")]
+    pub warp_routing: Option<WarpRouting>,
     #[serde(rename = "credentials-file")]
     pub credentials_file: String,
     pub ingress: Vec<TunnelIngress>,
 }
+
+impl TunnelConfig {
+    // config maps can be edited by hand, so malformed yaml must not panic
+    pub fn from_yaml(yaml: &str) -> Result<Self, Error> {
+        serde_yaml::from_str(yaml)
+            .map_err(|err| anyhow!("failed to deserialize tunnel config: {err}").into())
+    }
+
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        serde_yaml::to_string(self)
+            .map_err(|err| anyhow!("failed to serialize tunnel config: {err}").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{go_duration, validate_service_url, OriginRequest, TunnelConfig};
+
+    #[test]
+    fn accepts_supported_services() {
+        for service in [
+            "http://web.default.svc:80",
+            "https://web.default.svc",
+            "tcp://db.default.svc:5432/",
+            "udp://dns.default.svc:53",
+            "ssh://bastion.default.svc:22",
+            "rdp://desktop.default.svc:3389",
+            "http://[::1]:8080",
+            "http_status:404",
+            "hello_world",
+            "bastion",
+            "unix:/run/app.sock",
+            "unix+tls:/run/app.sock",
+        ] {
+            assert!(validate_service_url(service).is_ok(), "{service}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_services() {
+        for service in [
+            "web.default.svc:80",
+            "ftp://files.default.svc:21",
+            "http://:80",
+            "http://web .default.svc:80",
+            "http://web.default.svc:http",
+            "http://web.default.svc:70000",
+            "http_status:99",
+            "http_status:abc",
+            "unix:",
+            "unix+tls:/",
+        ] {
+            assert!(validate_service_url(service).is_err(), "{service}");
+        }
+    }
+
+    #[test]
+    fn durations_round_trip() {
+        let config = TunnelConfig {
+            tunnel: "tunnel".to_string(),
+            origin_request: Some(OriginRequest {
+                tls_timeout: Some(Duration::from_secs(10)),
+                connect_timeout: Some(Duration::from_secs(30)),
+                keep_alive_timeout: Some(Duration::from_secs(90)),
+                tcp_keep_alive: Some(Duration::from_millis(1500)),
+                ..OriginRequest::default()
+            }),
+            ..TunnelConfig::default()
+        };
+
+        let yaml = config.to_yaml().unwrap();
+        assert!(yaml.contains("connectTimeout: 30s"), "{yaml}");
+        assert!(yaml.contains("keepAliveTimeout: 1m30s"), "{yaml}");
+        assert!(yaml.contains("tcpKeepAlive: 1s500ms"), "{yaml}");
+
+        let origin_request = TunnelConfig::from_yaml(&yaml)
+            .unwrap()
+            .origin_request
+            .unwrap();
+        assert_eq!(origin_request.tls_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(
+            origin_request.connect_timeout,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            origin_request.keep_alive_timeout,
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            origin_request.tcp_keep_alive,
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn durations_are_go_compatible() {
+        for (duration, expected) in [
+            (Duration::ZERO, "0s"),
+            (Duration::from_secs(30), "30s"),
+            (Duration::from_secs(3600), "1h"),
+            (Duration::from_secs(3661), "1h1m1s"),
+            (Duration::from_micros(1_000_250), "1s250us"),
+        ] {
+            assert_eq!(go_duration::to_string(duration), expected);
+        }
+    }
+
+    #[test]
+    fn humantime_durations_are_accepted() {
+        let origin_request: OriginRequest =
+            serde_yaml::from_str("connectTimeout: 1m 30s\ntlsTimeout: 2min").unwrap();
+
+        assert_eq!(
+            origin_request.connect_timeout,
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(origin_request.tls_timeout, Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn unset_durations_are_omitted() {
+        let yaml = TunnelConfig {
+            origin_request: Some(OriginRequest::default()),
+            ..TunnelConfig::default()
+        }
+        .to_yaml()
+        .unwrap();
+
+        assert!(!yaml.contains("Timeout"), "{yaml}");
+        assert!(TunnelConfig::from_yaml(&yaml)
+            .unwrap()
+            .origin_request
+            .is_some_and(|origin_request| origin_request.tls_timeout.is_none()));
+    }
+}
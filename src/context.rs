@@ -1,4 +1,178 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use cloudflare::framework::Environment;
+use dashmap::{DashMap, DashSet};
+use k8s_openapi::NamespaceResourceScope;
+use kube::{
+    runtime::{events::Recorder, watcher},
+    Api, Resource,
+};
+use opentelemetry::global::BoxedTracer;
+use serde::de::DeserializeOwned;
+use tokio::sync::{watch, Mutex};
+
+use crate::{
+    cloudflare::{
+        ApiCache, Client, ClientConfig, CloudflareApi, CloudflareEnvironment, Credentials,
+        DryRunClient,
+    },
+    controller::FailureTracker,
+    metrics::Metrics,
+    Error,
+};
+
+pub type CloudflareClientFactory = Arc<
+    dyn Fn(&Context, String, Credentials) -> Result<Arc<dyn CloudflareApi>, Error> + Send + Sync,
+>;
+
+// Builds the HTTP client sharing the context's metrics, timeouts and cache.
+pub fn cloudflare_client_factory() -> CloudflareClientFactory {
+    Arc::new(|ctx, account_id, credentials| {
+        let client = Arc::new(Client::with_cache(
+            account_id.clone(),
+            credentials,
+            ctx.metrics.clone(),
+            ctx.cloudflare_config(),
+            ctx.cloudflare_api_environment()?,
+            ctx.cloudflare_cache.clone(),
+        )?);
+
+        if ctx.cloudflare_dry_run {
+            return Ok(Arc::new(DryRunClient::new(account_id, client)));
+        }
+
+        Ok(client)
+    })
+}
+
 pub struct Context {
     pub kube_cli: kube::Client,
-    pub ingress_class: Option<String>,
+    pub ingress_class: Option<Vec<String>>,
+    pub watch_namespace: Option<String>,
+    pub watch_label_selector: Option<String>,
+    pub operator_namespace: String,
+    pub ingress_classes: DashSet<String>,
+    // bumped whenever ingress_classes changes, so the ingresses get another look
+    pub ingress_classes_changed: watch::Sender<()>,
+    pub metrics: Metrics,
+    pub recorder: Recorder,
+    pub requeue_interval: Duration,
+    pub error_requeue_interval: Duration,
+    pub max_error_requeue_interval: Duration,
+    pub failures: FailureTracker,
+    pub config_locks: DashMap<String, Arc<Mutex<()>>>,
+    pub cloudflare_cache: ApiCache,
+    pub cloudflare_timeout: Duration,
+    pub cloudflare_retries: u32,
+    pub cloudflare_environment: CloudflareEnvironment,
+    pub cloudflare_api_url: String,
+    pub cloudflare_dry_run: bool,
+    pub cloudflare_client_factory: CloudflareClientFactory,
+    pub active_reconciles: Arc<AtomicU32>,
+    // for spans started through the opentelemetry api instead of tracing
+    pub tracer: BoxedTracer,
+}
+
+// Counts a reconcile as in flight until dropped, so shutdown can wait for it.
+pub struct ReconcileGuard(Arc<AtomicU32>);
+
+impl Drop for ReconcileGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Context {
+    pub fn cloudflare_config(&self) -> ClientConfig {
+        ClientConfig {
+            timeout: self.cloudflare_timeout,
+            max_retries: self.cloudflare_retries,
+        }
+    }
+
+    pub fn cloudflare_api_environment(&self) -> Result<Environment, Error> {
+        match self.cloudflare_environment {
+            CloudflareEnvironment::Production => Ok(Environment::Production),
+            CloudflareEnvironment::Staging => {
+                let url = url::Url::parse(&self.cloudflare_api_url).map_err(|err| {
+                    anyhow!(
+                        "invalid cloudflare api url {}: {err}",
+                        self.cloudflare_api_url
+                    )
+                })?;
+
+                Ok(Environment::Custom(url))
+            }
+        }
+    }
+
+    pub fn cloudflare_client(
+        &self,
+        account_id: String,
+        credentials: Credentials,
+    ) -> Result<Arc<dyn CloudflareApi>, Error> {
+        (self.cloudflare_client_factory)(self, account_id, credentials)
+    }
+
+    // Api for namespaced resources, restricted to --namespace when set.
+    pub fn watched_api<K>(&self) -> Api<K>
+    where
+        K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + DeserializeOwned,
+    {
+        match &self.watch_namespace {
+            Some(ns) => Api::namespaced(self.kube_cli.clone(), ns),
+            None => Api::all(self.kube_cli.clone()),
+        }
+    }
+
+    pub fn watcher_config(&self) -> watcher::Config {
+        match &self.watch_namespace {
+            Some(ns) => self
+                .cluster_watcher_config()
+                .fields(&format!("metadata.namespace={ns}")),
+            None => self.cluster_watcher_config(),
+        }
+    }
+
+    // for cluster scoped resources, only restricted to --watch-label-selector
+    pub fn cluster_watcher_config(&self) -> watcher::Config {
+        match &self.watch_label_selector {
+            Some(selector) => watcher::Config::default().labels(selector),
+            None => watcher::Config::default(),
+        }
+    }
+
+    pub fn manages_ingress_class(&self, class: Option<&String>) -> bool {
+        match class {
+            Some(class) => {
+                self.ingress_class
+                    .iter()
+                    .flatten()
+                    .any(|name| name == class)
+                    || self.ingress_classes.contains(class)
+            }
+            None => self.ingress_class.is_none(),
+        }
+    }
+
+    pub fn track_reconcile(&self) -> ReconcileGuard {
+        self.active_reconciles.fetch_add(1, Ordering::SeqCst);
+        ReconcileGuard(self.active_reconciles.clone())
+    }
+
+    // Ingresses sharing a tunnel read-modify-write the same config map, so
+    // reconcilers must hold the tunnel's lock for the whole update.
+    pub fn config_lock(&self, tunnel_name: &str) -> Arc<Mutex<()>> {
+        self.config_locks
+            .entry(tunnel_name.to_string())
+            .or_default()
+            .clone()
+    }
 }
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, CustomResource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{context::Context, error::Error};
+
+pub const ANNOTATION_CERTIFICATE_NAME: &'static str = "cert-manager.io/certificate-name";
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "Certificate",
+    group = "cert-manager.io",
+    version = "v1",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateSpec {
+    pub secret_name: String,
+}
+
+// Self-signed origins have no ca.crt, so the certificate itself is trusted instead.
+pub async fn origin_ca(ctx: Arc<Context>, ns: &str, certificate: &str) -> Result<String, Error> {
+    let cert_api: Api<Certificate> = Api::namespaced(ctx.kube_cli.clone(), ns);
+    let secret_api: Api<Secret> = Api::namespaced(ctx.kube_cli.clone(), ns);
+
+    let certificate = cert_api.get(certificate).await?;
+    let secret_name = &certificate.spec.secret_name;
+    let secret = secret_api.get(secret_name).await?;
+    let data = secret.data.ok_or_else(|| anyhow!("no data"))?;
+
+    let ca = data
+        .get("ca.crt")
+        .filter(|ca| !ca.0.is_empty())
+        .or_else(|| data.get("tls.crt"))
+        .ok_or_else(|| anyhow!("no ca.crt or tls.crt in {secret_name}"))?;
+
+    let ca = String::from_utf8(ca.clone().0).map_err(|err| anyhow!("ca not a string: {err:?}"))?;
+
+    Ok(ca)
+}
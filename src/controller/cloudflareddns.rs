@@ -0,0 +1,346 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use cloudflare::{endpoints::dns::DnsRecord, framework::response::ApiFailure};
+use futures_util::StreamExt;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, finalizer, Controller},
+    Api, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    cloudflare::{dns::DnsContent, is_dry_run_id, with_correlation_id, CloudflareApi, Credentials},
+    context::Context,
+    error::Error,
+};
+
+use super::{clustertunnel::SecretRef, error_policy, reset_failures, utils::*, OPERATOR_MANAGER};
+
+const CLOUDFLARE_DNS_FINALIZER: &'static str =
+    "cloudflaredns.cloudflare-tunnels-operator.io/finalizer";
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum DnsRecordType {
+    A,
+    AAAA,
+    CNAME,
+    TXT,
+    MX,
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "CloudflareDNS",
+    group = "cloudflare-tunnels-operator.io",
+    version = "v1alpha1",
+    namespaced,
+    status = "CloudflareDNSStatus"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudflareDNSSpec {
+    pub zone_id: String,
+    pub name: String,
+    pub record_type: DnsRecordType,
+    pub content: String,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+    pub priority: Option<u16>,
+    pub cloudflare_ref: SecretRef,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudflareDNSStatus {
+    pub record_id: Option<String>,
+}
+
+impl CloudflareDNS {
+    fn dns_content(&self) -> Result<DnsContent, Error> {
+        let content = self.spec.content.clone();
+
+        Ok(match self.spec.record_type {
+            DnsRecordType::A => DnsContent::A {
+                content: content
+                    .parse::<Ipv4Addr>()
+                    .map_err(|err| anyhow!("invalid A record content {content}: {err}"))?,
+            },
+            DnsRecordType::AAAA => DnsContent::AAAA {
+                content: content
+                    .parse::<Ipv6Addr>()
+                    .map_err(|err| anyhow!("invalid AAAA record content {content}: {err}"))?,
+            },
+            DnsRecordType::CNAME => DnsContent::CNAME { content },
+            DnsRecordType::TXT => DnsContent::TXT { content },
+            DnsRecordType::MX => DnsContent::MX {
+                content,
+                priority: self.spec.priority.unwrap_or(10),
+            },
+        })
+    }
+
+    fn record_type_matches(&self, content: &DnsContent) -> bool {
+        matches!(
+            (self.spec.record_type, content),
+            (DnsRecordType::A, DnsContent::A { .. })
+                | (DnsRecordType::AAAA, DnsContent::AAAA { .. })
+                | (DnsRecordType::CNAME, DnsContent::CNAME { .. })
+                | (DnsRecordType::TXT, DnsContent::TXT { .. })
+                | (DnsRecordType::MX, DnsContent::MX { .. })
+        )
+    }
+
+    // a name can hold records of several types, e.g. an A and a TXT record
+    fn matching_record_id(&self, records: &[Arc<DnsRecord>]) -> Option<String> {
+        records
+            .iter()
+            .find(|record| self.record_type_matches(&record.content))
+            .map(|record| record.id.clone())
+    }
+
+    async fn cloudflare_client(&self, ctx: Arc<Context>) -> Result<Arc<dyn CloudflareApi>, Error> {
+        let ns = self.namespace().unwrap_or_else(|| "default".to_string());
+        // namespaced records must not read secrets of other tenants
+        if self.spec.cloudflare_ref.namespace_or(&ns) != ns {
+            return Err(anyhow!("cloudflareRef must be in namespace {ns}").into());
+        }
+        let token = get_secret_value(ctx.clone(), &ns, &self.spec.cloudflare_ref).await?;
+
+        // dns endpoints are zone scoped, so no account is needed
+        ctx.cloudflare_client(String::new(), Credentials::UserAuthToken { token })
+    }
+
+    async fn set_record_id(
+        &self,
+        ctx: Arc<Context>,
+        record_id: Option<String>,
+    ) -> Result<(), Error> {
+        let api: Api<CloudflareDNS> = Api::namespaced(
+            ctx.kube_cli.clone(),
+            &self.namespace().unwrap_or_else(|| "default".to_string()),
+        );
+        let status = serde_json::json!({
+            "status": CloudflareDNSStatus { record_id }
+        });
+
+        api.patch_status(
+            &self.name_any(),
+            &PatchParams::apply(OPERATOR_MANAGER),
+            &Patch::Merge(&status),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let cf_cli = self.cloudflare_client(ctx.clone()).await?;
+        let content = self.dns_content()?;
+
+        // the record is looked up by name instead of trusting the stored id,
+        // so one deleted out of band is recreated and one whose id never made
+        // it into the status isn't created twice
+        let records = cf_cli
+            .list_dns_records(&self.spec.zone_id, &self.spec.name)
+            .await?;
+        let record_id = self.matching_record_id(&records);
+
+        let updated = match record_id {
+            Some(record_id) => {
+                let updated = cf_cli
+                    .update_record(
+                        &self.spec.zone_id,
+                        &record_id,
+                        &self.spec.name,
+                        self.dns_content()?,
+                        self.spec.ttl,
+                        self.spec.proxied,
+                    )
+                    .await;
+                match updated {
+                    // the lookup may have been answered from the cache
+                    Err(Error::CloudflareApiErr(ApiFailure::Error(status, _), _))
+                        if status.as_u16() == 404 =>
+                    {
+                        None
+                    }
+                    updated => Some(updated?),
+                }
+            }
+            None => None,
+        };
+
+        let record = match updated {
+            Some(record) => record,
+            None => {
+                info!("creating dns record {}", self.spec.name);
+
+                cf_cli
+                    .create_record(
+                        &self.spec.zone_id,
+                        &self.spec.name,
+                        content,
+                        self.spec.ttl,
+                        self.spec.proxied,
+                    )
+                    .await?
+            }
+        };
+
+        if !is_dry_run_id(&record.id) {
+            self.set_record_id(ctx.clone(), Some(record.id)).await?;
+        }
+
+        Ok(Action::requeue(ctx.requeue_interval))
+    }
+
+    pub async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let Some(record_id) = self
+            .status
+            .as_ref()
+            .and_then(|status| status.record_id.as_ref())
+        else {
+            return Ok(Action::await_change());
+        };
+
+        let cf_cli = self.cloudflare_client(ctx.clone()).await?;
+        cf_cli
+            .delete_dns_record(&self.spec.zone_id, record_id)
+            .await?;
+
+        Ok(Action::await_change())
+    }
+}
+
+#[instrument(skip(obj, ctx), fields(
+    name = %obj.name_any(),
+    namespace = ?obj.namespace(),
+    resource_version = ?obj.resource_version(),
+    k8s.resource.kind = "CloudflareDNS",
+    k8s.resource.name = %obj.name_any(),
+    k8s.resource.namespace = ?obj.namespace(),
+))]
+pub async fn reconcile(obj: Arc<CloudflareDNS>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let _reconcile = ctx.track_reconcile();
+
+    let api: Api<CloudflareDNS> = Api::namespaced(
+        ctx.kube_cli.clone(),
+        &obj.namespace().unwrap_or_else(|| "default".to_string()),
+    );
+
+    finalizer(&api, CLOUDFLARE_DNS_FINALIZER, obj, |event| async {
+        match event {
+            finalizer::Event::Apply(obj) => obj.reconcile(ctx.clone()).await,
+            finalizer::Event::Cleanup(obj) => obj.cleanup(ctx.clone()).await,
+        }
+    })
+    .await
+    .map_err(|e| Error::FinalizerError(Box::new(e)))
+}
+
+pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
+    let cfg = ctx.watcher_config();
+    let api: Api<CloudflareDNS> = ctx.watched_api();
+
+    Controller::new(api, cfg)
+        .shutdown_on_signal()
+        .run(
+            |obj, ctx| with_correlation_id(reconcile(obj, ctx)),
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(o) => {
+                        ctx.metrics.reconcile_success("CloudflareDNS");
+                        reset_failures(&ctx, &o.0);
+                        info!("reconciled cloudflare dns {o:?}")
+                    }
+                    Err(e) => warn!("reconcile cloudflare dns failed: {e:?}"),
+                }
+            }
+        })
+        .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, content: serde_json::Value) -> Arc<DnsRecord> {
+        let mut record = content;
+        record.as_object_mut().unwrap().extend([
+            ("id".to_string(), id.into()),
+            ("name".to_string(), "app.example.com".into()),
+            ("zone_id".to_string(), "zone".into()),
+            ("zone_name".to_string(), "example.com".into()),
+            ("ttl".to_string(), 1.into()),
+            ("proxied".to_string(), false.into()),
+            ("proxiable".to_string(), true.into()),
+            ("locked".to_string(), false.into()),
+            (
+                "meta".to_string(),
+                serde_json::json!({ "auto_added": false }),
+            ),
+            ("created_on".to_string(), "2024-01-01T00:00:00Z".into()),
+            ("modified_on".to_string(), "2024-01-01T00:00:00Z".into()),
+        ]);
+
+        Arc::new(serde_json::from_value(record).unwrap())
+    }
+
+    fn dns(record_type: DnsRecordType) -> CloudflareDNS {
+        CloudflareDNS::new(
+            "app",
+            CloudflareDNSSpec {
+                zone_id: "zone".to_string(),
+                name: "app.example.com".to_string(),
+                record_type,
+                content: "192.0.2.1".to_string(),
+                ttl: None,
+                proxied: None,
+                priority: None,
+                cloudflare_ref: SecretRef {
+                    name: "cloudflare".to_string(),
+                    key: "token".to_string(),
+                    namespace: None,
+                },
+            },
+        )
+    }
+
+    #[test]
+    fn record_is_matched_by_type_among_mixed_types() {
+        let records = [
+            record(
+                "txt",
+                serde_json::json!({ "type": "TXT", "content": "v=spf1 -all" }),
+            ),
+            record(
+                "a",
+                serde_json::json!({ "type": "A", "content": "192.0.2.1" }),
+            ),
+        ];
+
+        assert_eq!(
+            dns(DnsRecordType::A).matching_record_id(&records),
+            Some("a".to_string())
+        );
+        assert_eq!(
+            dns(DnsRecordType::TXT).matching_record_id(&records),
+            Some("txt".to_string())
+        );
+        assert_eq!(dns(DnsRecordType::AAAA).matching_record_id(&records), None);
+    }
+}
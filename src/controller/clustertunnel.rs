@@ -1,41 +1,99 @@
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 
-use anyhow::anyhow;
 use futures_util::StreamExt;
+use ipnet::IpNet;
 use k8s_openapi::{
     api::{
-        apps::v1::{Deployment, DeploymentSpec},
+        apps::v1::{
+            DaemonSet, DaemonSetSpec, DaemonSetUpdateStrategy, Deployment, DeploymentSpec,
+            RollingUpdateDaemonSet,
+        },
+        autoscaling::v2::{
+            CrossVersionObjectReference, HorizontalPodAutoscaler, HorizontalPodAutoscalerSpec,
+            MetricSpec, MetricTarget, ResourceMetricSource,
+        },
         core::v1::{
-            ConfigMap, ConfigMapVolumeSource, Container, HTTPGetAction, PodSpec, PodTemplateSpec,
-            Probe, Secret, SecretVolumeSource, Volume, VolumeMount,
+            ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, EnvFromSource, EnvVar,
+            EnvVarSource, HTTPGetAction, HostPathVolumeSource, LocalObjectReference, PodSpec,
+            PodTemplateSpec, Probe, ProjectedVolumeSource, ResourceRequirements, Secret,
+            SecretKeySelector, SecretVolumeSource, Service, ServiceAccount, ServicePort,
+            ServiceSpec, Toleration, Volume, VolumeMount, VolumeProjection,
         },
+        networking::v1::{
+            NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule, NetworkPolicyPeer,
+            NetworkPolicyPort, NetworkPolicySpec,
+        },
+        policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{Condition, LabelSelector, Time},
+        util::intstr::IntOrString,
     },
-    apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
+    chrono::{Duration, Utc},
 };
 use kube::{
     api::{ObjectMeta, Patch, PatchParams},
-    runtime::{controller::Action, finalizer, watcher, Controller},
-    Api, CustomResource, ResourceExt,
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+        finalizer,
+        reflector::ObjectRef,
+        watcher, Controller,
+    },
+    Api, CustomResource, Resource, ResourceExt,
 };
-use log::{info, warn};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tracing::{field, info, instrument, warn, Span};
 
 use crate::{
-    cloudflare::{self, TunnelConfig, TunnelCredentials, TunnelIngress},
+    cloudflare::{
+        self, is_dry_run_id, with_correlation_id, CloudflareApi, OriginRequest, TunnelConfig,
+        TunnelCredentials, TunnelIngress, TunnelRoute, WarpRouting,
+    },
     context::Context,
     error::Error,
 };
 
-use super::{error_policy, utils::*, OPERATOR_MANAGER};
+use super::{
+    error_policy,
+    externalsecret::apply_external_secret,
+    reset_failures,
+    servicemonitor::{apply_service_monitor, service_monitor_available, ServiceMonitor},
+    tunnelpolicy::{get_policy, TunnelPolicy},
+    utils::*,
+    OPERATOR_MANAGER,
+};
 
 const CLUSTER_TUNNEL_FINALIZER: &'static str = "cluster-tunnel.cloudflare-tunnels.io/finalizer";
+const TUNNEL_ADOPT_ATTEMPTS: u32 = 3;
+const ZONE_SETTINGS: [&'static str; 4] = ["ssl", "always_use_https", "brotli", "http3"];
+// releases before the workload names included the tunnel ran a single "cloudflared"
+const LEGACY_DEPLOYMENT_NAME: &'static str = "cloudflared";
+const CLOUDFLARED_IMAGE: &'static str = "cloudflare/cloudflared:2024.8.2";
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SecretRef {
     pub name: String,
     pub key: String,
+    // defaults to the operator namespace, other namespaces need extra rbac
+    pub namespace: Option<String>,
+}
+
+impl SecretRef {
+    pub fn namespace_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.namespace.as_deref().unwrap_or(default)
+    }
+}
+
+// provider names the ClusterSecretStore the credentials are synced from
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalSecretRef {
+    pub provider: String,
+    pub key: String,
+    pub version: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
@@ -45,239 +103,1897 @@ pub enum CloudflareSecretRef {
     ApiKey(SecretRef),
     #[serde(rename = "apiTokenSecretRef")]
     ApiToken(SecretRef),
+    // api token limited to the zones it may edit, checked against zoneId
+    #[serde(rename = "zoneScopedTokenSecretRef")]
+    ZoneScopedToken(SecretRef),
+    #[serde(rename = "tunnelTokenSecretRef")]
+    TunnelToken(SecretRef),
+    // escape hatch for clusters without secrets, not meant for production:
+    // the api token is read from a file mounted into the operator and
+    // cloudflared gets its credentials through spec.credentialsMount
+    #[serde(rename = "mountedFile")]
+    MountedFile { path: String },
 }
 
 impl CloudflareSecretRef {
-    pub fn secret_ref(&self) -> &SecretRef {
+    pub fn secret_ref(&self) -> Option<&SecretRef> {
         match self {
-            CloudflareSecretRef::ApiKey(secret_ref) => secret_ref,
-            CloudflareSecretRef::ApiToken(secret_ref) => secret_ref,
+            CloudflareSecretRef::ApiKey(secret_ref) => Some(secret_ref),
+            CloudflareSecretRef::ApiToken(secret_ref) => Some(secret_ref),
+            CloudflareSecretRef::ZoneScopedToken(secret_ref) => Some(secret_ref),
+            CloudflareSecretRef::TunnelToken(secret_ref) => Some(secret_ref),
+            CloudflareSecretRef::MountedFile { .. } => None,
+        }
+    }
+
+    pub fn mounted_file(&self) -> Option<&str> {
+        match self {
+            CloudflareSecretRef::MountedFile { path } => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn tunnel_token(&self) -> Option<&SecretRef> {
+        match self {
+            CloudflareSecretRef::TunnelToken(secret_ref) => Some(secret_ref),
+            _ => None,
         }
     }
 }
 
+// where cloudflared reads credentials.json from, projected volumes and host
+// paths let e.g. CSI drivers provide it without a kubernetes secret
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum CredentialsMount {
+    SecretRef(SecretRef),
+    ProjectedVolume { sources: Vec<VolumeProjection> },
+    HostPath { path: String },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ZoneId {
+    Explicit(String),
+    #[default]
+    Auto,
+}
+
+impl From<String> for ZoneId {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "" | "auto" => ZoneId::Auto,
+            _ => ZoneId::Explicit(value),
+        }
+    }
+}
+
+impl From<ZoneId> for String {
+    fn from(value: ZoneId) -> Self {
+        match value {
+            ZoneId::Explicit(zone_id) => zone_id,
+            ZoneId::Auto => "auto".to_string(),
+        }
+    }
+}
+
+impl Serialize for ZoneId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        String::from(self.clone()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ZoneId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(ZoneId::from)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CloudflareCredentials {
     pub account_id: String,
-    pub zone_id: String,
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub zone_id: ZoneId,
     pub email: Option<String>,
+    // zero trust team, either the name or <team>.cloudflareaccess.com. cloudflared
+    // only validates Access jwts at the origin, it has no setting to send service
+    // token headers, so there is no service token reference next to it
+    pub team_domain: Option<String>,
     #[serde(flatten)]
     pub secret_ref: CloudflareSecretRef,
+    pub external_secret_ref: Option<ExternalSecretRef>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum DnsCleanupPolicy {
+    #[default]
+    Delete,
+    Retain,
+}
+
+impl FromStr for DnsCleanupPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Delete" => Ok(DnsCleanupPolicy::Delete),
+            "Retain" => Ok(DnsCleanupPolicy::Retain),
+            _ => Err(Error::InvalidSpec(format!(
+                "unsupported dns cleanup policy {s}"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum DeploymentKind {
+    #[default]
+    Deployment,
+    DaemonSet,
+}
+
+// Remote tunnels get their config pushed to the Cloudflare api instead of
+// reading it from the mounted config map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ConfigMode {
+    #[default]
+    Local,
+    Remote,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum CloudflaredLogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl CloudflaredLogLevel {
+    fn as_arg(&self) -> &'static str {
+        match self {
+            CloudflaredLogLevel::Debug => "debug",
+            CloudflaredLogLevel::Info => "info",
+            CloudflaredLogLevel::Warn => "warn",
+            CloudflaredLogLevel::Error => "error",
+            CloudflaredLogLevel::Fatal => "fatal",
+        }
+    }
+}
+
+// utilization targets are relative to the requests in spec.resources
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoscalingSpec {
+    pub min_replicas: i32,
+    pub max_replicas: i32,
+    pub target_cpu_utilization_percentage: Option<i32>,
+    pub target_memory_utilization_percentage: Option<i32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudflaredProbeConfig {
+    pub initial_delay_seconds: Option<i32>,
+    pub period_seconds: Option<i32>,
+    pub timeout_seconds: Option<i32>,
+    pub failure_threshold: Option<i32>,
+    pub success_threshold: Option<i32>,
+}
+
+impl CloudflaredProbeConfig {
+    fn probe(config: Option<&CloudflaredProbeConfig>) -> Probe {
+        let config = config.cloned().unwrap_or_default();
+
+        Probe {
+            http_get: Some(HTTPGetAction {
+                path: Some("/ready".to_string()),
+                port: IntOrString::Int(2000),
+                ..HTTPGetAction::default()
+            }),
+            initial_delay_seconds: config.initial_delay_seconds.or(Some(10)),
+            period_seconds: config.period_seconds.or(Some(10)),
+            timeout_seconds: config.timeout_seconds,
+            failure_threshold: config.failure_threshold.or(Some(1)),
+            success_threshold: config.success_threshold,
+            ..Probe::default()
+        }
+    }
+}
+
+fn service_port(service: &str) -> Option<i32> {
+    let (scheme, rest) = service.split_once("://")?;
+    let authority = rest.split('/').next()?;
+
+    match authority.rsplit_once(':') {
+        Some((_, port)) => port.parse().ok(),
+        None if scheme == "https" => Some(443),
+        None if scheme == "http" => Some(80),
+        None => None,
+    }
+}
+
+fn network_policy_ports(ports: &[(i32, &str)]) -> Vec<NetworkPolicyPort> {
+    ports
+        .iter()
+        .map(|(port, protocol)| NetworkPolicyPort {
+            port: Some(IntOrString::Int(*port)),
+            protocol: Some(protocol.to_string()),
+            ..NetworkPolicyPort::default()
+        })
+        .collect()
+}
+
+// origins may be served over udp too, e.g. quic or dns
+fn origin_policy_ports(ports: &[IntOrString]) -> Vec<NetworkPolicyPort> {
+    ports
+        .iter()
+        .flat_map(|port| {
+            ["TCP", "UDP"].map(|protocol| NetworkPolicyPort {
+                port: Some(port.clone()),
+                protocol: Some(protocol.to_string()),
+                ..NetworkPolicyPort::default()
+            })
+        })
+        .collect()
+}
+
+// name and namespace of the in-cluster Service an origin url points at
+fn origin_service(service: &str) -> Option<(&str, &str)> {
+    let (_, rest) = service.split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let host = authority.split(':').next()?;
+
+    host.strip_suffix(".svc")?.split_once('.')
+}
+
+// policies are enforced after the Service address is translated, so traffic
+// to in-cluster origins has to be allowed on the target ports of their pods
+async fn origin_target_ports(
+    ctx: Arc<Context>,
+    config: &TunnelConfig,
+) -> Result<Vec<IntOrString>, Error> {
+    let mut ports = Vec::new();
+    for ing in config.ingress.iter() {
+        let Some(port) = service_port(&ing.service) else {
+            continue;
+        };
+
+        let target_port = match origin_service(&ing.service) {
+            Some((name, namespace)) => {
+                let svc_api: Api<Service> = Api::namespaced(ctx.kube_cli.clone(), namespace);
+                svc_api
+                    .get_opt(name)
+                    .await?
+                    .and_then(|svc| svc.spec)
+                    .and_then(|spec| spec.ports)
+                    .into_iter()
+                    .flatten()
+                    .find(|svc_port| svc_port.port == port)
+                    .and_then(|svc_port| svc_port.target_port)
+            }
+            None => None,
+        };
+
+        let target_port = target_port.unwrap_or(IntOrString::Int(port));
+        if !ports.contains(&target_port) {
+            ports.push(target_port);
+        }
+    }
+
+    Ok(ports)
 }
 
 #[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[kube(
     kind = "ClusterTunnel",
     group = "cloudflare-tunnels-operator.io",
-    version = "v1alpha1"
+    version = "v1alpha1",
+    status = "ClusterTunnelStatus",
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Generation","type":"integer","jsonPath":".metadata.generation"}"#,
+    printcolumn = r#"{"name":"Synced","type":"integer","jsonPath":".status.observedGeneration"}"#,
+    printcolumn = r#"{"name":"Tunnel-ID","type":"string","jsonPath":".status.tunnelId"}"#,
+    printcolumn = r#"{"name":"Tunnel-Name","type":"string","jsonPath":".spec.name"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct ClusterTunnelSpec {
     pub name: Option<String>,
+    pub tunnel_id: Option<String>,
     pub tunnel_secret_ref: Option<SecretRef>,
+    pub credentials_mount: Option<CredentialsMount>,
     pub cloudflare: CloudflareCredentials,
+    pub default_policy_ref: Option<String>,
+    pub origin_request: Option<OriginRequest>,
+    pub replicas: Option<i32>,
+    pub autoscaling: Option<AutoscalingSpec>,
+    pub resources: Option<ResourceRequirements>,
+    pub deployment_kind: Option<DeploymentKind>,
+    pub config_mode: Option<ConfigMode>,
+    pub co_located_tunnels: Option<Vec<String>>,
+    pub service_account_annotations: Option<BTreeMap<String, String>>,
+    pub default_dns_cleanup_policy: Option<DnsCleanupPolicy>,
+    pub deletion_protection: Option<bool>,
+    pub private_networks: Option<Vec<String>>,
+    pub create_load_balancer: Option<bool>,
+    pub node_selector: Option<BTreeMap<String, String>>,
+    pub tolerations: Option<Vec<Toleration>>,
+    pub liveness_probe: Option<CloudflaredProbeConfig>,
+    pub readiness_probe: Option<CloudflaredProbeConfig>,
+    pub pod_labels: Option<BTreeMap<String, String>>,
+    pub pod_annotations: Option<BTreeMap<String, String>>,
+    pub deployment_labels: Option<BTreeMap<String, String>>,
+    pub deployment_annotations: Option<BTreeMap<String, String>>,
+    pub create_network_policy: Option<bool>,
+    pub create_service_monitor: Option<bool>,
+    pub image_pull_secrets: Option<Vec<String>>,
+    #[schemars(regex(pattern = r"^(Always|IfNotPresent|Never)$"))]
+    pub image_pull_policy: Option<String>,
+    #[schemars(regex(pattern = r"^(auto|quic|http2)$"))]
+    pub transport_protocol: Option<String>,
+    pub log_level: Option<CloudflaredLogLevel>,
+    pub transport_log_level: Option<CloudflaredLogLevel>,
+    #[schemars(regex(pattern = r"^(Prefix|Exact)$"))]
+    pub implementation_specific_path_type: Option<String>,
+    pub env: Option<Vec<EnvVar>>,
+    pub env_from: Option<Vec<EnvFromSource>>,
+    pub extra_volumes: Option<Vec<Volume>>,
+    pub extra_volume_mounts: Option<Vec<VolumeMount>>,
+    #[schemars(regex(pattern = r"^[a-z0-9-]{0,20}$"))]
+    pub resource_prefix: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterTunnelStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_zone_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_kind: Option<DeploymentKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_mode: Option<ConfigMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_secret_rotation_time: Option<Time>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_token_secret_ref: Option<SecretRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_balancer_pool_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone_settings_hash: Option<String>,
+    // the private networks the operator routed through the tunnel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_networks: Option<Vec<String>>,
+    // generation of the default policy the tunnel was last reconciled with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_generation: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sync_time: Option<Time>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
 }
 
 impl ClusterTunnel {
+    pub fn deployment_kind(&self) -> DeploymentKind {
+        self.spec.deployment_kind.unwrap_or_default()
+    }
+
+    pub fn config_mode(&self) -> ConfigMode {
+        self.spec.config_mode.unwrap_or_default()
+    }
+
+    pub fn resource_prefix(&self) -> &str {
+        self.spec
+            .resource_prefix
+            .as_deref()
+            .filter(|prefix| !prefix.is_empty())
+            .unwrap_or(DEFAULT_RESOURCE_PREFIX)
+    }
+
+    // tunnels deployed before the prefix was recorded used the default one
+    fn previous_resource_prefix(&self) -> Option<&str> {
+        self.status
+            .as_ref()
+            .filter(|status| status.tunnel_id.is_some())
+            .map(|status| {
+                status
+                    .resource_prefix
+                    .as_deref()
+                    .unwrap_or(DEFAULT_RESOURCE_PREFIX)
+            })
+    }
+
+    pub fn secret_refs(&self) -> impl Iterator<Item = &SecretRef> {
+        self.spec
+            .cloudflare
+            .secret_ref
+            .secret_ref()
+            .into_iter()
+            .chain(self.credentials_secret_ref())
+    }
+
+    pub fn references_secret(&self, operator_ns: &str, ns: &str, name: &str) -> bool {
+        self.secret_refs()
+            .any(|secret_ref| secret_ref.namespace_or(operator_ns) == ns && secret_ref.name == name)
+    }
+
+    // the secret holding the tunnel credentials when the user provides one
+    fn credentials_secret_ref(&self) -> Option<&SecretRef> {
+        match self.spec.credentials_mount.as_ref() {
+            Some(CredentialsMount::SecretRef(secret_ref)) => Some(secret_ref),
+            _ => self.spec.tunnel_secret_ref.as_ref(),
+        }
+    }
+
+    fn credentials_volume(&self) -> Option<(Volume, String)> {
+        let volume = Volume {
+            name: "credentials".to_string(),
+            ..Volume::default()
+        };
+
+        match self.spec.credentials_mount.as_ref()? {
+            CredentialsMount::SecretRef(_) => None,
+            // the sources have to provide a credentials.json
+            CredentialsMount::ProjectedVolume { sources } => Some((
+                Volume {
+                    projected: Some(ProjectedVolumeSource {
+                        sources: Some(sources.clone()),
+                        ..ProjectedVolumeSource::default()
+                    }),
+                    ..volume
+                },
+                "/credentials".to_string(),
+            )),
+            CredentialsMount::HostPath { path } => Some((
+                Volume {
+                    host_path: Some(HostPathVolumeSource {
+                        path: path.clone(),
+                        type_: Some("File".to_string()),
+                    }),
+                    ..volume
+                },
+                "/credentials/credentials.json".to_string(),
+            )),
+        }
+    }
+
+    // cloudflared connects with the token, there are no api credentials
+    pub fn uses_tunnel_token(&self) -> bool {
+        self.spec.cloudflare.secret_ref.tunnel_token().is_some()
+    }
+
+    fn manages_credentials_secret(&self) -> bool {
+        self.credentials_secret_ref().is_none() && self.credentials_volume().is_none()
+    }
+
+    pub async fn zone_id(
+        &self,
+        ctx: Arc<Context>,
+        cf_cli: &dyn CloudflareApi,
+        hostname: &str,
+    ) -> Result<String, Error> {
+        if let ZoneId::Explicit(zone_id) = &self.spec.cloudflare.zone_id {
+            return Ok(zone_id.clone());
+        }
+
+        if let Some(zone_id) = self
+            .status
+            .as_ref()
+            .and_then(|status| status.resolved_zone_id.clone())
+        {
+            return Ok(zone_id);
+        }
+
+        let zone_id = cf_cli
+            .find_zone_id_for_hostname(hostname)
+            .await?
+            .ok_or_else(|| Error::ZoneNotFound {
+                hostname: hostname.to_string(),
+            })?;
+
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+        let status = serde_json::json!({
+            "status": ClusterTunnelStatus {
+                resolved_zone_id: Some(zone_id.clone()),
+                ..ClusterTunnelStatus::default()
+            }
+        });
+        ct_api
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Merge(&status),
+            )
+            .await?;
+
+        Ok(zone_id)
+    }
+
+    fn ready_conditions(&self, ready: bool, reason: &str, message: &str) -> Vec<Condition> {
+        let conditions = self
+            .status
+            .as_ref()
+            .and_then(|status| status.conditions.clone())
+            .unwrap_or_default();
+
+        merge_condition(
+            &conditions,
+            condition("Ready", ready, reason, message, self.metadata.generation),
+        )
+    }
+
+    // Whether cloudflared has had ready pods for long enough to be expected
+    // to hold connections to cloudflare.
+    async fn workload_settled(&self, ctx: Arc<Context>) -> Result<bool, Error> {
+        let ns = ctx.operator_namespace.clone();
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+        let name = deployment_name(self.resource_prefix(), &tunnel_name);
+
+        let (created, ready) = match self.deployment_kind() {
+            DeploymentKind::Deployment => {
+                let api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+                let Some(deploy) = api.get_opt(&name).await? else {
+                    return Ok(false);
+                };
+                let ready = deploy
+                    .status
+                    .and_then(|status| status.ready_replicas)
+                    .unwrap_or(0);
+                (deploy.metadata.creation_timestamp, ready)
+            }
+            DeploymentKind::DaemonSet => {
+                let api: Api<DaemonSet> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+                let Some(ds) = api.get_opt(&name).await? else {
+                    return Ok(false);
+                };
+                let ready = ds.status.map(|status| status.number_ready).unwrap_or(0);
+                (ds.metadata.creation_timestamp, ready)
+            }
+        };
+
+        Ok(ready > 0
+            && created.is_some_and(|created| Utc::now() - created.0 > Duration::seconds(60)))
+    }
+
+    // A tunnel that is ready, unchanged since the last reconcile and whose
+    // config wasn't touched by other controllers doesn't need the cloudflare api.
+    // The periodic resync still goes through to refresh the connection count
+    // and the conditions.
+    fn is_reconciliation_needed(
+        &self,
+        current_hash: &str,
+        policy_generation: Option<i64>,
+        resync_interval: std::time::Duration,
+    ) -> bool {
+        let Some(status) = self.status.as_ref() else {
+            return true;
+        };
+
+        let ready = status
+            .conditions
+            .iter()
+            .flatten()
+            .any(|condition| condition.type_ == "Ready" && condition.status == "True");
+
+        // keep checking the connections of a degraded tunnel until it recovers
+        let degraded = status
+            .conditions
+            .iter()
+            .flatten()
+            .any(|condition| condition.type_ == "Degraded" && condition.status == "True");
+
+        let synced_recently = status.last_sync_time.as_ref().is_some_and(|synced| {
+            Duration::from_std(resync_interval)
+                .is_ok_and(|interval| Utc::now() - synced.0 < interval)
+        });
+
+        !ready
+            || degraded
+            || self.secret_rotation_requested()
+            || status.observed_generation != self.metadata.generation
+            || status.config_hash.as_deref() != Some(current_hash)
+            || status.zone_settings_hash != Some(self.zone_settings_hash())
+            || status.policy_generation != policy_generation
+            || !synced_recently
+    }
+
+    // zone-setting-<name> annotations, dashes in the name are accepted for
+    // the underscores cloudflare uses
+    fn zone_settings(&self) -> BTreeMap<String, String> {
+        self.annotations()
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(ANNOTATION_ZONE_SETTING_PREFIX)
+                    .map(|name| (name.replace('-', "_"), value.clone()))
+            })
+            .collect()
+    }
+
+    fn zone_settings_hash(&self) -> String {
+        sha256::digest(format!("{:?}", self.zone_settings()))
+    }
+
+    // the zone of an automatically resolved tunnel is only known once one
+    // of its hosts has been routed
+    fn known_zone_id(&self) -> Option<String> {
+        match &self.spec.cloudflare.zone_id {
+            ZoneId::Explicit(zone_id) => Some(zone_id.clone()),
+            ZoneId::Auto => self
+                .status
+                .as_ref()
+                .and_then(|status| status.resolved_zone_id.clone()),
+        }
+    }
+
+    // how ingress paths of type ImplementationSpecific are matched
+    pub fn path_type<'a>(&'a self, path_type: &'a str) -> &'a str {
+        match path_type {
+            "ImplementationSpecific" => self
+                .spec
+                .implementation_specific_path_type
+                .as_deref()
+                .unwrap_or("Prefix"),
+            _ => path_type,
+        }
+    }
+
+    fn secret_rotation_requested(&self) -> bool {
+        self.annotations()
+            .get(ANNOTATION_ROTATE_SECRET)
+            .is_some_and(|value| value == "true")
+    }
+
+    async fn current_config_hash(&self, ctx: Arc<Context>) -> Result<Option<String>, Error> {
+        let ns = ctx.operator_namespace.clone();
+        let cm_api: Api<ConfigMap> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+        let workload_name = deployment_name(self.resource_prefix(), &tunnel_name);
+        let deployed = match self.deployment_kind() {
+            DeploymentKind::Deployment => {
+                let deploy_api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+                deploy_api.get_opt(&workload_name).await?.is_some()
+            }
+            DeploymentKind::DaemonSet => {
+                let ds_api: Api<DaemonSet> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+                ds_api.get_opt(&workload_name).await?.is_some()
+            }
+        };
+        if !deployed {
+            return Ok(None);
+        }
+
+        // a malformed config map falls through to the full reconcile, which
+        // fails on it and reports the parse error
+        cm_api
+            .get_opt(&config_map_name(self.resource_prefix(), &tunnel_name))
+            .await?
+            .and_then(|cm| cm.data)
+            .and_then(|data| data.get("config.yaml").cloned())
+            .and_then(|config| TunnelConfig::from_yaml(&config).ok())
+            .map(|config| compute_config_hash(&config))
+            .transpose()
+    }
+
+    // observedGeneration is left untouched so a failed reconcile shows up
+    // as a generation mismatch
+    async fn set_not_ready(&self, ctx: Arc<Context>, err: &Error) -> Result<(), Error> {
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+        let status = serde_json::json!({
+            "status": ClusterTunnelStatus {
+                conditions: Some(self.ready_conditions(false, "ReconcileFailed", &err.to_string())),
+                ..ClusterTunnelStatus::default()
+            }
+        });
+        ct_api
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Merge(&status),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // the credentials of the adopted, found or newly created tunnel
+    async fn api_tunnel_credentials(
+        &self,
+        ctx: Arc<Context>,
+        cf_cli: &dyn CloudflareApi,
+        tunnel_name: &str,
+    ) -> Result<TunnelCredentials, Error> {
+        if let Some(tunnel_id) = self.spec.tunnel_id.as_ref() {
+            info!("adopting tunnel: {tunnel_id}");
+
+            return cf_cli.get_tunnel(tunnel_id).await;
+        }
+
+        let Some(tunnel_id) = cf_cli.find_tunnel(tunnel_name).await? else {
+            info!("tunnel not found, creating...");
+
+            let remote_config = self.config_mode() == ConfigMode::Remote;
+            return match cf_cli.create_tunnel(tunnel_name, remote_config).await {
+                Err(Error::TunnelAlreadyExists(_)) => {
+                    self.adopt_existing_tunnel(cf_cli, tunnel_name).await
+                }
+                result => result,
+            };
+        };
+        info!("tunnel found: {tunnel_id}");
+
+        let secret_ref = self
+            .credentials_secret_ref()
+            .cloned()
+            .unwrap_or_else(|| SecretRef {
+                name: credentials_secret_name(
+                    self.previous_resource_prefix()
+                        .unwrap_or(self.resource_prefix()),
+                    tunnel_name,
+                ),
+                key: "credentials.json".to_string(),
+                namespace: None,
+            });
+
+        let ns = secret_ref.namespace_or(&ctx.operator_namespace);
+        let secret_api: Api<Secret> = Api::namespaced(ctx.kube_cli.clone(), ns);
+        let name = format!("{ns}/{}", secret_ref.name);
+        let secret = secret_api
+            .get_opt(&secret_ref.name)
+            .await?
+            .ok_or_else(|| Error::SecretNotFound { name: name.clone() })?;
+        let creds = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(&secret_ref.key))
+            .ok_or_else(|| Error::SecretKeyNotFound {
+                name,
+                key: secret_ref.key.clone(),
+            })?;
+
+        TunnelCredentials::from_json(&creds.0)
+    }
+
+    // Another replica or an interrupted reconcile created the tunnel between
+    // the lookup and the create, the listing can lag behind for a moment.
+    async fn adopt_existing_tunnel(
+        &self,
+        cf_cli: &dyn CloudflareApi,
+        tunnel_name: &str,
+    ) -> Result<TunnelCredentials, Error> {
+        for attempt in 1..=TUNNEL_ADOPT_ATTEMPTS {
+            match cf_cli.find_tunnel(tunnel_name).await {
+                Ok(Some(tunnel_id)) => {
+                    info!("adopting existing tunnel: {tunnel_id}");
+                    return cf_cli.get_tunnel(&tunnel_id).await;
+                }
+                Ok(None) => warn!("tunnel {tunnel_name} already exists but was not found"),
+                Err(err) => warn!("failed to find existing tunnel {tunnel_name}: {err}"),
+            }
+
+            if attempt < TUNNEL_ADOPT_ATTEMPTS {
+                tokio::time::sleep(std::time::Duration::from_secs(attempt as u64)).await;
+            }
+        }
+
+        Err(Error::TunnelAlreadyExists(tunnel_name.to_string()))
+    }
+
+    // the networks routed by a previous reconcile, their routes are removed
+    // again once the spec drops them
+    fn routed_private_networks(&self) -> Option<&Vec<String>> {
+        self.status
+            .as_ref()
+            .and_then(|status| status.private_networks.as_ref())
+    }
+
+    // Adds routes for private networks missing on the tunnel and removes the
+    // ones the operator added that are no longer listed in the spec. Tunnels
+    // that never listed any are left alone, their routes are someone else's.
+    async fn sync_tunnel_routes(
+        &self,
+        cf_cli: &dyn CloudflareApi,
+        tunnel_id: &str,
+    ) -> Result<Option<Vec<String>>, Error> {
+        if self.spec.private_networks.is_none() && self.routed_private_networks().is_none() {
+            return Ok(None);
+        }
+
+        // cidrs are compared by the network they cover, 10.0.0.1/8 is 10.0.0.0/8
+        let mut networks = self
+            .spec
+            .private_networks
+            .iter()
+            .flatten()
+            .map(|network| {
+                network
+                    .parse::<IpNet>()
+                    .map(|network| network.trunc())
+                    .map_err(|err| {
+                        Error::InvalidSpec(format!("invalid private network {network}: {err}"))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        networks.sort();
+        networks.dedup();
+        let routes = cf_cli.list_tunnel_routes(tunnel_id).await?;
+        let route_network = |route: &TunnelRoute| {
+            route
+                .network
+                .parse::<IpNet>()
+                .ok()
+                .map(|network| network.trunc())
+        };
+
+        for network in networks.iter() {
+            if !routes
+                .iter()
+                .any(|route| route_network(route) == Some(*network))
+            {
+                info!("adding tunnel route {network}");
+                cf_cli
+                    .add_tunnel_route(tunnel_id, &network.to_string())
+                    .await?;
+            }
+        }
+
+        for route in routes.iter() {
+            let wanted = route_network(route).is_some_and(|network| networks.contains(&network));
+            if route.tunnel_id == tunnel_id && route.is_managed() && !wanted {
+                info!("removing tunnel route {}", route.network);
+                cf_cli.delete_tunnel_route(&route.id).await?;
+            }
+        }
+
+        Ok(self
+            .spec
+            .private_networks
+            .as_ref()
+            .map(|_| networks.iter().map(IpNet::to_string).collect()))
+    }
+
     async fn deploy_cloudflared(
         &self,
         ctx: Arc<Context>,
+        cf_cli: Option<&dyn CloudflareApi>,
         creds: &TunnelCredentials,
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
         let oref = self.owner_references();
-        let ns = std::env::var("POD_NAMESPACE").unwrap_or_else(|_| "default".to_string());
+        let ns = ctx.operator_namespace.clone();
         let client = ctx.kube_cli.clone();
 
         let cm_api: Api<ConfigMap> = Api::namespaced(client.clone(), &ns);
         let secret_api: Api<Secret> = Api::namespaced(client.clone(), &ns);
         let deploy_api: Api<Deployment> = Api::namespaced(client.clone(), &ns);
+        let ds_api: Api<DaemonSet> = Api::namespaced(client.clone(), &ns);
+        let pdb_api: Api<PodDisruptionBudget> = Api::namespaced(client.clone(), &ns);
+        let hpa_api: Api<HorizontalPodAutoscaler> = Api::namespaced(client.clone(), &ns);
+        let netpol_api: Api<NetworkPolicy> = Api::namespaced(client.clone(), &ns);
+        let sa_api: Api<ServiceAccount> = Api::namespaced(client.clone(), &ns);
+        let service_api: Api<Service> = Api::namespaced(client.clone(), &ns);
 
         let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
 
-        let mut labels = BTreeMap::new();
-        labels.insert(
-            "app.kubernetes.io/part-of".to_string(),
-            "cloudflare-tunnels-operator".to_string(),
-        );
-        labels.insert(
-            "app.kubernetes.io/name".to_string(),
-            "cloudflared".to_string(),
-        );
+        let mut labels = BTreeMap::new();
+        labels.insert(
+            "app.kubernetes.io/part-of".to_string(),
+            "cloudflare-tunnels-operator".to_string(),
+        );
+        labels.insert(
+            "app.kubernetes.io/name".to_string(),
+            "cloudflared".to_string(),
+        );
+        labels.insert(
+            "app.kubernetes.io/instance".to_string(),
+            tunnel_name.clone(),
+        );
+
+        let creds_json = serde_json::to_string(creds)?;
+
+        let prefix = self.resource_prefix();
+        let config_name = config_map_name(prefix, &tunnel_name);
+
+        let mut flags = vec![];
+        if let Some(protocol) = self.spec.transport_protocol.as_ref() {
+            flags.extend(["--protocol".to_string(), protocol.clone()]);
+        }
+        // cloudflared logs at info without the flags
+        if let Some(level) = self.spec.log_level {
+            flags.extend(["--loglevel".to_string(), level.as_arg().to_string()]);
+        }
+        if let Some(level) = self.spec.transport_log_level {
+            flags.extend([
+                "--transport-loglevel".to_string(),
+                level.as_arg().to_string(),
+            ]);
+        }
+        let tunnel_args = |metrics_port: u16| {
+            let mut args = vec![
+                "tunnel".to_string(),
+                "--no-autoupdate".to_string(),
+                "--metrics".to_string(),
+                format!("0.0.0.0:{metrics_port}"),
+            ];
+            args.extend(flags.iter().cloned());
+            args
+        };
+
+        let mut args = tunnel_args(2000);
+        let mut volumes = vec![];
+        let mut volume_mounts = vec![];
+        let mut env = vec![];
+        let mut co_located = vec![];
+        let mut co_located_hashes = BTreeMap::new();
+
+        if let Some(token_ref) = self.spec.cloudflare.secret_ref.tunnel_token() {
+            args.push("run".to_string());
+            env.push(EnvVar {
+                name: "TUNNEL_TOKEN".to_string(),
+                value_from: Some(EnvVarSource {
+                    secret_key_ref: Some(SecretKeySelector {
+                        name: token_ref.name.clone(),
+                        key: token_ref.key.clone(),
+                        ..SecretKeySelector::default()
+                    }),
+                    ..EnvVarSource::default()
+                }),
+                ..EnvVar::default()
+            });
+        } else {
+            if self.spec.cloudflare.secret_ref.mounted_file().is_some()
+                && self.manages_credentials_secret()
+            {
+                return Err(Error::InvalidSpec(
+                    "mountedFile requires spec.credentialsMount".into(),
+                ));
+            }
+
+            let credentials_volume = self.credentials_volume();
+            let credentials_secret = if let Some(secret_ref) = self.credentials_secret_ref() {
+                // pods can only mount secrets from their own namespace
+                if secret_ref.namespace_or(&ns) != ns {
+                    return Err(Error::InvalidSpec(format!(
+                        "tunnel credentials secret {} must be in namespace {ns}",
+                        secret_ref.name
+                    )));
+                }
+                Some((secret_ref.name.clone(), Some(secret_ref.key.clone())))
+            } else if credentials_volume.is_some() {
+                None
+            } else {
+                let secret_name = credentials_secret_name(prefix, &tunnel_name);
+                let secret = Secret {
+                    metadata: ObjectMeta {
+                        name: Some(secret_name.clone()),
+                        namespace: Some(ns.to_owned()),
+                        owner_references: Some(oref.to_vec()),
+                        ..ObjectMeta::default()
+                    },
+                    string_data: Some({
+                        let mut map = BTreeMap::new();
+                        map.insert("credentials.json".to_string(), creds_json.clone());
+                        map
+                    }),
+                    ..Default::default()
+                };
+
+                secret_api
+                    .patch(
+                        &secret.name_any(),
+                        &PatchParams::apply(OPERATOR_MANAGER),
+                        &Patch::Apply(&secret),
+                    )
+                    .await?;
+
+                Some((secret_name, Some("credentials.json".to_string())))
+            };
+
+            let remote = self.config_mode() == ConfigMode::Remote;
+            if !remote {
+                args.extend(["--config".to_string(), "/config/config.yaml".to_string()]);
+            }
+
+            // other tunnels served by this pod, cloudflared runs a single tunnel
+            // per process so each gets its own container
+            let ct_api: Api<ClusterTunnel> = Api::all(client.clone());
+            for (i, name) in self.spec.co_located_tunnels.iter().flatten().enumerate() {
+                let Some(other) = ct_api.get_opt(name).await? else {
+                    return Err(Error::TunnelNotFound { name: name.clone() });
+                };
+                let Some(other_tunnel_id) = other
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.tunnel_id.clone())
+                else {
+                    return Err(Error::TunnelNotFound { name: name.clone() });
+                };
+                if other.uses_tunnel_token() || other.credentials_volume().is_some() {
+                    return Err(Error::InvalidSpec(format!(
+                        "co-located tunnel {name} must keep its credentials in a secret"
+                    )));
+                }
+
+                let other_tunnel_name = other.spec.name.clone().unwrap_or_else(|| other.name_any());
+                let (secret_name, secret_key) = match other.credentials_secret_ref() {
+                    Some(secret_ref) if secret_ref.namespace_or(&ns) == ns => {
+                        (secret_ref.name.clone(), secret_ref.key.clone())
+                    }
+                    Some(secret_ref) => {
+                        return Err(Error::InvalidSpec(format!(
+                            "tunnel credentials secret {} must be in namespace {ns}",
+                            secret_ref.name
+                        )))
+                    }
+                    None => (
+                        credentials_secret_name(other.resource_prefix(), &other_tunnel_name),
+                        "credentials.json".to_string(),
+                    ),
+                };
+                let other_config_name =
+                    config_map_name(other.resource_prefix(), &other_tunnel_name);
+
+                // rolls the pods when the other tunnel's routes or credentials
+                // change, just like for our own
+                let other_config = cm_api
+                    .get_opt(&other_config_name)
+                    .await?
+                    .and_then(|cm| cm.data)
+                    .and_then(|data| data.get("config.yaml").cloned())
+                    .unwrap_or_default();
+                let other_creds_version = secret_api
+                    .get_opt(&secret_name)
+                    .await?
+                    .and_then(|secret| secret.resource_version())
+                    .unwrap_or_default();
+                co_located_hashes.insert(
+                    format!("{ANNOTATION_CONFIG_HASH}-{i}"),
+                    sha256::digest(format!("{other_config}/{other_creds_version}")),
+                );
+
+                // indices keep the names within the 63 character limit
+                let other_config_volume = format!("config-{i}");
+                let other_credentials_volume = format!("credentials-{i}");
+                let mut other_args = tunnel_args(2001 + i as u16);
+                let mut other_mounts = vec![VolumeMount {
+                    name: other_credentials_volume.clone(),
+                    mount_path: "/credentials/credentials.json".to_string(),
+                    sub_path: Some(secret_key),
+                    read_only: Some(true),
+                    ..VolumeMount::default()
+                }];
+                if other.config_mode() == ConfigMode::Remote {
+                    other_args.extend([
+                        "run".to_string(),
+                        "--credentials-file".to_string(),
+                        "/credentials/credentials.json".to_string(),
+                    ]);
+                } else {
+                    other_args.extend([
+                        "--config".to_string(),
+                        "/config/config.yaml".to_string(),
+                        "run".to_string(),
+                    ]);
+                    volumes.push(Volume {
+                        name: other_config_volume.clone(),
+                        config_map: Some(ConfigMapVolumeSource {
+                            name: other_config_name,
+                            ..ConfigMapVolumeSource::default()
+                        }),
+                        ..Volume::default()
+                    });
+                    other_mounts.push(VolumeMount {
+                        name: other_config_volume,
+                        mount_path: "/config".to_string(),
+                        ..VolumeMount::default()
+                    });
+                }
+                other_args.push(other_tunnel_id);
+                volumes.push(Volume {
+                    name: other_credentials_volume,
+                    secret: Some(SecretVolumeSource {
+                        secret_name: Some(secret_name),
+                        ..SecretVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                });
+
+                co_located.push(Container {
+                    name: format!("cloudflared-{i}"),
+                    image: Some(CLOUDFLARED_IMAGE.to_string()),
+                    image_pull_policy: self.spec.image_pull_policy.clone(),
+                    args: Some(other_args),
+                    env_from: self.spec.env_from.clone(),
+                    resources: self.spec.resources.clone(),
+                    volume_mounts: Some(other_mounts),
+                    ..Container::default()
+                });
+            }
+
+            args.push("run".to_string());
+            if remote {
+                args.extend([
+                    "--credentials-file".to_string(),
+                    "/credentials/credentials.json".to_string(),
+                ]);
+            } else {
+                volumes.push(Volume {
+                    name: "config".to_string(),
+                    config_map: Some(ConfigMapVolumeSource {
+                        name: config_name.to_string(),
+                        ..ConfigMapVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                });
+                volume_mounts.push(VolumeMount {
+                    name: "config".to_string(),
+                    mount_path: "/config".to_string(),
+                    ..VolumeMount::default()
+                });
+            }
+            args.push(creds.tunnel_id.clone());
+            if let Some((volume, mount_path)) = credentials_volume {
+                volumes.push(volume);
+                volume_mounts.push(VolumeMount {
+                    name: "credentials".to_string(),
+                    mount_path,
+                    read_only: Some(true),
+                    ..VolumeMount::default()
+                });
+            } else if let Some((secret_name, secret_key)) = credentials_secret {
+                volumes.push(Volume {
+                    name: "credentials".to_string(),
+                    secret: Some(SecretVolumeSource {
+                        secret_name: Some(secret_name),
+                        ..SecretVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                });
+                volume_mounts.push(VolumeMount {
+                    name: "credentials".to_string(),
+                    mount_path: "/credentials/credentials.json".to_string(),
+                    sub_path: secret_key,
+                    ..VolumeMount::default()
+                });
+            }
+        }
+
+        // user provided variables replace operator managed ones of the same
+        // name, duplicate names would leave the winner up to the kubelet
+        for var in self.spec.env.iter().flatten() {
+            let Some(existing) = env.iter_mut().find(|existing| existing.name == var.name) else {
+                env.push(var.clone());
+                continue;
+            };
+
+            if existing != var {
+                ctx.recorder
+                    .publish(
+                        &Event {
+                            type_: EventType::Warning,
+                            reason: "EnvOverride".to_string(),
+                            note: Some(format!(
+                                "spec.env overrides {} which is managed by the operator",
+                                var.name
+                            )),
+                            action: "Deploying".to_string(),
+                            secondary: None,
+                        },
+                        &self.object_ref(&()),
+                    )
+                    .await?;
+                *existing = var.clone();
+            }
+        }
+
+        for volume in self.spec.extra_volumes.iter().flatten() {
+            if ["config", "credentials"].contains(&volume.name.as_str())
+                || volumes.iter().any(|existing| existing.name == volume.name)
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "extra volume {} conflicts with a volume managed by the operator",
+                    volume.name
+                )));
+            }
+        }
+        volumes.extend(self.spec.extra_volumes.iter().flatten().cloned());
+        volume_mounts.extend(self.spec.extra_volume_mounts.iter().flatten().cloned());
+
+        let mut existing = cm_api.get_opt(&config_name).await?;
+        // carry the routes over when the resources are being renamed
+        if let Some(previous) = self.previous_resource_prefix() {
+            if existing.is_none() && previous != prefix {
+                existing = cm_api
+                    .get_opt(&config_map_name(previous, &tunnel_name))
+                    .await?;
+            }
+        }
+        let existing_data = existing.as_ref().and_then(|cm| cm.data.clone());
+        let ingresses = existing.as_ref().map(managed_ingresses).unwrap_or_default();
+        let backend_owner = existing.as_ref().and_then(default_backend_owner);
+        let mut config = existing_data
+            .as_ref()
+            .and_then(|data| data.get("config.yaml").cloned())
+            .map(|config| TunnelConfig::from_yaml(&config))
+            .transpose()?
+            .unwrap_or_else(|| TunnelConfig {
+                tunnel: creds.tunnel_id.clone(),
+                credentials_file: "/credentials/credentials.json".to_string(),
+                ingress: vec![TunnelIngress {
+                    service: "http_status:404".to_string(),
+                    ..TunnelIngress::default()
+                }],
+                ..TunnelConfig::default()
+            });
+
+        let policy_origin_request = match self.spec.default_policy_ref.as_ref() {
+            Some(policy_ref) => Some(
+                get_policy(ctx.clone(), &ns, policy_ref)
+                    .await?
+                    .ok_or_else(|| Error::TunnelPolicyNotFound {
+                        name: policy_ref.clone(),
+                    })?
+                    .spec
+                    .origin_request,
+            ),
+            None => None,
+        };
+        // the tunnel's own defaults win over the ones of its policy
+        config.origin_request = match (policy_origin_request, self.spec.origin_request.as_ref()) {
+            (Some(policy), Some(own)) => Some(policy.merge(own)),
+            (policy, own) => policy.or_else(|| own.cloned()),
+        };
+
+        config.warp_routing = self
+            .spec
+            .private_networks
+            .as_ref()
+            .filter(|networks| !networks.is_empty())
+            .map(|_| WarpRouting { enabled: true });
+
+        let config_yaml = config.to_yaml()?;
+        let config_hash = compute_config_hash(&config)?;
+
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(config_name.to_string()),
+                namespace: Some(ns.to_owned()),
+                owner_references: Some(oref.to_vec()),
+                annotations: Some(managed_ingresses_annotations(
+                    &ingresses,
+                    backend_owner.as_ref(),
+                )),
+                ..ObjectMeta::default()
+            },
+            data: Some(config_map_data(
+                &config,
+                config_yaml,
+                existing_data.as_ref(),
+            )),
+            ..ConfigMap::default()
+        };
+
+        cm_api
+            .patch(
+                &config_map.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Apply(&config_map),
+            )
+            .await?;
+
+        // the config map stays the record of the routes, cloudflared reads
+        // the pushed copy; token tunnels can't be remote, see reconcile
+        if let Some(cf_cli) = cf_cli.filter(|_| self.config_mode() == ConfigMode::Remote) {
+            cf_cli
+                .put_tunnel_configuration(&creds.tunnel_id, &config)
+                .await?;
+        }
+
+        let service_account = ServiceAccount {
+            metadata: ObjectMeta {
+                name: Some(deployment_name(prefix, &tunnel_name)),
+                namespace: Some(ns.to_owned()),
+                owner_references: Some(oref.to_vec()),
+                labels: Some(labels.clone()),
+                annotations: self.spec.service_account_annotations.clone(),
+                ..ObjectMeta::default()
+            },
+            automount_service_account_token: Some(false),
+            ..ServiceAccount::default()
+        };
+
+        sa_api
+            .patch(
+                &service_account.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Apply(&service_account),
+            )
+            .await?;
+
+        let mut template = PodTemplateSpec {
+            metadata: Some(ObjectMeta {
+                labels: Some(merge_labels(&labels, self.spec.pod_labels.as_ref())),
+                annotations: Some({
+                    let mut map = self.spec.pod_annotations.clone().unwrap_or_default();
+                    // remote tunnels pick up config changes without a restart
+                    if self.config_mode() == ConfigMode::Local {
+                        map.insert(ANNOTATION_CONFIG_HASH.to_string(), config_hash.clone());
+                    }
+                    // rolls the pods when the credentials change, e.g. after a rotation
+                    map.insert(
+                        ANNOTATION_CREDENTIALS_HASH.to_string(),
+                        sha256::digest(creds_json.as_str()),
+                    );
+                    map.extend(co_located_hashes);
+                    map
+                }),
+                ..ObjectMeta::default()
+            }),
+            spec: Some(PodSpec {
+                service_account_name: Some(service_account.name_any()),
+                node_selector: self.spec.node_selector.clone(),
+                tolerations: self.spec.tolerations.clone(),
+                image_pull_secrets: self.spec.image_pull_secrets.as_ref().map(|secrets| {
+                    secrets
+                        .iter()
+                        .map(|name| LocalObjectReference { name: name.clone() })
+                        .collect()
+                }),
+                volumes: Some(volumes),
+                containers: vec![Container {
+                    name: "cloudflared".to_string(),
+                    image: Some(CLOUDFLARED_IMAGE.to_string()),
+                    image_pull_policy: self.spec.image_pull_policy.clone(),
+                    args: Some(args),
+                    env: Some(env),
+                    env_from: self.spec.env_from.clone(),
+                    resources: self.spec.resources.clone(),
+                    volume_mounts: Some(volume_mounts),
+                    // outbound edge connections, declared for network policy tooling
+                    ports: Some(vec![
+                        ContainerPort {
+                            name: Some("quic".to_string()),
+                            container_port: 7844,
+                            protocol: Some("UDP".to_string()),
+                            ..ContainerPort::default()
+                        },
+                        ContainerPort {
+                            name: Some("https".to_string()),
+                            container_port: 443,
+                            protocol: Some("TCP".to_string()),
+                            ..ContainerPort::default()
+                        },
+                    ]),
+                    liveness_probe: Some(CloudflaredProbeConfig::probe(
+                        self.spec.liveness_probe.as_ref(),
+                    )),
+                    readiness_probe: Some(CloudflaredProbeConfig::probe(
+                        self.spec.readiness_probe.as_ref(),
+                    )),
+                    ..Container::default()
+                }],
+                ..PodSpec::default()
+            }),
+            ..PodTemplateSpec::default()
+        };
+
+        if let Some(spec) = template.spec.as_mut() {
+            spec.containers.extend(co_located);
+        }
+
+        let workload_name = deployment_name(prefix, &tunnel_name);
+        let workload_metadata = ObjectMeta {
+            name: Some(workload_name.clone()),
+            namespace: Some(ns.to_owned()),
+            owner_references: Some(oref.to_vec()),
+            labels: Some(merge_labels(&labels, self.spec.deployment_labels.as_ref())),
+            annotations: self.spec.deployment_annotations.clone(),
+            ..ObjectMeta::default()
+        };
+        let selector = LabelSelector {
+            match_labels: Some(labels.clone()),
+            ..LabelSelector::default()
+        };
+
+        // switching kinds replaces the workload, the pods would clash otherwise
+        match self.deployment_kind() {
+            DeploymentKind::Deployment => {
+                let deployment = Deployment {
+                    metadata: workload_metadata,
+                    spec: Some(DeploymentSpec {
+                        // left to the autoscaler when there is one
+                        replicas: self
+                            .spec
+                            .replicas
+                            .filter(|_| self.spec.autoscaling.is_none()),
+                        selector,
+                        template,
+                        ..DeploymentSpec::default()
+                    }),
+                    ..Deployment::default()
+                };
+
+                deploy_api
+                    .patch(
+                        &workload_name,
+                        &PatchParams::apply(OPERATOR_MANAGER),
+                        &Patch::Apply(&deployment),
+                    )
+                    .await?;
+                delete_if_exists(&ds_api, &workload_name).await?;
+            }
+            DeploymentKind::DaemonSet => {
+                let daemon_set = DaemonSet {
+                    metadata: workload_metadata,
+                    spec: Some(DaemonSetSpec {
+                        selector,
+                        template,
+                        update_strategy: Some(DaemonSetUpdateStrategy {
+                            type_: Some("RollingUpdate".to_string()),
+                            rolling_update: Some(RollingUpdateDaemonSet {
+                                max_unavailable: Some(IntOrString::Int(1)),
+                                ..RollingUpdateDaemonSet::default()
+                            }),
+                        }),
+                        ..DaemonSetSpec::default()
+                    }),
+                    ..DaemonSet::default()
+                };
+
+                ds_api
+                    .patch(
+                        &workload_name,
+                        &PatchParams::apply(OPERATOR_MANAGER),
+                        &Patch::Apply(&daemon_set),
+                    )
+                    .await?;
+                delete_if_exists(&deploy_api, &workload_name).await?;
+            }
+        }
+
+        // only the legacy deployment this tunnel owns, a user's one may share the name
+        let legacy_owned = deploy_api
+            .get_opt(LEGACY_DEPLOYMENT_NAME)
+            .await?
+            .is_some_and(|legacy| {
+                legacy
+                    .owner_references()
+                    .iter()
+                    .any(|owner| self.uid().as_ref() == Some(&owner.uid))
+            });
+        if legacy_owned && workload_name != LEGACY_DEPLOYMENT_NAME {
+            info!("deleting legacy deployment {LEGACY_DEPLOYMENT_NAME}");
+            delete_if_exists(&deploy_api, LEGACY_DEPLOYMENT_NAME).await?;
+        }
+
+        let netpol_name = format!("{prefix}-{tunnel_name}-netpol");
+        if self.spec.create_network_policy.unwrap_or(false) {
+            let mut origin_ports: Vec<i32> = config
+                .ingress
+                .iter()
+                .filter_map(|ing| service_port(&ing.service))
+                .collect();
+            origin_ports.sort();
+            origin_ports.dedup();
+            let origin_ports: Vec<IntOrString> =
+                origin_ports.into_iter().map(IntOrString::Int).collect();
+
+            // 7844 is used by both the http2 and quic transports, 53 resolves the edge
+            let mut egress = vec![NetworkPolicyEgressRule {
+                ports: Some(network_policy_ports(&[
+                    (443, "TCP"),
+                    (7844, "TCP"),
+                    (7844, "UDP"),
+                    (53, "TCP"),
+                    (53, "UDP"),
+                ])),
+                ..NetworkPolicyEgressRule::default()
+            }];
+            let target_ports = origin_target_ports(ctx.clone(), &config).await?;
+            if !target_ports.is_empty() {
+                egress.push(NetworkPolicyEgressRule {
+                    ports: Some(origin_policy_ports(&target_ports)),
+                    ..NetworkPolicyEgressRule::default()
+                });
+            }
+
+            // a rule without ports would allow every port, so without any
+            // origins no ingress is allowed at all
+            let ingress = if origin_ports.is_empty() {
+                vec![]
+            } else {
+                vec![NetworkPolicyIngressRule {
+                    from: Some(vec![NetworkPolicyPeer {
+                        namespace_selector: Some(LabelSelector {
+                            match_labels: Some(BTreeMap::from([(
+                                "kubernetes.io/metadata.name".to_string(),
+                                ns.to_owned(),
+                            )])),
+                            ..LabelSelector::default()
+                        }),
+                        ..NetworkPolicyPeer::default()
+                    }]),
+                    ports: Some(origin_policy_ports(&origin_ports)),
+                }]
+            };
+
+            let netpol = NetworkPolicy {
+                metadata: ObjectMeta {
+                    name: Some(netpol_name),
+                    namespace: Some(ns.to_owned()),
+                    owner_references: Some(oref.to_vec()),
+                    labels: Some(labels.clone()),
+                    ..ObjectMeta::default()
+                },
+                spec: Some(NetworkPolicySpec {
+                    pod_selector: LabelSelector {
+                        match_labels: Some(labels.clone()),
+                        ..LabelSelector::default()
+                    },
+                    policy_types: Some(vec!["Ingress".to_string(), "Egress".to_string()]),
+                    ingress: Some(ingress),
+                    egress: Some(egress),
+                }),
+            };
+
+            netpol_api
+                .patch(
+                    &netpol.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Apply(&netpol),
+                )
+                .await?;
+        } else {
+            delete_if_exists(&netpol_api, &netpol_name).await?;
+        }
+
+        let hpa_name = format!("{prefix}-{tunnel_name}-hpa");
+        if let Some(autoscaling) = self.spec.autoscaling.as_ref() {
+            let utilization = |name: &str, percentage: Option<i32>| {
+                percentage.map(|percentage| MetricSpec {
+                    type_: "Resource".to_string(),
+                    resource: Some(ResourceMetricSource {
+                        name: name.to_string(),
+                        target: MetricTarget {
+                            type_: "Utilization".to_string(),
+                            average_utilization: Some(percentage),
+                            ..MetricTarget::default()
+                        },
+                    }),
+                    ..MetricSpec::default()
+                })
+            };
+
+            let metrics: Vec<MetricSpec> = [
+                utilization("cpu", autoscaling.target_cpu_utilization_percentage),
+                utilization("memory", autoscaling.target_memory_utilization_percentage),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+
+            let hpa = HorizontalPodAutoscaler {
+                metadata: ObjectMeta {
+                    name: Some(hpa_name),
+                    namespace: Some(ns.to_owned()),
+                    owner_references: Some(oref.to_vec()),
+                    labels: Some(labels.clone()),
+                    ..ObjectMeta::default()
+                },
+                spec: Some(HorizontalPodAutoscalerSpec {
+                    scale_target_ref: CrossVersionObjectReference {
+                        api_version: Some("apps/v1".to_string()),
+                        kind: "Deployment".to_string(),
+                        name: workload_name.clone(),
+                    },
+                    min_replicas: Some(autoscaling.min_replicas),
+                    max_replicas: autoscaling.max_replicas,
+                    // left out without a target, so the api server defaults it to
+                    // 80% cpu utilization
+                    metrics: Some(metrics).filter(|metrics| !metrics.is_empty()),
+                    ..HorizontalPodAutoscalerSpec::default()
+                }),
+                ..HorizontalPodAutoscaler::default()
+            };
+
+            hpa_api
+                .patch(
+                    &hpa.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Apply(&hpa),
+                )
+                .await?;
+        } else {
+            delete_if_exists(&hpa_api, &hpa_name).await?;
+        }
 
-        let creds_json = serde_json::to_string(creds).unwrap();
+        let pdb_name = format!("{prefix}-{tunnel_name}-pdb");
+        let replicas = match self.spec.autoscaling.as_ref() {
+            Some(autoscaling) => autoscaling.min_replicas,
+            None => self.spec.replicas.unwrap_or(1),
+        };
+        // a daemon set runs one pod per node, replicas don't apply to it
+        if self.deployment_kind() == DeploymentKind::Deployment && replicas > 1 {
+            let pdb = PodDisruptionBudget {
+                metadata: ObjectMeta {
+                    name: Some(pdb_name),
+                    namespace: Some(ns.to_owned()),
+                    owner_references: Some(oref.to_vec()),
+                    labels: Some(labels.clone()),
+                    ..ObjectMeta::default()
+                },
+                spec: Some(PodDisruptionBudgetSpec {
+                    min_available: Some(IntOrString::Int(1)),
+                    selector: Some(LabelSelector {
+                        match_labels: Some(labels.clone()),
+                        ..LabelSelector::default()
+                    }),
+                    ..PodDisruptionBudgetSpec::default()
+                }),
+                ..PodDisruptionBudget::default()
+            };
 
-        let (secret_name, secret_key) = if let Some(secret_ref) = self.spec.tunnel_secret_ref.as_ref() {
-            (secret_ref.name.clone(), Some(secret_ref.key.clone()))
+            pdb_api
+                .patch(
+                    &pdb.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Apply(&pdb),
+                )
+                .await?;
         } else {
-            let secret_name = format!("cloudflared-{tunnel_name}-credentials");
-            let secret = Secret {
+            delete_if_exists(&pdb_api, &pdb_name).await?;
+        }
+
+        let metrics_name = format!("{prefix}-{tunnel_name}-metrics");
+        if self.spec.create_service_monitor.unwrap_or(false) {
+            let service = Service {
                 metadata: ObjectMeta {
-                    name: Some(secret_name.clone()),
+                    name: Some(metrics_name.clone()),
                     namespace: Some(ns.to_owned()),
                     owner_references: Some(oref.to_vec()),
+                    labels: Some(labels.clone()),
                     ..ObjectMeta::default()
                 },
-                string_data: Some({
-                    let mut map = BTreeMap::new();
-                    map.insert("credentials.json".to_string(), creds_json.clone());
-                    map
+                spec: Some(ServiceSpec {
+                    selector: Some(labels.clone()),
+                    ports: Some(vec![ServicePort {
+                        name: Some("metrics".to_string()),
+                        port: 2000,
+                        target_port: Some(IntOrString::Int(2000)),
+                        protocol: Some("TCP".to_string()),
+                        ..ServicePort::default()
+                    }]),
+                    ..ServiceSpec::default()
                 }),
-                ..Default::default()
+                ..Service::default()
             };
 
-            secret_api
-            .patch(
-                &secret.name_any(),
-                &PatchParams::apply(OPERATOR_MANAGER),
-                &Patch::Apply(&secret),
-            )
-            .await?;
+            service_api
+                .patch(
+                    &service.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Apply(&service),
+                )
+                .await?;
 
-            (secret_name, Some("credentials.json".to_string()))
-        };
+            if service_monitor_available(ctx.clone()).await? {
+                apply_service_monitor(
+                    ctx.clone(),
+                    &ns,
+                    &metrics_name,
+                    "metrics",
+                    &labels,
+                    oref.to_vec(),
+                )
+                .await?;
+            } else {
+                warn!("ServiceMonitor CRD is not installed; skipping service monitor");
+            }
+        } else if service_api.get_opt(&metrics_name).await?.is_some() {
+            // the metrics service goes last, as long as it exists a service
+            // monitor may be left over, so the crd lookup is skipped for
+            // tunnels that never had one
+            if service_monitor_available(ctx.clone()).await? {
+                let sm_api: Api<ServiceMonitor> = Api::namespaced(client.clone(), &ns);
+                delete_if_exists(&sm_api, &metrics_name).await?;
+            }
 
-        let config_name = format!("cloudflared-{tunnel_name}-config");
-        let config = cm_api
-            .get_opt(&config_name)
-            .await?
-            .and_then(|cm| cm.data)
-            .and_then(|data| data.get("config.yaml").cloned())
-            .map(|config| serde_yaml::from_str(&config).unwrap())
-            .unwrap_or_else(|| TunnelConfig {
-                tunnel: creds.tunnel_id.clone(),
-                credentials_file: "/credentials/credentials.json".to_string(),
-                ingress: vec![TunnelIngress {
-                    service: "http_status:404".to_string(),
-                    ..TunnelIngress::default()
-                }],
-                ..TunnelConfig::default()
-            });
+            delete_if_exists(&service_api, &metrics_name).await?;
+        }
 
-        let config_yaml = serde_yaml::to_string(&config).unwrap();
-        let config_hash = sha256::digest(&config_yaml);
+        Ok(config_hash)
+    }
 
-        let config_map = ConfigMap {
+    // lets users run cloudflared by hand against the same tunnel, the token
+    // changes along with the tunnel secret
+    async fn apply_token_secret(
+        &self,
+        ctx: Arc<Context>,
+        cf_cli: &dyn CloudflareApi,
+        tunnel_id: &str,
+    ) -> Result<Option<SecretRef>, Error> {
+        if self.spec.cloudflare.secret_ref.mounted_file().is_some() {
+            return Ok(None);
+        }
+
+        let ns = ctx.operator_namespace.clone();
+        let secret_api: Api<Secret> = Api::namespaced(ctx.kube_cli.clone(), &ns);
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+        let token = cf_cli.get_tunnel_token(tunnel_id).await?;
+
+        let secret = Secret {
             metadata: ObjectMeta {
-                name: Some(config_name.to_string()),
+                name: Some(token_secret_name(self.resource_prefix(), &tunnel_name)),
                 namespace: Some(ns.to_owned()),
-                owner_references: Some(oref.to_vec()),
+                owner_references: Some(self.owner_references().to_vec()),
                 ..ObjectMeta::default()
             },
-            data: Some({
-                let mut map = BTreeMap::new();
-                map.insert("config.yaml".to_string(), config_yaml);
-                map
-            }),
-            ..ConfigMap::default()
+            string_data: Some(BTreeMap::from([("token".to_string(), token)])),
+            ..Secret::default()
         };
 
-        cm_api
+        secret_api
             .patch(
-                &config_map.name_any(),
+                &secret.name_any(),
                 &PatchParams::apply(OPERATOR_MANAGER),
-                &Patch::Apply(&config_map),
+                &Patch::Apply(&secret),
             )
             .await?;
 
-        let deployment = Deployment {
-            metadata: ObjectMeta {
-                name: Some("cloudflared".to_string()),
-                namespace: Some(ns.to_owned()),
-                owner_references: Some(oref.to_vec()),
-                labels: Some(labels.clone()),
-                ..ObjectMeta::default()
-            },
-            spec: Some(DeploymentSpec {
-                selector: LabelSelector {
-                    match_labels: Some(labels.clone()),
-                    ..LabelSelector::default()
-                },
-                template: PodTemplateSpec {
-                    metadata: Some(ObjectMeta {
-                        labels: Some(labels.clone()),
-                        annotations: Some({
-                            let mut map = BTreeMap::new();
-                            map.insert(ANNOTATION_CONFIG_HASH.to_string(), config_hash);
-                            map
-                        }),
-                        ..ObjectMeta::default()
-                    }),
-                    spec: Some(PodSpec {
-                        volumes: Some(vec![
-                            Volume {
-                                name: "config".to_string(),
-                                config_map: Some(ConfigMapVolumeSource {
-                                    name: config_name.to_string(),
-                                    ..ConfigMapVolumeSource::default()
-                                }),
-                                ..Volume::default()
-                            },
-                            Volume {
-                                name: "credentials".to_string(),
-                                secret: Some(SecretVolumeSource {
-                                    secret_name: Some(secret_name),
-                                    ..SecretVolumeSource::default()
-                                }),
-                                ..Volume::default()
-                            },
-                        ]),
-                        containers: vec![Container {
-                            name: "cloudflared".to_string(),
-                            image: Some("cloudflare/cloudflared:2024.8.2".to_string()),
-                            args: Some(vec![
-                                "tunnel".to_string(),
-                                "--no-autoupdate".to_string(),
-                                "--metrics".to_string(),
-                                "0.0.0.0:2000".to_string(),
-                                "--config".to_string(),
-                                "/config/config.yaml".to_string(),
-                                "run".to_string(),
-                                config.tunnel.clone(),
-                            ]),
-                            volume_mounts: Some(vec![
-                                VolumeMount {
-                                    name: "config".to_string(),
-                                    mount_path: "/config".to_string(),
-                                    ..VolumeMount::default()
-                                },
-                                VolumeMount {
-                                    name: "credentials".to_string(),
-                                    mount_path: "/credentials/credentials.json".to_string(),
-                                    sub_path: secret_key,
-                                    ..VolumeMount::default()
-                                },
-                            ]),
-                            liveness_probe: Some(Probe {
-                                http_get: Some(HTTPGetAction {
-                                    path: Some("/ready".to_string()),
-                                    port: IntOrString::Int(2000),
-                                    ..HTTPGetAction::default()
-                                }),
-                                failure_threshold: Some(1),
-                                initial_delay_seconds: Some(10),
-                                period_seconds: Some(10),
-                                ..Probe::default()
-                            }),
-                            ..Container::default()
-                        }],
-                        ..PodSpec::default()
-                    }),
-                    ..PodTemplateSpec::default()
-                },
-                ..DeploymentSpec::default()
-            }),
-            ..Deployment::default()
-        };
+        Ok(Some(SecretRef {
+            name: secret.name_any(),
+            key: "token".to_string(),
+            namespace: None,
+        }))
+    }
 
-        deploy_api
-            .patch(
-                &deployment.name_any(),
-                &PatchParams::apply(OPERATOR_MANAGER),
-                &Patch::Apply(&deployment),
-            )
+    // the tunnel is reachable as <tunnel id>.cfargotunnel.com, so its pool
+    // has a single origin; the load balancers are created per ingress host
+    async fn load_balancer_pool(
+        &self,
+        cf_cli: &dyn CloudflareApi,
+        tunnel_id: &str,
+    ) -> Result<String, Error> {
+        // only the pool the operator created is used, one of the same name may
+        // belong to someone else
+        if let Some(pool_id) = self
+            .status
+            .as_ref()
+            .and_then(|status| status.load_balancer_pool_id.as_ref())
+        {
+            if cf_cli.get_load_balancer_pool(pool_id).await?.is_some() {
+                return Ok(pool_id.clone());
+            }
+        }
+
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+        info!("creating load balancer pool {tunnel_name}");
+        let pool = cf_cli
+            .create_load_balancer_pool(&tunnel_name, &format!("{tunnel_id}.cfargotunnel.com"))
             .await?;
 
+        Ok(pool.id)
+    }
+
+    // the pool can only be deleted once no load balancer uses it anymore
+    async fn remove_load_balancers(
+        &self,
+        cf_cli: &dyn CloudflareApi,
+        pool_id: &str,
+    ) -> Result<(), Error> {
+        if let Some(zone_id) = self.known_zone_id() {
+            for load_balancer in cf_cli.list_load_balancers(&zone_id).await? {
+                if load_balancer.default_pools.iter().any(|id| id == pool_id) {
+                    info!("deleting load balancer {}", load_balancer.name);
+                    cf_cli
+                        .delete_load_balancer(&zone_id, &load_balancer.id)
+                        .await?;
+                }
+            }
+        }
+
+        cf_cli.delete_load_balancer_pool(pool_id).await
+    }
+
+    // the hash of the applied settings, None while they can't be applied yet
+    async fn apply_zone_settings(
+        &self,
+        ctx: Arc<Context>,
+        cf_cli: &dyn CloudflareApi,
+    ) -> Result<Option<String>, Error> {
+        let settings = self.zone_settings();
+        if settings.is_empty() {
+            return Ok(Some(self.zone_settings_hash()));
+        }
+
+        let Some(zone_id) = self.known_zone_id() else {
+            warn!("zone of the tunnel is not known yet, skipping zone settings");
+            return Ok(None);
+        };
+
+        for (name, value) in settings {
+            if !ZONE_SETTINGS.contains(&name.as_str()) {
+                ctx.recorder
+                    .publish(
+                        &Event {
+                            type_: EventType::Warning,
+                            reason: "UnsupportedZoneSetting".to_string(),
+                            note: Some(format!(
+                                "zone setting {name} is not supported, expected one of {}",
+                                ZONE_SETTINGS.join(", ")
+                            )),
+                            action: "Reconciling".to_string(),
+                            secondary: None,
+                        },
+                        &self.object_ref(&()),
+                    )
+                    .await?;
+                continue;
+            }
+
+            info!("setting zone setting {name} to {value}");
+            cf_cli
+                .set_zone_setting(&zone_id, &name, serde_json::Value::String(value))
+                .await?;
+        }
+
+        Ok(Some(self.zone_settings_hash()))
+    }
+
+    // Removes the resources deployed under a previous resource prefix.
+    async fn remove_resources(&self, ctx: Arc<Context>, prefix: &str) -> Result<(), Error> {
+        let ns = ctx.operator_namespace.clone();
+        let client = ctx.kube_cli.clone();
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+
+        info!("removing resources with previous prefix {prefix}");
+
+        let deploy_api: Api<Deployment> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&deploy_api, &deployment_name(prefix, &tunnel_name)).await?;
+
+        let ds_api: Api<DaemonSet> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&ds_api, &deployment_name(prefix, &tunnel_name)).await?;
+
+        let sa_api: Api<ServiceAccount> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&sa_api, &deployment_name(prefix, &tunnel_name)).await?;
+
+        let cm_api: Api<ConfigMap> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&cm_api, &config_map_name(prefix, &tunnel_name)).await?;
+
+        let pdb_api: Api<PodDisruptionBudget> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&pdb_api, &format!("{prefix}-{tunnel_name}-pdb")).await?;
+
+        let hpa_api: Api<HorizontalPodAutoscaler> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&hpa_api, &format!("{prefix}-{tunnel_name}-hpa")).await?;
+
+        let netpol_api: Api<NetworkPolicy> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&netpol_api, &format!("{prefix}-{tunnel_name}-netpol")).await?;
+
+        let metrics_name = format!("{prefix}-{tunnel_name}-metrics");
+        let service_api: Api<Service> = Api::namespaced(client.clone(), &ns);
+        delete_if_exists(&service_api, &metrics_name).await?;
+        if service_monitor_available(ctx.clone()).await? {
+            let sm_api: Api<ServiceMonitor> = Api::namespaced(client.clone(), &ns);
+            delete_if_exists(&sm_api, &metrics_name).await?;
+        }
+
+        let secret_api: Api<Secret> = Api::namespaced(client.clone(), &ns);
+        if self.manages_credentials_secret() {
+            delete_if_exists(&secret_api, &credentials_secret_name(prefix, &tunnel_name)).await?;
+        }
+        delete_if_exists(&secret_api, &token_secret_name(prefix, &tunnel_name)).await?;
+
         Ok(())
     }
 
@@ -285,33 +2001,48 @@ impl ClusterTunnel {
         &self,
         ctx: Arc<Context>,
     ) -> Result<cloudflare::Credentials, Error> {
-        let ns = std::env::var("POD_NAMESPACE").unwrap_or_else(|_| "default".to_string());
+        let ns = ctx.operator_namespace.clone();
         let kube_cli = ctx.kube_cli.clone();
 
-        let secret_api: Api<Secret> = Api::namespaced(kube_cli.clone(), &ns);
+        if let Some(path) = self.spec.cloudflare.secret_ref.mounted_file() {
+            return read_mounted_credentials(path).await;
+        }
+
+        let Some(secret_ref) = self.spec.cloudflare.secret_ref.secret_ref() else {
+            return Err(Error::InvalidSpec("no credentials configured".into()));
+        };
+
+        let ns = secret_ref.namespace_or(&ns);
+        let secret_api: Api<Secret> = Api::namespaced(kube_cli.clone(), ns);
+
+        let name = format!("{ns}/{}", secret_ref.name);
 
-        let secret_ref = match &self.spec.cloudflare.secret_ref {
-            CloudflareSecretRef::ApiKey(secret_ref) => secret_ref,
-            CloudflareSecretRef::ApiToken(secret_ref) => secret_ref,
+        let Some(secret) = secret_api.get_opt(&secret_ref.name).await? else {
+            if self.spec.cloudflare.external_secret_ref.is_some() {
+                info!("waiting for the external secret to sync");
+            }
+            return Err(Error::SecretNotFound { name });
+        };
+        let Some(value) = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(&secret_ref.key))
+        else {
+            return Err(Error::SecretKeyNotFound {
+                name,
+                key: secret_ref.key.clone(),
+            });
         };
 
-        let secret = secret_api.get(&secret_ref.name).await?;
-        let data = secret.data.ok_or_else(|| anyhow!("no data"))?;
-        let value = data.get(&secret_ref.key).ok_or_else(|| {
-            anyhow!(
-                "key {} not found or invalid in {}",
-                secret_ref.key,
-                secret_ref.name
-            )
+        let value = String::from_utf8(value.clone().0).map_err(|_| Error::SecretValueInvalid {
+            name,
+            key: secret_ref.key.clone(),
         })?;
 
-        let value = String::from_utf8(value.clone().0)
-            .map_err(|err| anyhow!("value not a string: {err:?}"))?;
-
         let creds = match &self.spec.cloudflare.secret_ref {
             CloudflareSecretRef::ApiKey(_) => {
                 let Some(email) = &self.spec.cloudflare.email else {
-                    return Err(anyhow!("api key requires email").into());
+                    return Err(Error::InvalidSpec("api key requires email".into()));
                 };
 
                 cloudflare::Credentials::UserAuthKey {
@@ -319,79 +2050,475 @@ impl ClusterTunnel {
                     key: value,
                 }
             }
-            CloudflareSecretRef::ApiToken(_) => {
+            CloudflareSecretRef::ApiToken(_)
+            | CloudflareSecretRef::ZoneScopedToken(_)
+            | CloudflareSecretRef::MountedFile { .. } => {
                 cloudflare::Credentials::UserAuthToken { token: value }
             }
+            CloudflareSecretRef::TunnelToken(_) => {
+                return Err(Error::InvalidSpec(
+                    "a tunnel token can't be used with the cloudflare api".into(),
+                ))
+            }
         };
 
         Ok(creds)
     }
 
     pub async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action, Error> {
-        let credentials = self.get_credentials(ctx.clone()).await?;
+        let prefix = self.resource_prefix();
+        if prefix.len() > 20
+            || !prefix
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(Error::InvalidSpec(format!(
+                "invalid resource prefix {prefix}, expected ^[a-z0-9-]{{0,20}}$"
+            )));
+        }
 
-        let cf_cli = cloudflare::Client::new(self.spec.cloudflare.account_id.clone(), credentials)?;
+        if let Some(policy) = self.spec.image_pull_policy.as_deref() {
+            if !matches!(policy, "Always" | "IfNotPresent" | "Never") {
+                return Err(Error::InvalidSpec(format!(
+                    "invalid image pull policy {policy}, expected Always, IfNotPresent or Never"
+                )));
+            }
+        }
 
-        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
-        let tunnel_credentials = if let Some(tunnel_id) = cf_cli.find_tunnel(&tunnel_name).await? {
-            info!("tunnel found: {tunnel_id}");
+        if let Some(protocol) = self.spec.transport_protocol.as_deref() {
+            if !matches!(protocol, "auto" | "quic" | "http2") {
+                return Err(Error::InvalidSpec(format!(
+                    "invalid transport protocol {protocol}, expected auto, quic or http2"
+                )));
+            }
+        }
+
+        if let Some(autoscaling) = self.spec.autoscaling.as_ref() {
+            if self.deployment_kind() != DeploymentKind::Deployment {
+                return Err(Error::InvalidSpec(
+                    "autoscaling requires deploymentKind Deployment".into(),
+                ));
+            }
+            if autoscaling.min_replicas < 1 || autoscaling.max_replicas < autoscaling.min_replicas {
+                return Err(Error::InvalidSpec(
+                    "invalid autoscaling, expected 1 <= minReplicas <= maxReplicas".into(),
+                ));
+            }
+        }
+
+        if let Some(path_type) = self.spec.implementation_specific_path_type.as_deref() {
+            if !matches!(path_type, "Prefix" | "Exact") {
+                return Err(Error::InvalidSpec(format!(
+                    "invalid implementation specific path type {path_type}, expected Prefix or Exact"
+                )));
+            }
+        }
+
+        // the operator can't read credentials out of a volume, so it could neither
+        // match a found tunnel nor hand the secret of a new one to the pods
+        if self.credentials_volume().is_some()
+            && self.spec.tunnel_id.is_none()
+            && !self.uses_tunnel_token()
+        {
+            return Err(Error::InvalidSpec(
+                "credentials mounted from a volume require spec.tunnelId or a tunnel token".into(),
+            ));
+        }
+
+        // a tunnel token only lets cloudflared connect, the api doesn't accept
+        // it, so the tunnel's routes and zone are managed in the dashboard
+        if self.uses_tunnel_token() {
+            let api_features = [
+                (
+                    self.config_mode() == ConfigMode::Remote,
+                    "configMode Remote",
+                ),
+                (self.spec.private_networks.is_some(), "privateNetworks"),
+                (
+                    self.spec.create_load_balancer == Some(true),
+                    "createLoadBalancer",
+                ),
+                (!self.zone_settings().is_empty(), "zone settings"),
+            ];
+            if let Some((_, feature)) = api_features.iter().find(|(used, _)| *used) {
+                return Err(Error::InvalidSpec(format!(
+                    "{feature} requires cloudflare api credentials instead of a tunnel token"
+                )));
+            }
+        }
+
+        // pushing a configuration hands existing tunnels over to the api, but
+        // cloudflared keeps preferring it over the local file afterwards
+        let was_remote = self
+            .status
+            .as_ref()
+            .and_then(|status| status.config_mode)
+            .is_some_and(|mode| mode == ConfigMode::Remote);
+        if was_remote && self.config_mode() == ConfigMode::Local {
+            return Err(Error::InvalidSpec(
+                "remotely managed tunnels can't switch back to configMode Local".into(),
+            ));
+        }
+
+        let policy_generation = match self.spec.default_policy_ref.as_ref() {
+            Some(policy_ref) => get_policy(ctx.clone(), &ctx.operator_namespace, policy_ref)
+                .await?
+                .and_then(|policy| policy.metadata.generation),
+            None => None,
+        };
 
-            let client = ctx.kube_cli.clone();
-            let ns = std::env::var("POD_NAMESPACE").unwrap_or_else(|_| "default".to_string());
-            let secret_api: Api<Secret> = Api::namespaced(client.clone(), &ns);
+        if let Some(current_hash) = self.current_config_hash(ctx.clone()).await? {
+            if !self.is_reconciliation_needed(
+                &current_hash,
+                policy_generation,
+                ctx.requeue_interval,
+            ) {
+                return Ok(Action::requeue(ctx.requeue_interval));
+            }
+        }
 
+        if let Some(external_secret_ref) = self.spec.cloudflare.external_secret_ref.as_ref() {
+            let ns = ctx.operator_namespace.clone();
             let secret_ref = self
                 .spec
-                .tunnel_secret_ref
-                .clone()
-                .unwrap_or_else(|| SecretRef {
-                    name: format!("cloudflared-{tunnel_name}-credentials"),
-                    key: "credentials.json".to_string(),
-                });
+                .cloudflare
+                .secret_ref
+                .secret_ref()
+                .ok_or_else(|| {
+                    Error::InvalidSpec("external secrets can't be synced to a mounted file".into())
+                })?;
+            apply_external_secret(
+                ctx.clone(),
+                secret_ref.namespace_or(&ns),
+                secret_ref,
+                external_secret_ref,
+                self.controller_owner_ref(&()),
+            )
+            .await?;
+        }
+
+        let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
+        let (cf_cli, tunnel_credentials) =
+            if let Some(token_ref) = self.spec.cloudflare.secret_ref.tunnel_token() {
+                let ns = ctx.operator_namespace.clone();
+                let token = get_secret_value(ctx.clone(), &ns, token_ref).await?;
+
+                (None, TunnelCredentials::from_token(&token)?)
+            } else {
+                let credentials = self.get_credentials(ctx.clone()).await?;
+                let cf_cli =
+                    ctx.cloudflare_client(self.spec.cloudflare.account_id.clone(), credentials)?;
+
+                if let CloudflareSecretRef::ZoneScopedToken(_) = &self.spec.cloudflare.secret_ref {
+                    match self.known_zone_id() {
+                        Some(zone_id) => {
+                            if !cf_cli.has_zone_access(&zone_id).await? {
+                                return Err(Error::InvalidSpec(format!(
+                                    "zone-scoped token does not have access to zone {zone_id}"
+                                )));
+                            }
+                        }
+                        // zones are resolved by listing the ones the token can see, so
+                        // there is nothing to check before one is
+                        None => info!("zone-scoped token is checked once the zone is resolved"),
+                    }
+                }
+
+                let tunnel_credentials = self
+                    .api_tunnel_credentials(ctx.clone(), cf_cli.as_ref(), &tunnel_name)
+                    .await?;
+                (Some(cf_cli), tunnel_credentials)
+            };
+
+        // a tunnel that was only pretended to be created has no credentials
+        // the pods could connect with
+        if is_dry_run_id(&tunnel_credentials.tunnel_id) {
+            info!(
+                "dry run: skipping the deployment of tunnel {}",
+                tunnel_credentials.tunnel_id
+            );
+            return Ok(Action::requeue(ctx.requeue_interval));
+        }
 
-            let secret = secret_api.get(&secret_ref.name).await?;
-            let data = secret.data.ok_or_else(|| anyhow!("no data"))?;
-            let creds = data
-                .get(&secret_ref.key)
-                .ok_or_else(|| anyhow!("no credentials"))?;
-            serde_json::from_slice(&creds.0)
-                .map_err(|err| anyhow!("failed to deserialize credentials: {err:?}"))?
+        let mut rotated_at = None;
+        let tunnel_credentials = if self.secret_rotation_requested() {
+            let Some(cf_cli) = cf_cli
+                .as_deref()
+                .filter(|_| self.manages_credentials_secret())
+            else {
+                return Err(Error::InvalidSpec(
+                    "secret rotation requires tunnel credentials managed by the operator".into(),
+                ));
+            };
+
+            info!("rotating secret of tunnel {}", tunnel_credentials.tunnel_id);
+            let rotated = cf_cli
+                .rotate_tunnel_secret(&tunnel_credentials.tunnel_id)
+                .await?;
+            // the request stays pending until the rotation really happens
+            if !ctx.cloudflare_dry_run {
+                rotated_at = Some(Time(Utc::now()));
+            }
+            rotated
         } else {
-            info!("tunnel not found, creating...");
+            tunnel_credentials
+        };
+
+        Span::current().record(
+            "cloudflare.tunnel_id",
+            tunnel_credentials.tunnel_id.as_str(),
+        );
+
+        // the secret is written before the workload is rolled, so new pods
+        // always start with the rotated credentials
+        let config_hash = self
+            .deploy_cloudflared(ctx.clone(), cf_cli.as_deref(), &tunnel_credentials)
+            .await?;
+
+        // without a client the tunnel runs on a token, the features needing
+        // the api were rejected above
+        let previous_pool_id = self
+            .status
+            .as_ref()
+            .and_then(|status| status.load_balancer_pool_id.clone());
+        let private_networks = match cf_cli.as_deref() {
+            Some(cf_cli) => {
+                self.sync_tunnel_routes(cf_cli, &tunnel_credentials.tunnel_id)
+                    .await?
+            }
+            None => self.routed_private_networks().cloned(),
+        };
+        let (token_secret_ref, load_balancer_pool_id, zone_settings_hash) = match cf_cli.as_deref()
+        {
+            Some(cf_cli) => {
+                let token_secret_ref = self
+                    .apply_token_secret(ctx.clone(), cf_cli, &tunnel_credentials.tunnel_id)
+                    .await?;
+
+                let load_balancer_pool_id = if self.spec.create_load_balancer == Some(true) {
+                    Some(
+                        self.load_balancer_pool(cf_cli, &tunnel_credentials.tunnel_id)
+                            .await?,
+                    )
+                } else {
+                    // the pool is removed before its id is dropped from the status
+                    if let Some(pool_id) = previous_pool_id.as_ref() {
+                        info!("deleting load balancer pool {pool_id}");
+                        self.remove_load_balancers(cf_cli, pool_id).await?;
+                    }
+                    None
+                }
+                .filter(|pool_id| !is_dry_run_id(pool_id));
+
+                let zone_settings_hash = self.apply_zone_settings(ctx.clone(), cf_cli).await?;
+
+                (token_secret_ref, load_balancer_pool_id, zone_settings_hash)
+            }
+            // a pool left from before the switch to a token is kept for when
+            // api credentials are configured again
+            None => (
+                self.spec.cloudflare.secret_ref.tunnel_token().cloned(),
+                previous_pool_id,
+                Some(self.zone_settings_hash()),
+            ),
+        };
+
+        if let Some(previous) = self.previous_resource_prefix() {
+            if previous != self.resource_prefix() {
+                self.remove_resources(ctx.clone(), previous).await?;
+            }
+        }
 
-            let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
-            cf_cli.create_tunnel(&tunnel_name).await?
+        // the tunnel itself is reconciled at this point, failing to read its
+        // connections only leaves its health unknown
+        let connections = match cf_cli.as_deref() {
+            Some(cf_cli) => Some(
+                cf_cli
+                    .get_tunnel_connections(&tunnel_credentials.tunnel_id)
+                    .await,
+            ),
+            None => None,
+        };
+        let connection_count = match connections {
+            Some(Ok(connections)) => Some(
+                connections
+                    .iter()
+                    .flat_map(|connection| connection.conns.iter())
+                    .filter(|conn| !conn.is_pending_reconnect)
+                    .count() as u32,
+            ),
+            Some(Err(err)) => {
+                warn!("failed to get tunnel connections: {err}");
+                None
+            }
+            None => None,
+        };
+        let degraded = match connection_count {
+            Some(0) if self.workload_settled(ctx.clone()).await? => condition(
+                "Degraded",
+                true,
+                "NoConnections",
+                "cloudflared is running but the tunnel has no connections",
+                self.metadata.generation,
+            ),
+            Some(_) => condition("Degraded", false, "Connected", "", self.metadata.generation),
+            None => Condition {
+                status: "Unknown".to_string(),
+                ..condition(
+                    "Degraded",
+                    false,
+                    "ConnectionsUnknown",
+                    "the tunnel connections could not be read",
+                    self.metadata.generation,
+                )
+            },
         };
+        let conditions = merge_condition(&self.ready_conditions(true, "Reconciled", ""), degraded);
 
-        self.deploy_cloudflared(ctx.clone(), &tunnel_credentials)
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+        let mut status = serde_json::json!({
+            "status": ClusterTunnelStatus {
+                tunnel_id: Some(tunnel_credentials.tunnel_id.clone()),
+                observed_generation: Some(self.metadata.generation.unwrap_or(0)),
+                config_hash: Some(config_hash),
+                resource_prefix: Some(self.resource_prefix().to_string()),
+                deployment_kind: Some(self.deployment_kind()),
+                config_mode: Some(self.config_mode()),
+                connection_count,
+                last_secret_rotation_time: rotated_at.clone(),
+                tunnel_token_secret_ref: token_secret_ref,
+                load_balancer_pool_id: load_balancer_pool_id.clone(),
+                zone_settings_hash,
+                private_networks: private_networks.clone(),
+                last_sync_time: Some(Time(Utc::now())),
+                conditions: Some(conditions),
+                ..ClusterTunnelStatus::default()
+            }
+        });
+        // the merge patch leaves out unset fields, the id of a deleted pool, the
+        // generation of a dropped policy and unrouted networks have to be
+        // cleared explicitly
+        status["status"]["loadBalancerPoolId"] = load_balancer_pool_id.into();
+        status["status"]["privateNetworks"] = private_networks.into();
+        status["status"]["policyGeneration"] = policy_generation.into();
+        ct_api
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Merge(&status),
+            )
             .await?;
 
-        Ok(Action::requeue(Duration::from_secs(3600)))
+        if rotated_at.is_some() {
+            let patch = serde_json::json!({
+                "metadata": {
+                    "annotations": { ANNOTATION_ROTATE_SECRET: null }
+                }
+            });
+            ct_api
+                .patch(
+                    &self.name_any(),
+                    &PatchParams::default(),
+                    &Patch::Merge(&patch),
+                )
+                .await?;
+        }
+
+        Ok(Action::requeue(ctx.requeue_interval))
     }
 
     pub async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        if self.spec.deletion_protection.unwrap_or(false) {
+            ctx.recorder
+                .publish(
+                    &Event {
+                        type_: EventType::Warning,
+                        reason: "DeletionProtected".to_string(),
+                        note: Some(
+                            "deletion protection is enabled; manual intervention is required to delete this tunnel"
+                                .to_string(),
+                        ),
+                        action: "Deleting".to_string(),
+                        secondary: None,
+                    },
+                    &self.object_ref(&()),
+                )
+                .await?;
+
+            return Err(Error::DeletionProtected);
+        }
+
+        if self.uses_tunnel_token() {
+            info!("tunnel is managed by token, skipping deletion");
+            return Ok(Action::requeue(ctx.requeue_interval));
+        }
+
         let credentials = self.get_credentials(ctx.clone()).await?;
 
-        let cf_cli = cloudflare::Client::new(self.spec.cloudflare.account_id.clone(), credentials)?;
+        let cf_cli = ctx.cloudflare_client(self.spec.cloudflare.account_id.clone(), credentials)?;
 
         let tunnel_name = self.spec.name.clone().unwrap_or_else(|| self.name_any());
-        let Some(tunnel_id) = cf_cli.find_tunnel(&tunnel_name).await? else {
-            return Ok(Action::requeue(Duration::from_secs(3600)));
+        let tunnel_id = match self.spec.tunnel_id.clone() {
+            Some(tunnel_id) => tunnel_id,
+            None => match cf_cli.find_tunnel(&tunnel_name).await? {
+                Some(tunnel_id) => tunnel_id,
+                None => return Ok(Action::requeue(ctx.requeue_interval)),
+            },
         };
 
+        if self.spec.private_networks.is_some() || self.routed_private_networks().is_some() {
+            for route in cf_cli.list_tunnel_routes(&tunnel_id).await? {
+                if route.tunnel_id == tunnel_id && route.is_managed() {
+                    cf_cli.delete_tunnel_route(&route.id).await?;
+                }
+            }
+        }
+
+        if let Some(pool_id) = self
+            .status
+            .as_ref()
+            .and_then(|status| status.load_balancer_pool_id.as_ref())
+        {
+            self.remove_load_balancers(&cf_cli, pool_id).await?;
+        }
+
         cf_cli.delete_tunnel(&tunnel_id).await?;
 
-        Ok(Action::requeue(Duration::from_secs(3600)))
+        Ok(Action::requeue(ctx.requeue_interval))
     }
 }
 
+#[instrument(skip(obj, ctx), fields(
+    tunnel_name = %obj.spec.name.clone().unwrap_or_else(|| obj.name_any()),
+    namespace = %ctx.operator_namespace,
+    resource_version = ?obj.resource_version(),
+    k8s.resource.kind = "ClusterTunnel",
+    k8s.resource.name = %obj.name_any(),
+    cloudflare.account_id = %obj.spec.cloudflare.account_id,
+    cloudflare.tunnel_id = field::Empty,
+))]
 pub async fn reconcile(obj: Arc<ClusterTunnel>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let _reconcile = ctx.track_reconcile();
+
+    if reconcile_paused(obj.annotations()) {
+        info!("reconciliation paused by annotation; skipping");
+        return Ok(Action::await_change());
+    }
+
     let client = ctx.kube_cli.clone();
 
     let ct_api: Api<ClusterTunnel> = Api::all(client);
     finalizer(&ct_api, CLUSTER_TUNNEL_FINALIZER, obj, |event| async {
         match event {
-            finalizer::Event::Apply(obj) => obj.reconcile(ctx.clone()).await,
+            finalizer::Event::Apply(obj) => match obj.reconcile(ctx.clone()).await {
+                Ok(action) => Ok(action),
+                Err(err) => {
+                    if let Err(status_err) = obj.set_not_ready(ctx.clone(), &err).await {
+                        warn!("failed to update cluster tunnel status: {status_err}");
+                    }
+                    Err(err)
+                }
+            },
             finalizer::Event::Cleanup(obj) => obj.cleanup(ctx.clone()).await,
         }
     })
@@ -402,19 +2529,178 @@ pub async fn reconcile(obj: Arc<ClusterTunnel>, ctx: Arc<Context>) -> Result<Act
 pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
     let client = ctx.kube_cli.clone();
 
-    let cfg = watcher::Config::default();
+    let cfg = ctx.cluster_watcher_config();
     let ct_api: Api<ClusterTunnel> = Api::all(client.clone());
 
-    Controller::new(ct_api, cfg)
+    // credentials are usually read from the operator namespace, so rotating
+    // them there re-enqueues every tunnel using them; secrets in other
+    // namespaces are picked up on the next requeue
+    let ns = ctx.operator_namespace.clone();
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), &ns);
+    // default policies are looked up in the operator namespace too
+    let policy_api: Api<TunnelPolicy> = Api::namespaced(client.clone(), &ns);
+    // ingresses add backends by editing the config map, the network policy
+    // has to follow them
+    let cm_api: Api<ConfigMap> = Api::namespaced(client.clone(), &ns);
+
+    let controller = Controller::new(ct_api, cfg);
+    let store = controller.store();
+
+    let tunnels = store.clone();
+    let policy_tunnels = store.clone();
+    let config_tunnels = store.clone();
+    controller
+        .watches(policy_api, watcher::Config::default(), move |policy| {
+            let policy_name = policy.name_any();
+            policy_tunnels
+                .state()
+                .into_iter()
+                .filter(move |ct| ct.spec.default_policy_ref.as_ref() == Some(&policy_name))
+                .map(|ct| ObjectRef::from_obj(ct.as_ref()))
+        })
+        .watches(cm_api, watcher::Config::default(), move |cm| {
+            let owners: Vec<String> = cm
+                .owner_references()
+                .iter()
+                .map(|owner| owner.uid.clone())
+                .collect();
+            config_tunnels
+                .state()
+                .into_iter()
+                .filter(move |ct| {
+                    ct.spec.create_network_policy.unwrap_or(false)
+                        && ct.uid().is_some_and(|uid| owners.contains(&uid))
+                })
+                .map(|ct| ObjectRef::from_obj(ct.as_ref()))
+        })
+        .watches(secret_api, watcher::Config::default(), move |secret| {
+            let secret_name = secret.name_any();
+            let secret_ns = secret.namespace().unwrap_or_default();
+            let operator_ns = ns.clone();
+            tunnels
+                .state()
+                .into_iter()
+                .filter(move |ct| ct.references_secret(&operator_ns, &secret_ns, &secret_name))
+                .map(|ct| ObjectRef::from_obj(ct.as_ref()))
+        })
         .shutdown_on_signal()
-        .run(reconcile, error_policy, ctx.clone())
-        .for_each(|res| async move {
-            match res {
-                Ok(o) => info!("reconciled cluster tunnel {o:?}"),
-                Err(e) => warn!("reconcile cluster tunnel failed: {e:?}"),
+        .run(
+            |obj, ctx| with_correlation_id(reconcile(obj, ctx)),
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            let store = store.clone();
+            async move {
+                ctx.metrics
+                    .tunnel_active_count
+                    .set(store.state().len() as i64);
+
+                match res {
+                    Ok(o) => {
+                        ctx.metrics.reconcile_success("ClusterTunnel");
+                        reset_failures(&ctx, &o.0);
+                        info!("reconciled cluster tunnel {o:?}")
+                    }
+                    Err(e) => warn!("reconcile cluster tunnel failed: {e:?}"),
+                }
             }
         })
         .await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use kube::ResourceExt;
+    use serde_json::json;
+
+    use super::{origin_service, ClusterTunnel, ClusterTunnelStatus};
+    use crate::controller::utils::ANNOTATION_ZONE_SETTING_PREFIX;
+
+    // extra is merged into a minimal spec
+    fn clustertunnel(extra: serde_json::Value) -> ClusterTunnel {
+        let mut spec = json!({
+            "cloudflare": {
+                "accountId": "account",
+                "zoneId": "zone",
+                "apiTokenSecretRef": { "name": "cloudflare-api-token", "key": "token" },
+            },
+        });
+        if let (Some(spec), Some(extra)) = (spec.as_object_mut(), extra.as_object()) {
+            spec.extend(extra.clone());
+        }
+
+        serde_json::from_value(json!({
+            "apiVersion": "cloudflare-tunnels-operator.io/v1alpha1",
+            "kind": "ClusterTunnel",
+            "metadata": { "name": "tunnel" },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn zone_setting_names_use_underscores() {
+        let mut ct = clustertunnel(json!({}));
+        let annotations = ct.annotations_mut();
+        annotations.insert(
+            format!("{ANNOTATION_ZONE_SETTING_PREFIX}always-use-https"),
+            "on".to_string(),
+        );
+        annotations.insert(
+            format!("{ANNOTATION_ZONE_SETTING_PREFIX}ssl"),
+            "strict".to_string(),
+        );
+        annotations.insert("example.com/other".to_string(), "ignored".to_string());
+
+        let settings: Vec<_> = ct.zone_settings().into_iter().collect();
+        assert_eq!(
+            settings,
+            [
+                ("always_use_https".to_string(), "on".to_string()),
+                ("ssl".to_string(), "strict".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_services() {
+        assert_eq!(
+            origin_service("http://web.default.svc:8080/api"),
+            Some(("web", "default"))
+        );
+        assert_eq!(
+            origin_service("https://web.default.svc"),
+            Some(("web", "default"))
+        );
+        assert_eq!(origin_service("https://example.com:8443"), None);
+        assert_eq!(origin_service("http_status:404"), None);
+    }
+
+    #[test]
+    fn known_zone_of_explicit_zone() {
+        let ct = clustertunnel(json!({}));
+        assert_eq!(ct.known_zone_id().as_deref(), Some("zone"));
+    }
+
+    #[test]
+    fn known_zone_of_auto_zone() {
+        let mut ct = clustertunnel(json!({
+            "cloudflare": {
+                "accountId": "account",
+                "zoneId": "auto",
+                "zoneScopedTokenSecretRef": { "name": "cloudflare-api-token", "key": "token" },
+            },
+        }));
+        assert_eq!(ct.known_zone_id(), None);
+
+        ct.status = Some(ClusterTunnelStatus {
+            resolved_zone_id: Some("resolved".to_string()),
+            ..ClusterTunnelStatus::default()
+        });
+        assert_eq!(ct.known_zone_id().as_deref(), Some("resolved"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, CustomResource,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{context::Context, error::Error};
+
+use super::{
+    clustertunnel::{ExternalSecretRef, SecretRef},
+    OPERATOR_MANAGER,
+};
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "ExternalSecret",
+    group = "external-secrets.io",
+    version = "v1beta1",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalSecretSpec {
+    pub refresh_interval: Option<String>,
+    pub secret_store_ref: SecretStoreRef,
+    pub target: ExternalSecretTarget,
+    pub data: Vec<ExternalSecretData>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretStoreRef {
+    pub name: String,
+    pub kind: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalSecretTarget {
+    pub name: String,
+    pub creation_policy: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalSecretData {
+    pub secret_key: String,
+    pub remote_ref: RemoteRef,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteRef {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+// The ExternalSecret syncs into the secret named by secret_ref, so the
+// regular secret lookup keeps working once ESO has written it.
+pub async fn apply_external_secret(
+    ctx: Arc<Context>,
+    ns: &str,
+    secret_ref: &SecretRef,
+    external_secret_ref: &ExternalSecretRef,
+    owner_reference: Option<OwnerReference>,
+) -> Result<(), Error> {
+    let api: Api<ExternalSecret> = Api::namespaced(ctx.kube_cli.clone(), ns);
+
+    let mut external_secret = ExternalSecret::new(
+        &secret_ref.name,
+        ExternalSecretSpec {
+            refresh_interval: Some("1h".to_string()),
+            secret_store_ref: SecretStoreRef {
+                name: external_secret_ref.provider.clone(),
+                kind: "ClusterSecretStore".to_string(),
+            },
+            target: ExternalSecretTarget {
+                name: secret_ref.name.clone(),
+                creation_policy: "Owner".to_string(),
+            },
+            data: vec![ExternalSecretData {
+                secret_key: secret_ref.key.clone(),
+                remote_ref: RemoteRef {
+                    key: external_secret_ref.key.clone(),
+                    version: external_secret_ref.version.clone(),
+                },
+            }],
+        },
+    );
+    external_secret.metadata.namespace = Some(ns.to_string());
+    external_secret.metadata.owner_references = owner_reference.map(|oref| vec![oref]);
+
+    api.patch(
+        &secret_ref.name,
+        &PatchParams::apply(OPERATOR_MANAGER),
+        &Patch::Apply(&external_secret),
+    )
+    .await?;
+
+    Ok(())
+}
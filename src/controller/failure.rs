@@ -0,0 +1,32 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// entries that haven't failed for this long are forgotten
+const EVICTION_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Default)]
+pub struct FailureTracker(Arc<Mutex<HashMap<String, (u32, Instant)>>>);
+
+impl FailureTracker {
+    // Records a failure for `key`, returning how many consecutive failures
+    // preceded it.
+    pub fn record(&self, key: String) -> u32 {
+        let now = Instant::now();
+        let mut failures = self.0.lock().unwrap();
+        failures.retain(|_, (_, last_failure)| now.duration_since(*last_failure) < EVICTION_TTL);
+
+        let (count, last_failure) = failures.entry(key).or_insert((0, now));
+        let previous = *count;
+        *count = count.saturating_add(1);
+        *last_failure = now;
+
+        previous
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.0.lock().unwrap().remove(key);
+    }
+}
@@ -0,0 +1,938 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
+
+use futures_util::StreamExt;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    runtime::{controller::Action, finalizer, Controller},
+    Api, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    cloudflare::{
+        dns::DnsContent, validate_service_url, with_correlation_id, CloudflareApi, TunnelIngress,
+    },
+    context::Context,
+    error::Error,
+    ClusterTunnel,
+};
+
+use super::{
+    clustertunnel::DnsCleanupPolicy,
+    error_policy,
+    ingress::{build_path_regex, encode_host_label, insert_ingress, update_config},
+    reset_failures,
+    utils::*,
+    OPERATOR_MANAGER,
+};
+
+const HTTPROUTE_FINALIZER: &'static str = "httproute.cloudflare-tunnels-operator.io/finalizer";
+const GATEWAY_CLASS_NAME: &'static str = "cloudflare-tunnels";
+pub const GATEWAY_CONTROLLER: &'static str = "cloudflare-tunnels.io/gateway";
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "GatewayClass",
+    group = "gateway.networking.k8s.io",
+    version = "v1"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewayClassSpec {
+    pub controller_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "Gateway",
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewaySpec {
+    pub gateway_class_name: String,
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "ReferenceGrant",
+    group = "gateway.networking.k8s.io",
+    version = "v1beta1",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceGrantSpec {
+    pub from: Vec<ReferenceGrantFrom>,
+    pub to: Vec<ReferenceGrantTo>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceGrantFrom {
+    pub group: String,
+    pub kind: String,
+    pub namespace: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceGrantTo {
+    pub group: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl ReferenceGrant {
+    // whether HTTPRoutes in namespace may use the service of the grant's namespace
+    fn permits(&self, namespace: &str, service: &str) -> bool {
+        let from = self.spec.from.iter().any(|from| {
+            from.group == "gateway.networking.k8s.io"
+                && from.kind == "HTTPRoute"
+                && from.namespace == namespace
+        });
+        let to = self.spec.to.iter().any(|to| {
+            to.group.is_empty()
+                && to.kind == "Service"
+                && to.name.as_deref().map_or(true, |name| name == service)
+        });
+
+        from && to
+    }
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "HTTPRoute",
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    namespaced,
+    status = "HTTPRouteStatus"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteSpec {
+    #[serde(default)]
+    pub parent_refs: Vec<ParentReference>,
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<HTTPRouteRule>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentReference {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section_name: Option<String>,
+}
+
+impl ParentReference {
+    fn is_clustertunnel(&self) -> bool {
+        matches!(
+            (self.group.as_deref(), self.kind.as_deref()),
+            (
+                Some("cloudflare-tunnels-operator.io"),
+                Some("ClusterTunnel")
+            )
+        )
+    }
+
+    fn is_gateway(&self) -> bool {
+        matches!(
+            (self.group.as_deref(), self.kind.as_deref()),
+            (
+                None | Some("gateway.networking.k8s.io"),
+                None | Some("Gateway")
+            )
+        )
+    }
+}
+
+// a Gateway parent only belongs to us when its class is handled by this
+// controller, otherwise another gateway implementation owns the route
+async fn gateway_managed(ctx: &Context, namespace: &str, name: &str) -> Result<bool, Error> {
+    let gateway_api: Api<Gateway> = Api::namespaced(ctx.kube_cli.clone(), namespace);
+    let Some(gateway) = gateway_api.get_opt(name).await? else {
+        return Ok(false);
+    };
+
+    let gc_api: Api<GatewayClass> = Api::all(ctx.kube_cli.clone());
+    let Some(gateway_class) = gc_api.get_opt(&gateway.spec.gateway_class_name).await? else {
+        return Ok(false);
+    };
+
+    Ok(gateway_class.spec.controller_name == GATEWAY_CONTROLLER)
+}
+
+// replaces this controller's entries and leaves the ones written by other
+// gateway controllers untouched, dropping our entries for detached parents
+fn merge_parent_statuses(
+    existing: &[RouteParentStatus],
+    ours: Vec<RouteParentStatus>,
+) -> Vec<RouteParentStatus> {
+    existing
+        .iter()
+        .filter(|parent| parent.controller_name != GATEWAY_CONTROLLER)
+        .cloned()
+        .chain(ours)
+        .collect()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteRule {
+    #[serde(default)]
+    pub matches: Vec<HTTPRouteMatch>,
+    #[serde(default)]
+    pub backend_refs: Vec<HTTPBackendRef>,
+}
+
+impl HTTPRouteRule {
+    // cloudflared routes each rule to a single origin, so only the first
+    // service backend of a rule is used
+    fn service_backend(&self) -> Option<&HTTPBackendRef> {
+        self.backend_refs
+            .iter()
+            .find(|backend| backend.kind.as_deref().unwrap_or("Service") == "Service")
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteMatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<HTTPPathMatch>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPPathMatch {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl HTTPPathMatch {
+    fn to_regex(&self) -> Option<String> {
+        let value = self.value.as_deref().unwrap_or("/");
+        match self.type_.as_deref().unwrap_or("PathPrefix") {
+            "Exact" => Some(format!("^{}$", regex::escape(value))),
+            "RegularExpression" => Some(value.to_string()),
+            // prefixes match whole path elements, same as ingress Prefix paths
+            _ => build_path_regex(value, "Prefix"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPBackendRef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteStatus {
+    #[serde(default)]
+    pub parents: Vec<RouteParentStatus>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteParentStatus {
+    pub parent_ref: ParentReference,
+    pub controller_name: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+impl HTTPRoute {
+    // routes attach either to a ClusterTunnel directly or to a Gateway of our
+    // class named after one
+    async fn parent_tunnels(&self, ctx: &Context) -> Result<Vec<(ParentReference, String)>, Error> {
+        let ns = self.namespace().unwrap_or_else(|| "default".to_string());
+
+        let mut parents = vec![];
+        for parent_ref in &self.spec.parent_refs {
+            let managed = if parent_ref.is_clustertunnel() {
+                true
+            } else if parent_ref.is_gateway() {
+                let namespace = parent_ref.namespace.as_deref().unwrap_or(&ns);
+                gateway_managed(ctx, namespace, &parent_ref.name).await?
+            } else {
+                false
+            };
+
+            if managed {
+                parents.push((parent_ref.clone(), parent_ref.name.clone()));
+            }
+        }
+
+        Ok(parents)
+    }
+
+    fn hostnames(&self) -> Vec<Option<String>> {
+        if self.spec.hostnames.is_empty() {
+            return vec![None];
+        }
+
+        self.spec.hostnames.iter().cloned().map(Some).collect()
+    }
+
+    // backends in another namespace need a ReferenceGrant there, the refused
+    // ones are returned as namespace/name
+    async fn refused_backends(&self, ctx: &Context) -> Result<BTreeSet<String>, Error> {
+        let ns = self.namespace().unwrap_or_else(|| "default".to_string());
+
+        let mut refused = BTreeSet::new();
+        for backend in self
+            .spec
+            .rules
+            .iter()
+            .filter_map(HTTPRouteRule::service_backend)
+        {
+            let Some(backend_ns) = backend.namespace.as_deref().filter(|b_ns| *b_ns != ns) else {
+                continue;
+            };
+
+            let grant_api: Api<ReferenceGrant> = Api::namespaced(ctx.kube_cli.clone(), backend_ns);
+            let grants = match grant_api.list(&ListParams::default()).await {
+                Ok(grants) => grants.items,
+                // nothing can be granted without the ReferenceGrant CRD
+                Err(kube::Error::Api(err)) if err.code == 404 => vec![],
+                Err(err) => return Err(err.into()),
+            };
+            if !grants.iter().any(|grant| grant.permits(&ns, &backend.name)) {
+                refused.insert(format!("{backend_ns}/{}", backend.name));
+            }
+        }
+
+        Ok(refused)
+    }
+
+    fn tunnel_ingresses(&self, refused: &BTreeSet<String>) -> Vec<TunnelIngress> {
+        let ns = self.namespace().unwrap_or_else(|| "default".to_string());
+
+        let mut ingresses = vec![];
+        for rule in &self.spec.rules {
+            let Some(backend) = rule.service_backend() else {
+                continue;
+            };
+
+            let Some(port) = backend.port else {
+                continue;
+            };
+
+            let backend_ns = backend.namespace.as_deref().unwrap_or(&ns);
+            if refused.contains(&format!("{backend_ns}/{}", backend.name)) {
+                continue;
+            }
+
+            let service = format!(
+                "http://{}.{}.svc:{}",
+                encode_host_label(&backend.name),
+                encode_host_label(backend_ns),
+                port
+            );
+
+            let paths = if rule.matches.is_empty() {
+                vec![None]
+            } else {
+                rule.matches
+                    .iter()
+                    .map(|m| m.path.as_ref().and_then(HTTPPathMatch::to_regex))
+                    .collect()
+            };
+
+            for hostname in self.hostnames() {
+                for path in &paths {
+                    ingresses.push(TunnelIngress {
+                        hostname: hostname.clone(),
+                        path: path.clone(),
+                        service: service.clone(),
+                        origin_request: None,
+                    });
+                }
+            }
+        }
+
+        ingresses
+    }
+
+    fn resolved_refs(&self) -> bool {
+        self.spec.rules.iter().all(|rule| {
+            rule.backend_refs.iter().any(|backend| {
+                backend.kind.as_deref().unwrap_or("Service") == "Service" && backend.port.is_some()
+            })
+        })
+    }
+
+    async fn cloudflare_client(
+        &self,
+        ctx: Arc<Context>,
+        clustertunnel: &ClusterTunnel,
+    ) -> Result<Arc<dyn CloudflareApi>, Error> {
+        let ns = ctx.operator_namespace.clone();
+        let cloudflare_creds =
+            get_credentials(ctx.clone(), &ns, &clustertunnel.spec.cloudflare).await?;
+
+        ctx.cloudflare_client(
+            clustertunnel.spec.cloudflare.account_id.clone(),
+            cloudflare_creds,
+        )
+    }
+
+    fn dns_cleanup_policy(&self, clustertunnel: &ClusterTunnel) -> Result<DnsCleanupPolicy, Error> {
+        Ok(self
+            .annotations()
+            .get(ANNOTATION_DNS_CLEANUP_POLICY)
+            .map(|policy| policy.parse::<DnsCleanupPolicy>())
+            .transpose()?
+            .or(clustertunnel.spec.default_dns_cleanup_policy)
+            .unwrap_or_default())
+    }
+
+    pub async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+        let generation = self.metadata.generation;
+
+        let refused = self.refused_backends(&ctx).await?;
+        let ingresses = self.tunnel_ingresses(&refused);
+        for ing in &ingresses {
+            validate_service_url(&ing.service)?;
+        }
+
+        // entries and hostnames of an earlier version of the route
+        let entries: Vec<RouteEntry> = ingresses.iter().map(RouteEntry::from).collect();
+        let previous = route_entries(self.annotations());
+        let stale: Vec<&RouteEntry> = previous
+            .iter()
+            .filter(|entry| !entries.contains(entry))
+            .collect();
+        let stale_hostnames: HashSet<&String> = stale
+            .iter()
+            .filter_map(|entry| entry.hostname.as_ref())
+            .filter(|hostname| !self.spec.hostnames.contains(hostname))
+            .collect();
+
+        let mut parents = vec![];
+        for (parent_ref, clustertunnel_name) in self.parent_tunnels(&ctx).await? {
+            let Some(clustertunnel) = ct_api.get_opt(&clustertunnel_name).await? else {
+                parents.push(RouteParentStatus {
+                    parent_ref,
+                    controller_name: GATEWAY_CONTROLLER.to_string(),
+                    conditions: vec![condition(
+                        "Accepted",
+                        false,
+                        "NoMatchingParent",
+                        &format!("clustertunnel {clustertunnel_name} not found"),
+                        generation,
+                    )],
+                });
+                continue;
+            };
+
+            // see the ingress controller, token tunnels are routed in the dashboard
+            if clustertunnel.uses_tunnel_token() {
+                parents.push(RouteParentStatus {
+                    parent_ref,
+                    controller_name: GATEWAY_CONTROLLER.to_string(),
+                    conditions: vec![condition(
+                        "Accepted",
+                        false,
+                        "UnsupportedValue",
+                        &Error::TunnelTokenRoutes(clustertunnel_name.clone()).to_string(),
+                        generation,
+                    )],
+                });
+                continue;
+            }
+
+            let tunnel_name = clustertunnel
+                .spec
+                .name
+                .clone()
+                .unwrap_or_else(|| clustertunnel.name_any());
+
+            let config_lock = ctx.config_lock(&tunnel_name);
+            let _config_guard = config_lock.lock().await;
+
+            let config = update_config(ctx.clone(), &clustertunnel, &tunnel_name, |config| {
+                config
+                    .ingress
+                    .retain(|existing| !stale.iter().any(|entry| entry.matches(existing)));
+                for ing in ingresses.iter().cloned() {
+                    insert_ingress(config, ing);
+                }
+            })
+            .await?;
+
+            let cloudflare_client = self.cloudflare_client(ctx.clone(), &clustertunnel).await?;
+            let cname = format!("{}.cfargotunnel.com", config.tunnel);
+            if self.dns_cleanup_policy(&clustertunnel)? != DnsCleanupPolicy::Retain {
+                for hostname in &stale_hostnames {
+                    let zone_id = clustertunnel
+                        .zone_id(ctx.clone(), &cloudflare_client, hostname)
+                        .await?;
+
+                    // only the tunnel's own record, the name may have been reused
+                    let Some(record) = cloudflare_client
+                        .find_dns_record(&zone_id, hostname)
+                        .await?
+                        .filter(|record| match &record.content {
+                            DnsContent::CNAME { content } => *content == cname,
+                            _ => false,
+                        })
+                    else {
+                        continue;
+                    };
+
+                    cloudflare_client
+                        .delete_dns_record(&zone_id, &record.id)
+                        .await?;
+                }
+            }
+
+            for hostname in self.spec.hostnames.iter() {
+                let zone_id = clustertunnel
+                    .zone_id(ctx.clone(), &cloudflare_client, hostname)
+                    .await?;
+
+                match cloudflare_client
+                    .find_dns_record(&zone_id, hostname)
+                    .await?
+                {
+                    Some(record) => match &record.content {
+                        DnsContent::CNAME { content } if *content == cname => continue,
+                        _ => {
+                            cloudflare_client
+                                .update_dns_record(
+                                    &zone_id,
+                                    &record.id,
+                                    hostname,
+                                    &config.tunnel,
+                                    None,
+                                )
+                                .await?;
+                        }
+                    },
+                    None => {
+                        cloudflare_client
+                            .create_dns_record(
+                                &zone_id,
+                                hostname,
+                                DnsContent::CNAME {
+                                    content: cname.clone(),
+                                },
+                            )
+                            .await?;
+                    }
+                }
+                ctx.metrics.dns_records_managed_total.inc();
+            }
+
+            let resolved_refs = if !refused.is_empty() {
+                let refused: Vec<&str> = refused.iter().map(String::as_str).collect();
+                condition(
+                    "ResolvedRefs",
+                    false,
+                    "RefNotPermitted",
+                    &format!(
+                        "no ReferenceGrant allows the backends {}",
+                        refused.join(", ")
+                    ),
+                    generation,
+                )
+            } else if self.resolved_refs() {
+                condition("ResolvedRefs", true, "ResolvedRefs", "", generation)
+            } else {
+                condition(
+                    "ResolvedRefs",
+                    false,
+                    "InvalidKind",
+                    "every rule needs a Service backend with a port",
+                    generation,
+                )
+            };
+            parents.push(RouteParentStatus {
+                parent_ref,
+                controller_name: GATEWAY_CONTROLLER.to_string(),
+                conditions: vec![
+                    condition("Accepted", true, "Accepted", "", generation),
+                    resolved_refs,
+                ],
+            });
+        }
+
+        let route_api: Api<HTTPRoute> = Api::namespaced(
+            ctx.kube_cli.clone(),
+            &self.namespace().unwrap_or_else(|| "default".to_string()),
+        );
+        let existing = self
+            .status
+            .as_ref()
+            .map(|status| status.parents.as_slice())
+            .unwrap_or_default();
+        let parents = merge_parent_statuses(existing, parents);
+
+        // parents is a plain list in a merge patch, so guard the write with the
+        // resourceVersion it was computed from to not drop concurrent updates
+        let status = serde_json::json!({
+            "metadata": { "resourceVersion": self.resource_version() },
+            "status": HTTPRouteStatus { parents }
+        });
+        route_api
+            .patch_status(
+                &self.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Merge(&status),
+            )
+            .await?;
+
+        // written after the status, whose patch is guarded by the old resourceVersion
+        if entries != previous {
+            let patch = serde_json::json!({
+                "metadata": {
+                    "annotations": {
+                        ANNOTATION_ROUTE_ENTRIES: serde_json::to_string(&entries)?,
+                    }
+                }
+            });
+            route_api
+                .patch(
+                    &self.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Merge(&patch),
+                )
+                .await?;
+        }
+
+        Ok(Action::requeue(ctx.requeue_interval))
+    }
+
+    pub async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+
+        for (_, clustertunnel_name) in self.parent_tunnels(&ctx).await? {
+            let Some(clustertunnel) = ct_api.get_opt(&clustertunnel_name).await? else {
+                continue;
+            };
+
+            let tunnel_name = clustertunnel
+                .spec
+                .name
+                .clone()
+                .unwrap_or_else(|| clustertunnel.name_any());
+
+            let config_lock = ctx.config_lock(&tunnel_name);
+            let _config_guard = config_lock.lock().await;
+
+            // the recorded entries cover the ones written before a later edit
+            let mut entries = route_entries(self.annotations());
+            entries.extend(
+                self.tunnel_ingresses(&BTreeSet::new())
+                    .iter()
+                    .map(RouteEntry::from),
+            );
+            update_config(ctx.clone(), &clustertunnel, &tunnel_name, |config| {
+                config
+                    .ingress
+                    .retain(|existing| !entries.iter().any(|entry| entry.matches(existing)));
+            })
+            .await?;
+
+            let dns_cleanup_policy = self.dns_cleanup_policy(&clustertunnel)?;
+            // token tunnels never got records, the api can't be called for them
+            if dns_cleanup_policy == DnsCleanupPolicy::Retain || clustertunnel.uses_tunnel_token() {
+                continue;
+            }
+
+            let cloudflare_client = self.cloudflare_client(ctx.clone(), &clustertunnel).await?;
+            for hostname in self.spec.hostnames.iter() {
+                let zone_id = clustertunnel
+                    .zone_id(ctx.clone(), &cloudflare_client, hostname)
+                    .await?;
+
+                let Some(dns_record) = cloudflare_client
+                    .find_dns_record(&zone_id, hostname)
+                    .await?
+                else {
+                    continue;
+                };
+
+                cloudflare_client
+                    .delete_dns_record(&zone_id, &dns_record.id)
+                    .await?;
+            }
+        }
+
+        Ok(Action::await_change())
+    }
+}
+
+#[instrument(skip(obj, ctx), fields(
+    name = %obj.name_any(),
+    namespace = ?obj.namespace(),
+    resource_version = ?obj.resource_version(),
+    k8s.resource.kind = "HTTPRoute",
+    k8s.resource.name = %obj.name_any(),
+    k8s.resource.namespace = ?obj.namespace(),
+))]
+pub async fn reconcile(obj: Arc<HTTPRoute>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let _reconcile = ctx.track_reconcile();
+
+    // routes not attached to any tunnel belong to another gateway controller,
+    // unless we handled them before and still have status entries to prune
+    if !obj.finalizers().iter().any(|f| f == HTTPROUTE_FINALIZER)
+        && obj.parent_tunnels(&ctx).await?.is_empty()
+    {
+        return Ok(Action::await_change());
+    }
+
+    let route_api: Api<HTTPRoute> = Api::namespaced(
+        ctx.kube_cli.clone(),
+        &obj.namespace().unwrap_or_else(|| "default".to_string()),
+    );
+    finalizer(&route_api, HTTPROUTE_FINALIZER, obj, |event| async {
+        match event {
+            finalizer::Event::Apply(obj) => obj.reconcile(ctx.clone()).await,
+            finalizer::Event::Cleanup(obj) => obj.cleanup(ctx.clone()).await,
+        }
+    })
+    .await
+    .map_err(|e| Error::FinalizerError(Box::new(e)))
+}
+
+async fn ensure_gateway_class(ctx: &Context) -> Result<(), Error> {
+    let gc_api: Api<GatewayClass> = Api::all(ctx.kube_cli.clone());
+
+    let gateway_class = GatewayClass::new(
+        GATEWAY_CLASS_NAME,
+        GatewayClassSpec {
+            controller_name: GATEWAY_CONTROLLER.to_string(),
+            description: Some("Routes traffic through Cloudflare Tunnels".to_string()),
+        },
+    );
+
+    gc_api
+        .patch(
+            GATEWAY_CLASS_NAME,
+            &PatchParams::apply(OPERATOR_MANAGER),
+            &Patch::Apply(&gateway_class),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
+    ensure_gateway_class(&ctx).await?;
+
+    let cfg = ctx.watcher_config();
+    let route_api: Api<HTTPRoute> = ctx.watched_api();
+
+    Controller::new(route_api, cfg)
+        .shutdown_on_signal()
+        .run(
+            |obj, ctx| with_correlation_id(reconcile(obj, ctx)),
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(o) => {
+                        ctx.metrics.reconcile_success("HTTPRoute");
+                        reset_failures(&ctx, &o.0);
+                        info!("reconciled httproute {o:?}")
+                    }
+                    Err(e) => warn!("reconcile httproute failed: {e:?}"),
+                }
+            }
+        })
+        .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde_json::json;
+
+    use super::{
+        merge_parent_statuses, HTTPPathMatch, HTTPRoute, ParentReference, ReferenceGrant,
+        RouteParentStatus, GATEWAY_CONTROLLER,
+    };
+
+    fn parent_ref(group: Option<&str>, kind: Option<&str>, name: &str) -> ParentReference {
+        ParentReference {
+            group: group.map(str::to_string),
+            kind: kind.map(str::to_string),
+            namespace: None,
+            name: name.to_string(),
+            section_name: None,
+        }
+    }
+
+    fn parent_status(name: &str, controller_name: &str) -> RouteParentStatus {
+        RouteParentStatus {
+            parent_ref: parent_ref(None, None, name),
+            controller_name: controller_name.to_string(),
+            conditions: vec![],
+        }
+    }
+
+    #[test]
+    fn parent_ref_kinds() {
+        let clustertunnel = parent_ref(
+            Some("cloudflare-tunnels-operator.io"),
+            Some("ClusterTunnel"),
+            "tunnel",
+        );
+        assert!(clustertunnel.is_clustertunnel());
+        assert!(!clustertunnel.is_gateway());
+
+        assert!(parent_ref(None, None, "gateway").is_gateway());
+        assert!(parent_ref(Some("gateway.networking.k8s.io"), Some("Gateway"), "gw").is_gateway());
+
+        let service = parent_ref(Some(""), Some("Service"), "svc");
+        assert!(!service.is_gateway());
+        assert!(!service.is_clustertunnel());
+    }
+
+    #[test]
+    fn keeps_other_controllers_parents() {
+        let existing = vec![
+            parent_status("other", "example.com/gateway"),
+            parent_status("tunnel", GATEWAY_CONTROLLER),
+        ];
+
+        let parents =
+            merge_parent_statuses(&existing, vec![parent_status("tunnel", GATEWAY_CONTROLLER)]);
+
+        assert_eq!(parents.len(), 2);
+        assert_eq!(parents[0].controller_name, "example.com/gateway");
+        assert_eq!(parents[1].parent_ref.name, "tunnel");
+    }
+
+    #[test]
+    fn prunes_detached_parents() {
+        let existing = vec![
+            parent_status("old", GATEWAY_CONTROLLER),
+            parent_status("other", "example.com/gateway"),
+        ];
+
+        let parents =
+            merge_parent_statuses(&existing, vec![parent_status("new", GATEWAY_CONTROLLER)]);
+
+        let names: Vec<_> = parents.iter().map(|p| p.parent_ref.name.as_str()).collect();
+        assert_eq!(names, ["other", "new"]);
+    }
+
+    #[test]
+    fn path_prefix_matches_whole_segments() {
+        let path = HTTPPathMatch {
+            type_: Some("PathPrefix".to_string()),
+            value: Some("/api".to_string()),
+        };
+        let re = regex::Regex::new(&path.to_regex().unwrap()).unwrap();
+
+        assert!(re.is_match("/api"));
+        assert!(re.is_match("/api/users"));
+        assert!(!re.is_match("/apis"));
+    }
+
+    fn reference_grant(from_namespace: &str, name: Option<&str>) -> ReferenceGrant {
+        serde_json::from_value(json!({
+            "apiVersion": "gateway.networking.k8s.io/v1beta1",
+            "kind": "ReferenceGrant",
+            "metadata": { "name": "grant", "namespace": "backend" },
+            "spec": {
+                "from": [{
+                    "group": "gateway.networking.k8s.io",
+                    "kind": "HTTPRoute",
+                    "namespace": from_namespace,
+                }],
+                "to": [{ "group": "", "kind": "Service", "name": name }],
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reference_grant_permits_routes_of_its_namespace() {
+        assert!(reference_grant("apps", None).permits("apps", "svc"));
+        assert!(reference_grant("apps", Some("svc")).permits("apps", "svc"));
+        assert!(!reference_grant("apps", Some("other")).permits("apps", "svc"));
+        assert!(!reference_grant("other", None).permits("apps", "svc"));
+    }
+
+    fn route(backend_namespace: &str, backend_name: &str) -> HTTPRoute {
+        serde_json::from_value(json!({
+            "apiVersion": "gateway.networking.k8s.io/v1",
+            "kind": "HTTPRoute",
+            "metadata": { "name": "route", "namespace": "apps" },
+            "spec": {
+                "hostnames": ["app.example.com"],
+                "rules": [{
+                    "backendRefs": [{
+                        "name": backend_name,
+                        "namespace": backend_namespace,
+                        "port": 80,
+                    }],
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn refused_backends_are_not_routed() {
+        let refused = BTreeSet::from(["backend/svc".to_string()]);
+
+        assert!(route("backend", "svc")
+            .tunnel_ingresses(&refused)
+            .is_empty());
+        assert_eq!(
+            route("apps", "svc").tunnel_ingresses(&refused)[0].service,
+            "http://svc.apps.svc:80"
+        );
+    }
+
+    #[test]
+    fn backend_names_are_encoded() {
+        let ingresses = route("apps", "svc@evil.com#").tunnel_ingresses(&BTreeSet::new());
+
+        assert_eq!(
+            ingresses[0].service,
+            "http://svc%40evil%2Ecom%23.apps.svc:80"
+        );
+    }
+}
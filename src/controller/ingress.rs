@@ -1,102 +1,590 @@
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::anyhow;
-use futures_util::StreamExt;
+use futures_util::{future, stream, StreamExt};
 use k8s_openapi::api::{
-    apps::v1::Deployment,
-    core::v1::{ConfigMap, Service},
-    networking::v1::{
-        Ingress, IngressLoadBalancerIngress, IngressLoadBalancerStatus, IngressStatus,
-    },
+    apps::v1::{DaemonSet, Deployment},
+    core::v1::{ConfigMap, PodTemplateSpec, Secret, Service},
+    networking::v1::{Ingress, IngressServiceBackend},
 };
 use kube::{
     api::{ListParams, ObjectMeta, Patch, PatchParams},
-    runtime::{controller::Action, finalizer, watcher, Controller},
-    Api, ResourceExt,
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+        finalizer, reflector,
+        reflector::ObjectRef,
+        watcher, Controller, WatchStreamExt,
+    },
+    Api, Resource, ResourceExt,
 };
-use log::{info, warn};
+use tracing::{field, info, instrument, warn, Span};
 
 use crate::{
-    cloudflare::{dns::DnsContent, Client as CloudflareClient, TunnelConfig, TunnelIngress},
+    cloudflare::{
+        dns::DnsContent, is_dry_run_id, validate_service_url, with_correlation_id, AccessConfig,
+        CloudflareApi, OriginRequest, TunnelConfig, TunnelIngress,
+    },
     context::Context,
+    controller::clustertunnel::{ConfigMode, DeploymentKind, DnsCleanupPolicy},
     controller::utils::*,
     error::Error,
     ClusterTunnel,
 };
 
-use super::{error_policy, OPERATOR_MANAGER};
+use super::{
+    certmanager::{origin_ca, ANNOTATION_CERTIFICATE_NAME},
+    error_policy, reset_failures,
+    tunnelpolicy::{apply_policies, get_policy, release_policies},
+    OPERATOR_MANAGER,
+};
 
 const INGRESS_FINALIZER: &'static str = "ingress.cloudflare-tunnels-operator.io/finalizer";
+const NOT_FOUND_SERVICE: &'static str = "http_status:404";
+
+fn template_config_hash(template: Option<PodTemplateSpec>) -> Option<String> {
+    template
+        .and_then(|template| template.metadata)
+        .and_then(|metadata| metadata.annotations)
+        .and_then(|annotations| annotations.get(ANNOTATION_CONFIG_HASH).cloned())
+}
+
+pub(super) async fn patch_workload(
+    ctx: Arc<Context>,
+    clustertunnel: &ClusterTunnel,
+    tunnel_name: &str,
+    hash: String,
+) -> Result<(), Error> {
+    let operator_ns = ctx.operator_namespace.clone();
+    let name = deployment_name(clustertunnel.resource_prefix(), tunnel_name);
 
-async fn patch_deployment(deploy_api: &Api<Deployment>, hash: String) -> Result<(), Error> {
     let patch: json_patch::Patch = serde_json::from_value(serde_json::json!([
-        { 
-            "op": "replace", 
-            "path": format!("/spec/template/metadata/annotations/{}", ANNOTATION_CONFIG_HASH.replace("/", "~1")), 
-            "value": hash 
+        {
+            "op": "replace",
+            "path": format!(
+                "/spec/template/metadata/annotations/{}",
+                ANNOTATION_CONFIG_HASH.replace("/", "~1")
+            ),
+            "value": hash
         },
-      ])).map_err(|err|Error::Other(anyhow!("parse patch: {err}")))?;
+    ]))
+    .map_err(|err| Error::Other(anyhow!("parse patch: {err}")))?;
+    let pp = PatchParams::apply(OPERATOR_MANAGER);
 
-    deploy_api
-        .patch(
-            "cloudflared",
-            &PatchParams::apply(OPERATOR_MANAGER),
-            &Patch::Json::<()>(patch),
-        )
-        .await?;
+    // restarting cloudflared is only needed when the effective config changed
+    match clustertunnel.deployment_kind() {
+        DeploymentKind::Deployment => {
+            let api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), &operator_ns);
+            let template = api
+                .get_opt(&name)
+                .await?
+                .and_then(|deploy| deploy.spec)
+                .map(|spec| spec.template);
+            if template_config_hash(template).as_ref() != Some(&hash) {
+                api.patch(&name, &pp, &Patch::Json::<()>(patch)).await?;
+            }
+        }
+        DeploymentKind::DaemonSet => {
+            let api: Api<DaemonSet> = Api::namespaced(ctx.kube_cli.clone(), &operator_ns);
+            let template = api
+                .get_opt(&name)
+                .await?
+                .and_then(|ds| ds.spec)
+                .map(|spec| spec.template);
+            if template_config_hash(template).as_ref() != Some(&hash) {
+                api.patch(&name, &pp, &Patch::Json::<()>(patch)).await?;
+            }
+        }
+    }
 
     Ok(())
 }
 
-pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, Error> {
-    if obj
-        .annotations()
-        .get("kubernetes.io/ingress.class")
-        .or(obj
+pub(super) fn insert_ingress(config: &mut TunnelConfig, ing: TunnelIngress) {
+    if let Some(index) = config.ingress.iter().position(|existing| {
+        existing.hostname == ing.hostname && existing.path == ing.path && !is_not_found(existing)
+    }) {
+        config.ingress[index] = ing
+    } else {
+        insert_route(config, ing);
+    }
+}
+
+// Local tunnels are restarted onto the new config map, remote ones get the
+// config pushed to the Cloudflare api.
+pub(super) async fn publish_config(
+    ctx: Arc<Context>,
+    clustertunnel: &ClusterTunnel,
+    tunnel_name: &str,
+    config: &TunnelConfig,
+    hash: String,
+) -> Result<(), Error> {
+    match clustertunnel.config_mode() {
+        ConfigMode::Local => patch_workload(ctx, clustertunnel, tunnel_name, hash).await,
+        ConfigMode::Remote => {
+            let credentials = get_credentials(
+                ctx.clone(),
+                &ctx.operator_namespace,
+                &clustertunnel.spec.cloudflare,
+            )
+            .await?;
+            let cloudflare_client = ctx.cloudflare_client(
+                clustertunnel.spec.cloudflare.account_id.clone(),
+                credentials,
+            )?;
+
+            cloudflare_client
+                .put_tunnel_configuration(&config.tunnel, config)
+                .await
+        }
+    }
+}
+
+pub(super) async fn update_config(
+    ctx: Arc<Context>,
+    clustertunnel: &ClusterTunnel,
+    tunnel_name: &str,
+    update: impl FnOnce(&mut TunnelConfig),
+) -> Result<TunnelConfig, Error> {
+    let operator_ns = ctx.operator_namespace.clone();
+    let cm_api: Api<ConfigMap> = Api::namespaced(ctx.kube_cli.clone(), &operator_ns);
+
+    let config_name = config_map_name(clustertunnel.resource_prefix(), tunnel_name);
+    let not_found = || Error::ConfigMapNotFound {
+        name: format!("{operator_ns}/{config_name}"),
+    };
+    let config_map = cm_api.get_opt(&config_name).await?.ok_or_else(not_found)?;
+    let config_yaml = config_map
+        .data
+        .as_ref()
+        .and_then(|data| data.get("config.yaml"))
+        .ok_or_else(not_found)?;
+    let mut config = TunnelConfig::from_yaml(config_yaml)?;
+
+    update(&mut config);
+    ensure_catch_all(&mut config);
+
+    let config_yaml = config.to_yaml()?;
+    let config_hash = compute_config_hash(&config)?;
+    let data = config_map_data(&config, config_yaml, config_map.data.as_ref());
+
+    if config_map.data.as_ref() != Some(&data) {
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(config_map.name_any()),
+                namespace: config_map.namespace(),
+                owner_references: Some(config_map.owner_references().to_vec()),
+                annotations: Some(managed_ingresses_annotations(
+                    &managed_ingresses(&config_map),
+                    default_backend_owner(&config_map).as_ref(),
+                )),
+                ..ObjectMeta::default()
+            },
+            data: Some(data),
+            ..config_map.clone()
+        };
+
+        cm_api
+            .patch(
+                &config_map.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Apply(&config_map),
+            )
+            .await?;
+        ctx.metrics
+            .configmap_write_total
+            .with_label_values(&[tunnel_name])
+            .inc();
+    } else {
+        ctx.metrics
+            .configmap_write_skipped_total
+            .with_label_values(&[tunnel_name])
+            .inc();
+    }
+
+    publish_config(
+        ctx.clone(),
+        clustertunnel,
+        tunnel_name,
+        &config,
+        config_hash,
+    )
+    .await?;
+
+    Ok(config)
+}
+
+// Entries without hostname and path match all traffic, so new routes go in
+// front of them.
+fn is_catch_all(ing: &TunnelIngress) -> bool {
+    ing.hostname.is_none() && ing.path.is_none()
+}
+
+fn is_not_found(ing: &TunnelIngress) -> bool {
+    is_catch_all(ing) && ing.service == NOT_FOUND_SERVICE
+}
+
+// cloudflared refuses a config unless its last rule, and only that one,
+// matches everything; the 404 is the terminal rule when there's no other one
+pub(super) fn ensure_catch_all(config: &mut TunnelConfig) {
+    let terminal = config
+        .ingress
+        .iter()
+        .rposition(is_catch_all)
+        .map(|index| config.ingress.remove(index));
+    config.ingress.retain(|ing| !is_catch_all(ing));
+    config
+        .ingress
+        .push(terminal.unwrap_or_else(|| TunnelIngress {
+            service: NOT_FOUND_SERVICE.to_string(),
+            ..TunnelIngress::default()
+        }));
+}
+
+// a catch-all replaces the terminal rule, other routes go in front of it
+fn insert_route(config: &mut TunnelConfig, ing: TunnelIngress) {
+    if is_catch_all(&ing) {
+        config.ingress.retain(|existing| !is_catch_all(existing));
+        config.ingress.push(ing);
+    } else {
+        let index = config
+            .ingress
+            .iter()
+            .position(is_catch_all)
+            .unwrap_or(config.ingress.len());
+        config.ingress.insert(index, ing);
+    }
+    ensure_catch_all(config);
+}
+
+// "<zone id>/<app id>" of the Access applications created for the ingress
+fn access_application_ids(obj: &Ingress) -> Vec<String> {
+    obj.annotations()
+        .get(ANNOTATION_ACCESS_APPLICATION_ID)
+        .map(|ids| {
+            ids.split(',')
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// None unless the tunnel creates load balancers and its pool exists
+fn load_balancer_pool_id(clustertunnel: &ClusterTunnel) -> Option<String> {
+    if clustertunnel.spec.create_load_balancer != Some(true) {
+        return None;
+    }
+
+    clustertunnel
+        .status
+        .as_ref()
+        .and_then(|status| status.load_balancer_pool_id.clone())
+}
+
+// The other ingresses routed through the tunnel, they share its load balancer
+// pool and may serve the same hosts.
+async fn other_managed_ingresses(
+    client: &kube::Client,
+    config_map: &ConfigMap,
+    ingress_key: &str,
+) -> Result<Vec<Ingress>, Error> {
+    let mut ingresses = vec![];
+    for key in managed_ingresses(config_map) {
+        let Some((ns, name)) = key.split_once('/').filter(|_| key != ingress_key) else {
+            continue;
+        };
+
+        let ing_api: Api<Ingress> = Api::namespaced(client.clone(), ns);
+        if let Some(ing) = ing_api.get_opt(name).await? {
+            ingresses.push(ing);
+        }
+    }
+
+    Ok(ingresses)
+}
+
+fn ingress_hosts(ing: &Ingress) -> impl Iterator<Item = &String> {
+    ing.spec
+        .iter()
+        .flat_map(|spec| spec.rules.iter().flatten())
+        .filter_map(|rule| rule.host.as_ref())
+}
+
+// A pool has a single monitor, so the ingresses sharing it have to agree.
+fn pool_monitors<'a>(ingresses: &[&'a Ingress]) -> BTreeSet<&'a str> {
+    ingresses
+        .iter()
+        .copied()
+        .filter_map(|ing| {
+            ing.annotations()
+                .get(ANNOTATION_LOAD_BALANCER_HEALTH_MONITOR)
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+// Attaches the monitor the ingresses ask for to the pool, or detaches it once
+// none of them does.
+async fn sync_pool_monitor(
+    ctx: &Context,
+    cloudflare_client: &dyn CloudflareApi,
+    pool_id: &str,
+    ingresses: &[&Ingress],
+    obj: &Ingress,
+) -> Result<(), Error> {
+    let monitors = pool_monitors(ingresses);
+    if monitors.len() > 1 {
+        ctx.recorder
+            .publish(
+                &Event {
+                    type_: EventType::Warning,
+                    reason: "LoadBalancerMonitorConflict".to_string(),
+                    note: Some(format!(
+                        "ingresses of the tunnel ask for different load balancer monitors: {}",
+                        monitors.into_iter().collect::<Vec<_>>().join(", ")
+                    )),
+                    action: "Reconciling".to_string(),
+                    secondary: None,
+                },
+                &obj.object_ref(&()),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let monitor = monitors.into_iter().next();
+    let Some(pool) = cloudflare_client.get_load_balancer_pool(pool_id).await? else {
+        return Ok(());
+    };
+    if pool.monitor.as_deref() != monitor {
+        cloudflare_client
+            .set_load_balancer_pool_monitor(pool_id, monitor)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Resolves the service url of a backend, None when its port can't be found.
+// An explicit protocol wins over the one the service port advertises.
+async fn backend_service(
+    svc_api: &Api<Service>,
+    svc: &IngressServiceBackend,
+    ing_ns: &str,
+    backend_protocol: Option<BackendProtocol>,
+    http2_origin: bool,
+) -> Result<Option<(String, BackendProtocol)>, Error> {
+    let Some(svc_port) = svc.port.as_ref() else {
+        return Ok(None);
+    };
+
+    // backends of a deleted service are skipped whichever way their port is given
+    let Some(service) = svc_api.get_opt(&svc.name).await? else {
+        return Err(Error::ServiceNotFound {
+            name: format!("{ing_ns}/{}", svc.name),
+        });
+    };
+
+    let mut detected_protocol = None;
+    let port = if let Some(port) = svc_port.number {
+        // the port advertises its protocol even when referenced by number
+        detected_protocol = service
             .spec
-            .as_ref()
-            .and_then(|spec| spec.ingress_class_name.as_ref()))
-        .cloned()
-        != ctx.ingress_class
-    {
+            .iter()
+            .flat_map(|spec| spec.ports.iter().flatten())
+            .find(|service_port| service_port.port == port)
+            .and_then(BackendProtocol::from_service_port);
+
+        port
+    } else if let Some(name) = svc_port.name.as_ref() {
+        let Some(service_port) = service
+            .spec
+            .iter()
+            .flat_map(|spec| spec.ports.iter().flatten())
+            .find(|port| port.name.as_ref() == Some(name))
+        else {
+            return Ok(None);
+        };
+        detected_protocol = BackendProtocol::from_service_port(service_port);
+
+        service_port.port
+    } else {
+        warn!("skipping backend {}: no port specified", svc.name);
+        return Ok(None);
+    };
+
+    let default_protocol = match port {
+        443 => BackendProtocol::Https,
+        _ => BackendProtocol::Http,
+    };
+    let protocol = backend_protocol
+        .or(detected_protocol)
+        .or(http2_origin.then_some(BackendProtocol::H2))
+        .unwrap_or(default_protocol);
+    let service = format!(
+        "{}://{}.{}.svc:{}",
+        protocol.scheme(),
+        encode_host_label(&svc.name),
+        ing_ns,
+        port
+    );
+    validate_service_url(&service)?;
+
+    Ok(Some((service, protocol)))
+}
+
+// Kubernetes only allows DNS labels as service names, anything else is
+// escaped so it can't change the meaning of the service url.
+pub(super) fn encode_host_label(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => c.to_string(),
+            _ => c
+                .to_string()
+                .bytes()
+                .map(|byte| format!("%{byte:02X}"))
+                .collect(),
+        })
+        .collect()
+}
+
+fn ingress_class_name(obj: &Ingress) -> Option<&String> {
+    let class_name = obj
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.ingress_class_name.as_ref());
+
+    obj.annotations()
+        .get("kubernetes.io/ingress.class")
+        .or(class_name)
+}
+
+// An empty path matches everything, same as no path at all. Exact matches
+// also accept a trailing slash, prefixes only match whole path elements.
+// cloudflared matches against the path alone, so query strings don't matter.
+pub(super) fn build_path_regex(path: &str, path_type: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+
+    let path = path.strip_suffix('/').unwrap_or(path);
+    if path_type == "Exact" {
+        return Some(format!("^{}\\/?$", regex::escape(path)));
+    }
+
+    Some(format!("^{}(\\/|$)", regex::escape(path)))
+}
+
+// an explicit annotation wins, otherwise the tunnel named after the ingress class is used
+fn clustertunnel_name(obj: &Ingress) -> Option<&String> {
+    obj.annotations()
+        .get(ANNOTATION_CLUSTER_TUNNEL)
+        .or(ingress_class_name(obj))
+}
+
+#[instrument(skip(obj, ctx), fields(
+    name = %obj.name_any(),
+    namespace = ?obj.namespace(),
+    resource_version = ?obj.resource_version(),
+    k8s.resource.kind = "Ingress",
+    k8s.resource.name = %obj.name_any(),
+    k8s.resource.namespace = ?obj.namespace(),
+    tunnel_name = field::Empty,
+))]
+pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let _reconcile = ctx.track_reconcile();
+
+    if !ctx.manages_ingress_class(ingress_class_name(&obj)) {
         return Ok(Action::await_change());
     }
 
-    let ns = std::env::var("POD_NAMESPACE").unwrap_or_else(|_| "default".to_string());
+    if reconcile_paused(obj.annotations()) {
+        info!("reconciliation paused by annotation; skipping");
+        return Ok(Action::await_change());
+    }
+
+    // the tunnel config lives next to cloudflared in the operator namespace,
+    // the backend services next to the ingress
+    let operator_ns = ctx.operator_namespace.clone();
     let client = ctx.kube_cli.clone();
 
-    let cm_api: Api<ConfigMap> = Api::namespaced(client.clone(), &ns);
-    let deploy_api: Api<Deployment> = Api::namespaced(client.clone(), &ns);
+    let cm_api: Api<ConfigMap> = Api::namespaced(client.clone(), &operator_ns);
+    // ClusterTunnels are cluster scoped, so they are looked up by name even
+    // when the operator is restricted to a namespace
     let ct_api: Api<ClusterTunnel> = Api::all(client.clone());
 
     let ing_ns = obj.namespace().unwrap_or_else(|| "default".to_string());
     let ing_api: Api<Ingress> = Api::namespaced(client.clone(), &ing_ns);
     let svc_api: Api<Service> = Api::namespaced(client.clone(), &ing_ns);
+    let ingress_key = format!("{ing_ns}/{}", obj.name_any());
+
+    // without annotation or class the ingress keeps using the default tunnel,
+    // the first one listed
+    let lp = match clustertunnel_name(&obj) {
+        Some(name) => ListParams::default().fields(&format!("metadata.name={name}")),
+        None => ListParams::default().limit(1),
+    };
+    let clustertunnels = ct_api.list(&lp).await?;
+    let Some(clustertunnel) = clustertunnels.items.first() else {
+        return Err(Error::TunnelNotFound {
+            name: clustertunnel_name(&obj)
+                .cloned()
+                .unwrap_or_else(|| "default".to_string()),
+        });
+    };
+    let clustertunnel_name = clustertunnel.name_any();
+
+    // the tunnel's credentials may be gone before its DNS records are
+    if clustertunnel.metadata.deletion_timestamp.is_some() {
+        warn!("clustertunnel {clustertunnel_name} is being deleted; requeueing");
+        return Ok(Action::requeue(Duration::from_secs(30)));
+    }
 
-    let tunnel_name = if let Some(tunnel_name) = obj.metadata.annotations.as_ref().and_then(|ann|ann.get(ANNOTATION_TUNNEL_NAME)) {
+    let tunnel_name = if let Some(tunnel_name) = obj
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|ann| ann.get(ANNOTATION_TUNNEL_NAME))
+    {
         tunnel_name.to_owned()
-    } else if let Some(tunnel) = ct_api.list(&ListParams::default()).await?.items.first() {
-        tunnel.spec.name.clone().unwrap_or_else(|| tunnel.name_any())
     } else {
-        return Err(Error::Other(anyhow!("no clustertunnel found")));
+        clustertunnel
+            .spec
+            .name
+            .clone()
+            .unwrap_or_else(|| clustertunnel.name_any())
     };
-    let config_name = format!("cloudflared-{tunnel_name}-config");
-    let config_map = cm_api.get(&config_name).await?;
-    let mut config = config_map
+    Span::current().record("tunnel_name", tunnel_name.as_str());
+
+    // cloudflared reads no config map with a tunnel token and the token can't
+    // route the hosts through the api, the dashboard owns the routes instead
+    if clustertunnel.uses_tunnel_token() {
+        return Err(Error::TunnelTokenRoutes(clustertunnel_name));
+    }
+
+    let config_lock = ctx.config_lock(&tunnel_name);
+    let _config_guard = config_lock.lock().await;
+
+    let config_name = config_map_name(clustertunnel.resource_prefix(), &tunnel_name);
+    let not_found = || Error::ConfigMapNotFound {
+        name: format!("{operator_ns}/{config_name}"),
+    };
+    let config_map = cm_api.get_opt(&config_name).await?.ok_or_else(not_found)?;
+    let config_yaml = config_map
         .data
         .as_ref()
         .and_then(|data| data.get("config.yaml"))
-        .and_then(|cfg| serde_yaml::from_str::<TunnelConfig>(cfg).ok())
-        .ok_or_else(|| anyhow!("no data"))?;
-
-    let clustertunnels = ct_api.list(&ListParams::default()).await?;
-    let Some(clustertunnel) = clustertunnels.items.first() else {
-        return Err(anyhow!("no cluster tunnel available").into());
-    };
+        .ok_or_else(not_found)?;
+    let mut config = TunnelConfig::from_yaml(config_yaml)?;
 
     let cloudflare_creds =
-        get_credentials(ctx.clone(), &ns, &clustertunnel.spec.cloudflare).await?;
-    let cloudflare_client = CloudflareClient::new(
+        get_credentials(ctx.clone(), &operator_ns, &clustertunnel.spec.cloudflare).await?;
+    let cloudflare_client = ctx.cloudflare_client(
         clustertunnel.spec.cloudflare.account_id.clone(),
         cloudflare_creds,
     )?;
@@ -105,9 +593,181 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
         match event {
             finalizer::Event::Apply(obj) => {
                 let Some(spec) = obj.spec.as_ref() else {
-                    return Ok(Action::requeue(Duration::from_secs(3600)));
+                    return Ok(Action::requeue(ctx.requeue_interval));
+                };
+
+                let origin_request =
+                    apply_policies(ctx.clone(), &ing_ns, &ingress_key, obj.labels()).await?;
+                let overrides = origin_request_from_annotations(obj.annotations());
+                // http/2 to the origin needs tls unless the service port asks for h2c
+                let http2_origin = overrides
+                    .as_ref()
+                    .is_some_and(|overrides| overrides.http_2_origin == Some(true));
+                let origin_request = match overrides {
+                    Some(overrides) => Some(origin_request.unwrap_or_default().merge(&overrides)),
+                    None => origin_request,
+                };
+
+                // without the annotation the scheme follows the service port
+                let backend_protocol = obj
+                    .annotations()
+                    .get(ANNOTATION_BACKEND_PROTOCOL)
+                    .map(|protocol| protocol.parse::<BackendProtocol>())
+                    .transpose()?;
+
+                let mut ca_files = BTreeMap::new();
+                let origin_request = match obj.annotations().get(ANNOTATION_ORIGIN_CA_SECRET) {
+                    Some(certificate) => {
+                        let ca = origin_ca(ctx.clone(), &ing_ns, certificate).await?;
+                        // the digest changes the path on renewal so cloudflared gets restarted
+                        let file = format!(
+                            "ca-{ing_ns}-{certificate}-{}.pem",
+                            &sha256::digest(&ca)[..8]
+                        );
+                        let ca_pool = format!("/config/{file}");
+                        ca_files.insert(file, ca);
+
+                        Some(OriginRequest {
+                            ca_pool: Some(ca_pool),
+                            ..origin_request.unwrap_or_default()
+                        })
+                    }
+                    None => origin_request,
+                };
+
+                // e.g. media servers that only answer to their internal name
+                let origin_request = match obj.annotations().get(ANNOTATION_UPSTREAM_HOST_HEADER) {
+                    Some(host) => Some(OriginRequest {
+                        http_host_header: Some(host.clone()),
+                        ..origin_request.unwrap_or_default()
+                    }),
+                    None => origin_request,
                 };
 
+                let access_protected = obj
+                    .annotations()
+                    .get(ANNOTATION_ACCESS_PROTECTED)
+                    .is_some_and(|value| value == "true");
+                let team_domain = clustertunnel.spec.cloudflare.team_domain.as_deref();
+                // cloudflared rejects every jwt when it has no audience to check
+                // against, the tags come from the annotation or the Access apps of
+                // the hosts
+                let mut aud_tags: Vec<String> = obj
+                    .annotations()
+                    .get(ANNOTATION_ACCESS_AUD_TAG)
+                    .map(|tags| {
+                        tags.split(',')
+                            .map(str::trim)
+                            .filter(|tag| !tag.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if access_protected && team_domain.is_some() {
+                    for host in spec
+                        .rules
+                        .iter()
+                        .flatten()
+                        .filter_map(|rule| rule.host.as_ref())
+                    {
+                        let zone_id = clustertunnel
+                            .zone_id(ctx.clone(), &cloudflare_client, host)
+                            .await?;
+                        if let Some(app) = cloudflare_client
+                            .find_access_application(&zone_id, host)
+                            .await?
+                        {
+                            aud_tags.push(app.aud);
+                        }
+                    }
+                }
+                aud_tags.sort();
+                aud_tags.dedup();
+
+                let origin_request = match team_domain.filter(|_| access_protected) {
+                    Some(team_domain) if !aud_tags.is_empty() => Some(OriginRequest {
+                        access: Some(AccessConfig {
+                            required: true,
+                            team_name: team_domain
+                                .trim_end_matches(".cloudflareaccess.com")
+                                .to_string(),
+                            aud_tag: aud_tags,
+                        }),
+                        ..origin_request.unwrap_or_default()
+                    }),
+                    Some(_) => {
+                        ctx.recorder
+                            .publish(
+                                &Event {
+                                    type_: EventType::Warning,
+                                    reason: "AccessAudTagMissing".to_string(),
+                                    note: Some(format!(
+                                        "no Access application found, set {ANNOTATION_ACCESS_AUD_TAG}"
+                                    )),
+                                    action: "Reconciling".to_string(),
+                                    secondary: None,
+                                },
+                                &obj.object_ref(&()),
+                            )
+                            .await?;
+                        origin_request
+                    }
+                    None => origin_request,
+                };
+                if access_protected && team_domain.is_none() {
+                    ctx.recorder
+                        .publish(
+                            &Event {
+                                type_: EventType::Warning,
+                                reason: "AccessTeamDomainMissing".to_string(),
+                                note: Some(format!(
+                                    "clustertunnel {clustertunnel_name} has no team domain"
+                                )),
+                                action: "Reconciling".to_string(),
+                                secondary: None,
+                            },
+                            &obj.object_ref(&()),
+                        )
+                        .await?;
+                }
+
+                for warning in origin_annotation_warnings(obj.annotations()) {
+                    ctx.recorder
+                        .publish(
+                            &Event {
+                                type_: EventType::Warning,
+                                reason: "InvalidAnnotation".to_string(),
+                                note: Some(warning),
+                                action: "Reconciling".to_string(),
+                                secondary: None,
+                            },
+                            &obj.object_ref(&()),
+                        )
+                        .await?;
+                }
+
+                // cloudflared forwards the request path as is and refuses
+                // service urls with a path, so there is nothing to rewrite with
+                if let Some(rewrite_target) = obj.annotations().get(ANNOTATION_REWRITE_TARGET) {
+                    ctx.recorder
+                        .publish(
+                            &Event {
+                                type_: EventType::Warning,
+                                reason: "RewriteNotSupported".to_string(),
+                                note: Some(format!(
+                                    "cloudflared does not support path rewriting, ignoring {ANNOTATION_REWRITE_TARGET}={rewrite_target}; serve the upstream under the ingress path or set {ANNOTATION_ORIGIN_PREFIX}http-host-header instead"
+                                )),
+                                action: "Reconciling".to_string(),
+                                secondary: None,
+                            },
+                            &obj.object_ref(&()),
+                        )
+                        .await?;
+                }
+
+                // a missing service only drops its own paths
+                let mut missing_services = Vec::new();
+
                 for rule in spec.rules.iter().flatten() {
                     for ingress_path in rule
                         .http
@@ -116,72 +776,54 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
                         .iter()
                         .flatten()
                     {
-                        let path = if let Some(mut path) = ingress_path
+                        let path = ingress_path
                             .path
-                            .as_ref()
-                            .map(|p| format!("^{}", regex::escape(p)))
-                        {
-                            if ingress_path.path_type == "Exact" {
-                                path = format!("{path}\\/?$");
-                            }
-
-                            Some(path)
-                        } else {
-                            None
-                        };
+                            .as_deref()
+                            .and_then(|path| {
+                                build_path_regex(
+                                    path,
+                                    clustertunnel.path_type(&ingress_path.path_type),
+                                )
+                            });
 
                         let Some(svc) = ingress_path.backend.service.as_ref() else {
                             continue;
                         };
-
-                        let Some(svc_port) = svc.port.as_ref() else {
-                            continue;
+                        let backend = match backend_service(
+                            &svc_api,
+                            svc,
+                            &ing_ns,
+                            backend_protocol,
+                            http2_origin,
+                        )
+                        .await
+                        {
+                            Err(Error::ServiceNotFound { name }) => {
+                                warn!("skipping path, service {name} not found");
+                                missing_services.push(name);
+                                None
+                            }
+                            backend => backend?,
                         };
-
-                        let port = if let Some(port) = svc_port.number {
-                            port
-                        } else if let Some(name) = svc_port.name.as_ref() {
-                            let svc = svc_api.get(&svc.name).await?;
-                            let Some(svc_spec) = svc.spec.as_ref() else {
-                                continue;
-                            };
-                            let Some(port) = svc_spec.ports.iter().flatten().find_map(|svc_port| {
-                                (svc_port.name == Some(name.to_string())).then(|| svc_port.port)
-                            }) else {
-                                continue;
-                            };
-
-                            port
-                        } else {
+                        let Some((service, protocol)) = backend else {
                             continue;
                         };
 
-                        let service = format!(
-                            "http://{}.{}.svc:{}",
-                            svc.name,
-                            obj.namespace().unwrap_or_else(|| "default".to_string()),
-                            port
-                        );
-
                         let ing = TunnelIngress {
                             hostname: rule.host.clone(),
                             path,
                             service: service.clone(),
-                            origin_request: None,
+                            origin_request: protocol.apply_defaults(origin_request.clone()),
                         };
 
-                        if let Some(index) =
-                            config.ingress.iter().position(|ing| ing.service == service)
+                        if let Some(index) = config
+                            .ingress
+                            .iter()
+                            .position(|ing| !is_catch_all(ing) && ing.service == service)
                         {
                             config.ingress[index] = ing
-                        } else if config.ingress.len() == 0 {
-                            config.ingress.push(ing);
-                            config.ingress.push(TunnelIngress {
-                                service: "http_status:404".to_string(),
-                                ..TunnelIngress::default()
-                            });
                         } else {
-                            config.ingress.insert(config.ingress.len() - 1, ing);
+                            insert_route(&mut config, ing);
                         }
                     }
 
@@ -190,98 +832,429 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
                         None => "@".to_string(),
                     };
 
+                    let zone_id = clustertunnel
+                        .zone_id(ctx.clone(), &cloudflare_client, &hostname)
+                        .await?;
+
                     let dns_record = cloudflare_client
-                        .find_dns_record(&clustertunnel.spec.cloudflare.zone_id, &hostname)
+                        .find_dns_record(&zone_id, &hostname)
                         .await?;
 
                     let cname = format!("{}.cfargotunnel.com", config.tunnel);
                     match dns_record {
-                        Some(record) => match record.content {
-                            DnsContent::CNAME { content } if content == cname => {
+                        Some(record) => match &record.content {
+                            DnsContent::CNAME { content } if *content == cname => {
+                                ctx.metrics
+                                    .cloudflare_dns_skip_total
+                                    .with_label_values(&[&tunnel_name, &zone_id])
+                                    .inc();
                                 continue;
                             }
                             _ => {
                                 cloudflare_client
                                     .update_dns_record(
-                                        &clustertunnel.spec.cloudflare.zone_id,
+                                        &zone_id,
                                         &record.id,
                                         &hostname,
                                         &config.tunnel,
+                                        None,
                                     )
                                     .await?;
+                                ctx.metrics.dns_records_managed_total.inc();
+                                ctx.metrics
+                                    .cloudflare_dns_update_total
+                                    .with_label_values(&[&tunnel_name, &zone_id])
+                                    .inc();
                             }
                         },
                         None => {
                             cloudflare_client
                                 .create_dns_record(
-                                    &clustertunnel.spec.cloudflare.zone_id,
+                                    &zone_id,
                                     &hostname,
-                                    &cname,
+                                    DnsContent::CNAME { content: cname },
                                 )
                                 .await?;
+                            ctx.metrics.dns_records_managed_total.inc();
+                            ctx.metrics
+                                .cloudflare_dns_create_total
+                                .with_label_values(&[&tunnel_name, &zone_id])
+                                .inc();
                         }
                     }
                 }
 
-                let config_yaml = serde_yaml::to_string(&config).unwrap();
-                let config_hash = sha256::digest(&config_yaml);
-
-                /*
-                name: Some(config_name.to_string()),
-                namespace: Some(ns.to_owned()),
-                owner_references: Some(oref.to_vec()),
-                 */
-                let config_map = ConfigMap {
-                    metadata: ObjectMeta {
-                        name: Some(config_map.name_any()),
-                        namespace: config_map.namespace(),
-                        owner_references: Some(config_map.owner_references().to_vec()),
-                        ..ObjectMeta::default()
-                    },
-                    data: Some({
-                        let mut map = BTreeMap::new();
-                        map.insert("config.yaml".to_string(), config_yaml);
-                        map
-                    }),
-                    ..config_map.clone()
-                };
+                // the default backend takes whatever none of the rules matched,
+                // so it replaces the terminal 404 rule of which a tunnel has one
+                let mut backend_owner = default_backend_owner(&config_map);
+                let default_backend = spec
+                    .default_backend
+                    .as_ref()
+                    .and_then(|backend| backend.service.as_ref());
+                let owned_elsewhere = backend_owner
+                    .as_ref()
+                    .is_some_and(|owner| *owner != ingress_key);
+                match default_backend {
+                    Some(_) if owned_elsewhere => {
+                        ctx.recorder
+                            .publish(
+                                &Event {
+                                    type_: EventType::Warning,
+                                    reason: "DefaultBackendConflict".to_string(),
+                                    note: Some(format!(
+                                        "tunnel {tunnel_name} already uses the default backend of {}",
+                                        backend_owner.as_deref().unwrap_or_default()
+                                    )),
+                                    action: "Reconciling".to_string(),
+                                    secondary: None,
+                                },
+                                &obj.object_ref(&()),
+                            )
+                            .await?;
+                    }
+                    Some(svc) => {
+                        let backend = match backend_service(
+                            &svc_api,
+                            svc,
+                            &ing_ns,
+                            backend_protocol,
+                            http2_origin,
+                        )
+                        .await
+                        {
+                            Err(Error::ServiceNotFound { name }) => {
+                                warn!("skipping default backend, service {name} not found");
+                                missing_services.push(name);
+                                None
+                            }
+                            backend => backend?,
+                        };
+                        if let Some((service, protocol)) = backend {
+                            insert_route(
+                                &mut config,
+                                TunnelIngress {
+                                    service,
+                                    origin_request: protocol
+                                        .apply_defaults(origin_request.clone()),
+                                    ..TunnelIngress::default()
+                                },
+                            );
+                            backend_owner = Some(ingress_key.clone());
+                        }
+                    }
+                    // the default backend was dropped from the spec
+                    None if backend_owner.as_ref() == Some(&ingress_key) => {
+                        config.ingress.retain(|ing| !is_catch_all(ing));
+                        ensure_catch_all(&mut config);
+                        backend_owner = None;
+                    }
+                    None => {}
+                }
 
-                cm_api
-                    .patch(
-                        &config_map.name_any(),
-                        &PatchParams::apply(OPERATOR_MANAGER),
-                        &Patch::Apply(&config_map),
+                // cleared again once every service exists
+                let status = (!missing_services.is_empty()).then_some(STATUS_SERVICE_NOT_FOUND);
+                if obj.annotations().get(ANNOTATION_STATUS).map(String::as_str) != status {
+                    let patch = serde_json::json!({
+                        "metadata": {
+                            "annotations": {
+                                ANNOTATION_STATUS: status,
+                            }
+                        }
+                    });
+
+                    ing_api
+                        .patch(
+                            &obj.name_any(),
+                            &PatchParams::apply(OPERATOR_MANAGER),
+                            &Patch::Merge(&patch),
+                        )
+                        .await?;
+                }
+
+                // an owner reference would get the ingress garbage collected
+                // with its tunnel, the uid only records the link
+                if let Some(uid) = clustertunnel
+                    .uid()
+                    .filter(|uid| obj.annotations().get(ANNOTATION_CLUSTER_TUNNEL_UID) != Some(uid))
+                {
+                    let patch = serde_json::json!({
+                        "metadata": {
+                            "annotations": {
+                                ANNOTATION_CLUSTER_TUNNEL_UID: uid,
+                            }
+                        }
+                    });
+
+                    ing_api
+                        .patch(
+                            &obj.name_any(),
+                            &PatchParams::apply(OPERATOR_MANAGER),
+                            &Patch::Merge(&patch),
+                        )
+                        .await?;
+
+                    ctx.recorder
+                        .publish(
+                            &Event {
+                                type_: EventType::Normal,
+                                reason: "ClusterTunnelLinked".to_string(),
+                                note: Some(format!(
+                                    "{ANNOTATION_CLUSTER_TUNNEL_UID}={uid} ({clustertunnel_name})"
+                                )),
+                                action: "Reconciling".to_string(),
+                                secondary: None,
+                            },
+                            &obj.object_ref(&()),
+                        )
+                        .await?;
+                }
+
+                if let Some(policy_name) = obj.annotations().get(ANNOTATION_ACCESS_POLICY) {
+                    let include = get_policy(ctx.clone(), &ing_ns, policy_name)
+                        .await?
+                        .ok_or_else(|| Error::TunnelPolicyNotFound {
+                            name: format!("{ing_ns}/{policy_name}"),
+                        })?
+                        .access_include();
+                    if include.is_empty() {
+                        return Err(Error::InvalidSpec(format!(
+                            "tunnel policy {policy_name} has no accessInclude rules"
+                        )));
+                    }
+
+                    let mut app_ids = access_application_ids(&obj);
+                    for host in spec
+                        .rules
+                        .iter()
+                        .flatten()
+                        .filter_map(|rule| rule.host.as_ref())
+                    {
+                        let zone_id = clustertunnel
+                            .zone_id(ctx.clone(), &cloudflare_client, host)
+                            .await?;
+
+                        // an application the operator didn't create is left to its owner
+                        match cloudflare_client
+                            .find_access_application(&zone_id, host)
+                            .await?
+                        {
+                            Some(app) if app_ids.contains(&format!("{zone_id}/{}", app.id)) => {}
+                            Some(app) => warn!(
+                                "access application {} for {host} is not managed by the operator",
+                                app.id
+                            ),
+                            None => {
+                                info!("creating access application for {host}");
+                                let app = cloudflare_client
+                                    .create_access_application(&zone_id, host)
+                                    .await?;
+                                cloudflare_client
+                                    .create_access_policy(&zone_id, &app.id, policy_name, &include)
+                                    .await?;
+                                if !is_dry_run_id(&app.id) {
+                                    app_ids.push(format!("{zone_id}/{}", app.id));
+                                }
+                            }
+                        }
+                    }
+
+                    if app_ids != access_application_ids(&obj) {
+                        let patch = serde_json::json!({
+                            "metadata": {
+                                "annotations": {
+                                    ANNOTATION_ACCESS_APPLICATION_ID: app_ids.join(","),
+                                }
+                            }
+                        });
+
+                        ing_api
+                            .patch(
+                                &obj.name_any(),
+                                &PatchParams::apply(OPERATOR_MANAGER),
+                                &Patch::Merge(&patch),
+                            )
+                            .await?;
+                    }
+                }
+
+                if let Some(pool_id) = load_balancer_pool_id(clustertunnel) {
+                    // the pool is shared by every ingress of the tunnel
+                    let others =
+                        other_managed_ingresses(&ctx.kube_cli, &config_map, &ingress_key).await?;
+                    let sharing: Vec<&Ingress> =
+                        others.iter().chain(std::iter::once(&*obj)).collect();
+                    sync_pool_monitor(
+                        &ctx,
+                        cloudflare_client.as_ref(),
+                        &pool_id,
+                        &sharing,
+                        &obj,
                     )
                     .await?;
 
-                patch_deployment(&deploy_api, config_hash).await?;
+                    for host in spec
+                        .rules
+                        .iter()
+                        .flatten()
+                        .filter_map(|rule| rule.host.as_ref())
+                    {
+                        let zone_id = clustertunnel
+                            .zone_id(ctx.clone(), &cloudflare_client, host)
+                            .await?;
+
+                        let load_balancers = cloudflare_client.list_load_balancers(&zone_id).await?;
+                        if !load_balancers.iter().any(|lb| lb.name == *host) {
+                            info!("creating load balancer for {host}");
+                            cloudflare_client
+                                .create_load_balancer(&zone_id, host, &pool_id)
+                                .await?;
+                        }
+                    }
+                }
 
-                let mut ing = ing_api.get_status(&obj.name_any()).await?;
+                let config_yaml = config.to_yaml()?;
+                let config_hash = compute_config_hash(&config)?;
+                let mut data = config_map_data(&config, config_yaml, config_map.data.as_ref());
+                data.extend(ca_files);
 
-                ing.status = Some(IngressStatus {
-                    load_balancer: Some(IngressLoadBalancerStatus {
-                        ingress: Some(vec![IngressLoadBalancerIngress {
-                            hostname: Some(format!("{}.cfargotunnel.com", config.tunnel)),
-                            ..IngressLoadBalancerIngress::default()
-                        }]),
-                    }),
-                });
+                let mut ingresses = managed_ingresses(&config_map);
+                let ingress_added = ingresses.insert(ingress_key.clone());
+                let owner_changed = backend_owner != default_backend_owner(&config_map);
 
+                if config_map.data.as_ref() != Some(&data) || ingress_added || owner_changed {
+                    let config_map = ConfigMap {
+                        metadata: ObjectMeta {
+                            name: Some(config_map.name_any()),
+                            namespace: config_map.namespace(),
+                            owner_references: Some(config_map.owner_references().to_vec()),
+                            annotations: Some(managed_ingresses_annotations(
+                                &ingresses,
+                                backend_owner.as_ref(),
+                            )),
+                            ..ObjectMeta::default()
+                        },
+                        data: Some(data),
+                        ..config_map.clone()
+                    };
+
+                    cm_api
+                        .patch(
+                            &config_map.name_any(),
+                            &PatchParams::apply(OPERATOR_MANAGER),
+                            &Patch::Apply(&config_map),
+                        )
+                        .await?;
+                    ctx.metrics
+                        .configmap_write_total
+                        .with_label_values(&[&tunnel_name])
+                        .inc();
+                } else {
+                    ctx.metrics
+                        .configmap_write_skipped_total
+                        .with_label_values(&[&tunnel_name])
+                        .inc();
+                }
+
+                publish_config(ctx.clone(), clustertunnel, &tunnel_name, &config, config_hash)
+                    .await?;
+
+                // external-dns reads the published hostnames back from the status
+                let mut hostnames: Vec<String> = spec
+                    .rules
+                    .iter()
+                    .flatten()
+                    .filter_map(|rule| rule.host.clone())
+                    .collect();
+                hostnames.sort();
+                hostnames.dedup();
+                if hostnames.is_empty() {
+                    hostnames.push(format!("{}.cfargotunnel.com", config.tunnel));
+                }
+
+                let status = serde_json::json!({
+                    "status": {
+                        "loadBalancer": {
+                            "ingress": hostnames
+                                .iter()
+                                .map(|hostname| serde_json::json!({ "hostname": hostname }))
+                                .collect::<Vec<_>>(),
+                        }
+                    }
+                });
                 ing_api
                     .patch_status(
-                        &ing.name_any(),
+                        &obj.name_any(),
                         &PatchParams::apply(OPERATOR_MANAGER),
-                        &Patch::Merge(ing),
+                        &Patch::Merge(&status),
                     )
                     .await?;
 
-                Ok(Action::requeue(Duration::from_secs(3600)))
+                Ok(Action::requeue(ctx.requeue_interval))
             }
             finalizer::Event::Cleanup(obj) => {
+                release_policies(ctx.clone(), &ing_ns, &ingress_key).await?;
+
+                let dns_cleanup_policy = obj
+                    .annotations()
+                    .get(ANNOTATION_DNS_CLEANUP_POLICY)
+                    .map(|policy| policy.parse::<DnsCleanupPolicy>())
+                    .transpose()?
+                    .or(clustertunnel.spec.default_dns_cleanup_policy)
+                    .unwrap_or_default();
+
                 let Some(spec) = obj.spec.as_ref() else {
-                    return Ok(Action::requeue(Duration::from_secs(3600)));
+                    return Ok(Action::requeue(ctx.requeue_interval));
                 };
 
+                // only the applications the operator created, found by their stored id
+                for app_id in access_application_ids(&obj) {
+                    let Some((zone_id, app_id)) = app_id.split_once('/') else {
+                        continue;
+                    };
+                    cloudflare_client
+                        .delete_access_application(zone_id, app_id)
+                        .await?;
+                }
+
+                if let Some(pool_id) = load_balancer_pool_id(clustertunnel) {
+                    // load balancers are named after their host, which other
+                    // ingresses of the tunnel may still serve
+                    let others =
+                        other_managed_ingresses(&ctx.kube_cli, &config_map, &ingress_key).await?;
+                    let shared_hosts: HashSet<&String> =
+                        others.iter().flat_map(ingress_hosts).collect();
+
+                    for host in spec
+                        .rules
+                        .iter()
+                        .flatten()
+                        .filter_map(|rule| rule.host.as_ref())
+                        .filter(|host| !shared_hosts.contains(host))
+                    {
+                        let zone_id = clustertunnel
+                            .zone_id(ctx.clone(), &cloudflare_client, host)
+                            .await?;
+
+                        for lb in cloudflare_client.list_load_balancers(&zone_id).await? {
+                            if lb.name == *host && lb.default_pools.contains(&pool_id) {
+                                cloudflare_client
+                                    .delete_load_balancer(&zone_id, &lb.id)
+                                    .await?;
+                            }
+                        }
+                    }
+
+                    let sharing: Vec<&Ingress> = others.iter().collect();
+                    sync_pool_monitor(
+                        &ctx,
+                        cloudflare_client.as_ref(),
+                        &pool_id,
+                        &sharing,
+                        &obj,
+                    )
+                    .await?;
+                }
+
                 for rule in spec.rules.iter().flatten() {
                     for ingress_path in rule
                         .http
@@ -294,11 +1267,28 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
                             continue;
                         };
 
-                        config.ingress = config
-                            .ingress
-                            .into_iter()
-                            .filter(|ing| !ing.service.contains(&svc.name))
-                            .collect();
+                        // only the entries this ingress produced, other ingresses may
+                        // route to a service with a similar name
+                        let path = ingress_path
+                            .path
+                            .as_deref()
+                            .and_then(|path| {
+                                build_path_regex(
+                                    path,
+                                    clustertunnel.path_type(&ingress_path.path_type),
+                                )
+                            });
+                        let service_host =
+                            format!("://{}.{ing_ns}.svc:", encode_host_label(&svc.name));
+                        config.ingress.retain(|ing| {
+                            ing.hostname != rule.host
+                                || ing.path != path
+                                || !ing.service.contains(&service_host)
+                        });
+                    }
+
+                    if dns_cleanup_policy == DnsCleanupPolicy::Retain {
+                        continue;
                     }
 
                     let hostname = match &rule.host {
@@ -306,45 +1296,88 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
                         None => "@".to_string(),
                     };
 
+                    let zone_id = clustertunnel
+                        .zone_id(ctx.clone(), &cloudflare_client, &hostname)
+                        .await?;
+
                     let Some(dns_record) = cloudflare_client
-                        .find_dns_record(&clustertunnel.spec.cloudflare.zone_id, &hostname)
+                        .find_dns_record(&zone_id, &hostname)
                         .await?
                     else {
                         continue;
                     };
 
                     cloudflare_client
-                        .delete_dns_record(&clustertunnel.spec.cloudflare.zone_id, &dns_record.id)
+                        .delete_dns_record(&zone_id, &dns_record.id)
                         .await?;
+                    ctx.metrics
+                        .cloudflare_dns_delete_total
+                        .with_label_values(&[&tunnel_name, &zone_id])
+                        .inc();
                 }
 
-                let config_yaml = serde_yaml::to_string(&config).unwrap();
-                let config_hash = sha256::digest(&config_yaml);
-
-                let config_map = ConfigMap {
-                    metadata: ObjectMeta {
-                        managed_fields: None,
-                        ..config_map.metadata.clone()
-                    },
-                    data: Some({
-                        let mut map = BTreeMap::new();
-                        map.insert("config.yaml".to_string(), config_yaml);
-                        map
-                    }),
-                    ..config_map.clone()
-                };
+                // hand the terminal rule back to the 404
+                let owns_default_backend =
+                    default_backend_owner(&config_map).as_ref() == Some(&ingress_key);
+                if owns_default_backend {
+                    config.ingress.retain(|ing| !is_catch_all(ing));
+                }
+                ensure_catch_all(&mut config);
 
-                cm_api
-                    .patch(
-                        &config_map.name_any(),
-                        &PatchParams::apply(OPERATOR_MANAGER),
-                        &Patch::Apply(&config_map),
-                    )
-                    .await?;
+                let config_yaml = config.to_yaml()?;
+                let config_hash = compute_config_hash(&config)?;
+                let data = config_map_data(&config, config_yaml, config_map.data.as_ref());
+
+                let mut ingresses = managed_ingresses(&config_map);
+                let ingress_removed = ingresses.remove(&ingress_key);
+
+                if config_map.data.as_ref() != Some(&data)
+                    || ingress_removed
+                    || owns_default_backend
+                {
+                    let mut annotations = config_map.annotations().clone();
+                    if owns_default_backend {
+                        annotations.remove(ANNOTATION_DEFAULT_BACKEND_OWNER);
+                    }
+                    annotations.extend(managed_ingresses_annotations(
+                        &ingresses,
+                        default_backend_owner(&config_map)
+                            .as_ref()
+                            .filter(|_| !owns_default_backend),
+                    ));
+
+                    let config_map = ConfigMap {
+                        metadata: ObjectMeta {
+                            managed_fields: None,
+                            annotations: Some(annotations),
+                            ..config_map.metadata.clone()
+                        },
+                        data: Some(data),
+                        ..config_map.clone()
+                    };
+
+                    cm_api
+                        .patch(
+                            &config_map.name_any(),
+                            &PatchParams::apply(OPERATOR_MANAGER),
+                            &Patch::Apply(&config_map),
+                        )
+                        .await?;
+                    ctx.metrics
+                        .configmap_write_total
+                        .with_label_values(&[&tunnel_name])
+                        .inc();
+                } else {
+                    ctx.metrics
+                        .configmap_write_skipped_total
+                        .with_label_values(&[&tunnel_name])
+                        .inc();
+                }
 
-                patch_deployment(&deploy_api, config_hash).await?;
+                publish_config(ctx.clone(), clustertunnel, &tunnel_name, &config, config_hash)
+                    .await?;
 
-                Ok(Action::requeue(Duration::from_secs(3600)))
+                Ok(Action::requeue(ctx.requeue_interval))
             }
         }
     })
@@ -355,19 +1388,347 @@ pub async fn reconcile(obj: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, E
 pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
     let client = ctx.kube_cli.clone();
 
-    let cfg = watcher::Config::default();
-    let ing_api: Api<Ingress> = Api::all(client.clone());
+    let cfg = ctx.watcher_config();
+    let ing_api: Api<Ingress> = ctx.watched_api();
+    let ct_api: Api<ClusterTunnel> = Api::all(client.clone());
+
+    let operator_ns = ctx.operator_namespace.clone();
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), &operator_ns);
+
+    let (tunnels, tunnels_writer) = reflector::store::<ClusterTunnel>();
+    let tunnels_reflector = reflector(
+        tunnels_writer,
+        watcher(ct_api.clone(), ctx.cluster_watcher_config()).default_backoff(),
+    )
+    .for_each(|_| future::ready(()));
 
-    Controller::new(ing_api, cfg)
+    let controller = Controller::new(ing_api, cfg);
+    let ingresses = controller.store();
+
+    // a class the operator starts or stops managing changes which ingresses it serves
+    let class_changes = stream::unfold(
+        ctx.ingress_classes_changed.subscribe(),
+        |mut changes| async move {
+            changes.changed().await.ok()?;
+            Some(((), changes))
+        },
+    );
+
+    let secret_ingresses = ingresses.clone();
+    let certificate_ingresses = ingresses.clone();
+    let ingress_controller = controller
+        .watches(ct_api, ctx.cluster_watcher_config(), move |ct| {
+            let ct_name = ct.name_any();
+            let ct_uid = ct.uid();
+            // linked ingresses are found by uid, ones not reconciled yet by name
+            ingresses
+                .state()
+                .into_iter()
+                .filter(move |ing| {
+                    clustertunnel_name(ing).is_some_and(|name| *name == ct_name)
+                        || ct_uid.is_some()
+                            && ing.annotations().get(ANNOTATION_CLUSTER_TUNNEL_UID)
+                                == ct_uid.as_ref()
+                })
+                .map(|ing| ObjectRef::from_obj(ing.as_ref()))
+        })
+        .watches(secret_api, watcher::Config::default(), move |secret| {
+            let secret_name = secret.name_any();
+            let secret_ns = secret.namespace().unwrap_or_default();
+            let ct_names: Vec<String> = tunnels
+                .state()
+                .into_iter()
+                .filter(|ct| ct.references_secret(&operator_ns, &secret_ns, &secret_name))
+                .map(|ct| ct.name_any())
+                .collect();
+            secret_ingresses
+                .state()
+                .into_iter()
+                .filter(move |ing| {
+                    clustertunnel_name(ing).is_some_and(|name| ct_names.contains(name))
+                })
+                .map(|ing| ObjectRef::from_obj(ing.as_ref()))
+        })
+        // cert-manager renewals rotate the origin CA
+        .watches(
+            ctx.watched_api::<Secret>(),
+            watcher::Config::default().fields("type=kubernetes.io/tls"),
+            move |secret| {
+                let secret_ns = secret.namespace();
+                let certificate = secret
+                    .annotations()
+                    .get(ANNOTATION_CERTIFICATE_NAME)
+                    .cloned();
+                certificate_ingresses
+                    .state()
+                    .into_iter()
+                    .filter(move |ing| {
+                        certificate.is_some()
+                            && ing.namespace() == secret_ns
+                            && ing.annotations().get(ANNOTATION_ORIGIN_CA_SECRET)
+                                == certificate.as_ref()
+                    })
+                    .map(|ing| ObjectRef::from_obj(ing.as_ref()))
+            },
+        )
+        .reconcile_all_on(class_changes)
         .shutdown_on_signal()
-        .run(reconcile, error_policy, ctx.clone())
-        .for_each(|res| async move {
-            match res {
-                Ok(o) => info!("reconciled ingress {o:?}"),
-                Err(e) => warn!("reconcile ingress failed: {e:?}"),
+        .run(
+            |obj, ctx| with_correlation_id(reconcile(obj, ctx)),
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(o) => {
+                        ctx.metrics.reconcile_success("Ingress");
+                        reset_failures(&ctx, &o.0);
+                        info!("reconciled ingress {o:?}")
+                    }
+                    Err(e) => warn!("reconcile ingress failed: {e:?}"),
+                }
             }
-        })
-        .await;
+        });
+
+    future::join(tunnels_reflector, ingress_controller).await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::networking::v1::Ingress;
+    use kube::ResourceExt;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{
+        build_path_regex, encode_host_label, ensure_catch_all, insert_ingress, insert_route,
+        is_catch_all, pool_monitors, BackendProtocol, NOT_FOUND_SERVICE,
+    };
+    use crate::{
+        cloudflare::{OriginRequest, TunnelConfig, TunnelIngress},
+        controller::utils::ANNOTATION_LOAD_BALANCER_HEALTH_MONITOR,
+    };
+
+    #[test]
+    fn exact_path() {
+        assert_eq!(
+            build_path_regex("/foo", "Exact").as_deref(),
+            Some("^/foo\\/?$")
+        );
+    }
+
+    #[test]
+    fn exact_path_with_trailing_slash() {
+        assert_eq!(
+            build_path_regex("/foo/", "Exact").as_deref(),
+            Some("^/foo\\/?$")
+        );
+        assert_eq!(build_path_regex("/", "Exact").as_deref(), Some("^\\/?$"));
+    }
+
+    #[test]
+    fn prefix_path() {
+        assert_eq!(
+            build_path_regex("/api/", "Prefix").as_deref(),
+            Some("^/api(\\/|$)")
+        );
+        assert_eq!(
+            build_path_regex("/api", "Prefix").as_deref(),
+            Some("^/api(\\/|$)")
+        );
+        assert_eq!(build_path_regex("/", "Prefix").as_deref(), Some("^(\\/|$)"));
+    }
+
+    #[test]
+    fn prefix_path_matches_whole_elements() {
+        let re = regex::Regex::new(&build_path_regex("/api", "Prefix").unwrap()).unwrap();
+        assert!(re.is_match("/api"));
+        assert!(re.is_match("/api/"));
+        assert!(re.is_match("/api/v1"));
+        assert!(!re.is_match("/apiv2"));
+
+        let re = regex::Regex::new(&build_path_regex("/api", "Exact").unwrap()).unwrap();
+        assert!(re.is_match("/api"));
+        assert!(re.is_match("/api/"));
+        assert!(!re.is_match("/api/v1"));
+    }
+
+    #[test]
+    fn empty_path() {
+        assert_eq!(build_path_regex("", "Prefix"), None);
+        assert_eq!(build_path_regex("", "Exact"), None);
+    }
+
+    #[test]
+    fn path_with_regex_metacharacters() {
+        assert_eq!(
+            build_path_regex("/foo/bar?query=1", "Prefix").as_deref(),
+            Some("^/foo/bar\\?query=1(\\/|$)")
+        );
+        assert_eq!(
+            build_path_regex("/v1.0/(x)", "Exact").as_deref(),
+            Some("^/v1\\.0/\\(x\\)\\/?$")
+        );
+    }
+
+    #[test]
+    fn http2_origin_config_key() {
+        let origin_request = BackendProtocol::H2.apply_defaults(None).unwrap();
+        let value = serde_json::to_value(&origin_request).unwrap();
+        assert_eq!(value["http2Origin"], true);
+    }
+
+    #[test]
+    fn grpc_backend() {
+        let protocol = "grpc".parse::<BackendProtocol>().unwrap();
+        assert_eq!(protocol.scheme(), "https");
+
+        let origin_request = protocol.apply_defaults(None).unwrap();
+        assert_eq!(origin_request.http_2_origin, Some(true));
+        assert_eq!(origin_request.no_tls_verify, Some(true));
+
+        // internal CAs are trusted through the origin annotations instead
+        let origin_request = protocol
+            .apply_defaults(Some(OriginRequest {
+                no_tls_verify: Some(false),
+                ..OriginRequest::default()
+            }))
+            .unwrap();
+        assert_eq!(origin_request.no_tls_verify, Some(false));
+    }
+
+    #[test]
+    fn routes_go_before_the_default_backend() {
+        let mut config = TunnelConfig::default();
+        insert_route(
+            &mut config,
+            TunnelIngress {
+                service: "http://default.default.svc:80".to_string(),
+                ..TunnelIngress::default()
+            },
+        );
+        insert_route(
+            &mut config,
+            TunnelIngress {
+                hostname: Some("app.example.com".to_string()),
+                service: "http://app.default.svc:80".to_string(),
+                ..TunnelIngress::default()
+            },
+        );
+
+        let services: Vec<&str> = config
+            .ingress
+            .iter()
+            .map(|ing| ing.service.as_str())
+            .collect();
+        assert_eq!(
+            services,
+            ["http://app.default.svc:80", "http://default.default.svc:80"]
+        );
+    }
+
+    #[test]
+    fn default_backend_replaces_the_404() {
+        let mut config = TunnelConfig::default();
+        ensure_catch_all(&mut config);
+        insert_route(
+            &mut config,
+            TunnelIngress {
+                service: "http://default.default.svc:80".to_string(),
+                ..TunnelIngress::default()
+            },
+        );
+        assert_eq!(config.ingress.len(), 1);
+        assert_eq!(config.ingress[0].service, "http://default.default.svc:80");
+
+        config.ingress.retain(|ing| !is_catch_all(ing));
+        ensure_catch_all(&mut config);
+        assert_eq!(config.ingress.len(), 1);
+        assert_eq!(config.ingress[0].service, NOT_FOUND_SERVICE);
+    }
+
+    #[test]
+    fn catch_all_stays_last() {
+        let hosts = [None, Some("a.example.com"), Some("b.example.com")];
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..200 {
+            let mut config = TunnelConfig::default();
+            for _ in 0..rng.gen_range(1..20) {
+                let hostname = hosts[rng.gen_range(0..hosts.len())].map(str::to_string);
+                let service = format!("http://svc-{}.default.svc:80", rng.gen_range(0..3));
+                match rng.gen_range(0..3) {
+                    0 => insert_route(
+                        &mut config,
+                        TunnelIngress {
+                            hostname,
+                            service,
+                            ..TunnelIngress::default()
+                        },
+                    ),
+                    1 => insert_ingress(
+                        &mut config,
+                        TunnelIngress {
+                            hostname,
+                            service,
+                            ..TunnelIngress::default()
+                        },
+                    ),
+                    _ => {
+                        config
+                            .ingress
+                            .retain(|ing| ing.hostname != hostname || ing.service != service);
+                        ensure_catch_all(&mut config);
+                    }
+                }
+
+                assert!(is_catch_all(config.ingress.last().unwrap()));
+                assert!(config.ingress[..config.ingress.len() - 1]
+                    .iter()
+                    .all(|i| !is_catch_all(i)));
+            }
+        }
+    }
+
+    #[test]
+    fn service_name_is_escaped() {
+        assert_eq!(encode_host_label("my-svc"), "my-svc");
+        assert_eq!(encode_host_label("evil/x:1@y"), "evil%2Fx%3A1%40y");
+    }
+
+    fn ingress_with_monitor(monitor: Option<&str>) -> Ingress {
+        let mut ing = Ingress::default();
+        if let Some(monitor) = monitor {
+            ing.annotations_mut().insert(
+                ANNOTATION_LOAD_BALANCER_HEALTH_MONITOR.to_string(),
+                monitor.to_string(),
+            );
+        }
+        ing
+    }
+
+    #[test]
+    fn pool_monitor_is_shared() {
+        let a = ingress_with_monitor(Some("monitor"));
+        let b = ingress_with_monitor(None);
+        let c = ingress_with_monitor(Some("monitor"));
+
+        assert_eq!(
+            pool_monitors(&[&a, &b, &c]).into_iter().collect::<Vec<_>>(),
+            ["monitor"]
+        );
+        // nothing left to attach, so the pool's monitor is detached
+        assert!(pool_monitors(&[&b]).is_empty());
+    }
+
+    #[test]
+    fn conflicting_pool_monitors() {
+        let a = ingress_with_monitor(Some("a"));
+        let b = ingress_with_monitor(Some("b"));
+
+        assert_eq!(pool_monitors(&[&a, &b]).len(), 2);
+    }
+}
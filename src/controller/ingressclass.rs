@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use k8s_openapi::api::networking::v1::IngressClass;
+use kube::{runtime::watcher, Api, ResourceExt};
+use tracing::{info, warn};
+
+use crate::context::Context;
+
+// spec.controller is immutable, changing it would break upgrades of existing classes
+pub const INGRESS_CONTROLLER: &'static str = "cloudflare-tunnels-operator.io/ingress-controller";
+
+fn is_managed(class: &IngressClass) -> bool {
+    class
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.controller.as_deref())
+        == Some(INGRESS_CONTROLLER)
+}
+
+fn apply(ctx: &Context, class: &IngressClass) {
+    if is_managed(class) {
+        if ctx.ingress_classes.insert(class.name_any()) {
+            info!("watching ingress class {}", class.name_any());
+            ctx.ingress_classes_changed.send_replace(());
+        }
+    } else {
+        remove(ctx, class);
+    }
+}
+
+fn remove(ctx: &Context, class: &IngressClass) {
+    if ctx.ingress_classes.remove(&class.name_any()).is_some() {
+        ctx.ingress_classes_changed.send_replace(());
+    }
+}
+
+pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
+    let ic_api: Api<IngressClass> = Api::all(ctx.kube_cli.clone());
+
+    watcher(ic_api, watcher::Config::default())
+        .for_each(|event| {
+            let ctx = ctx.clone();
+            async move {
+                match event {
+                    Ok(watcher::Event::Init) => ctx.ingress_classes.clear(),
+                    Ok(watcher::Event::Apply(class) | watcher::Event::InitApply(class)) => {
+                        apply(&ctx, &class)
+                    }
+                    Ok(watcher::Event::Delete(class)) => remove(&ctx, &class),
+                    Ok(watcher::Event::InitDone) => {}
+                    Err(e) => warn!("watch ingress class failed: {e:?}"),
+                }
+            }
+        })
+        .await;
+
+    Ok(())
+}
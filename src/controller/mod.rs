@@ -1,20 +1,117 @@
 use std::{sync::Arc, time::Duration};
 
-use kube::runtime::controller::Action;
-use log::error;
+use kube::{
+    runtime::{controller::Action, finalizer, reflector::ObjectRef},
+    Resource, ResourceExt,
+};
+use rand::Rng;
+use tracing::error;
 
 use crate::{context::Context, error::Error};
 
+pub mod certmanager;
+
 pub mod clustertunnel;
 pub use clustertunnel::ClusterTunnel;
 
+pub mod cloudflareddns;
+pub use cloudflareddns::CloudflareDNS;
+
+pub mod externalsecret;
+
+mod failure;
+pub use failure::FailureTracker;
+
+pub mod httproute;
+
 pub mod ingress;
 
+pub mod ingressclass;
+
+pub mod servicemonitor;
+
+mod startup;
+pub use startup::validate_startup;
+
+pub mod tunnelpolicy;
+pub use tunnelpolicy::TunnelPolicy;
+
+pub mod tunnelroute;
+pub use tunnelroute::TunnelRoute;
+
 mod utils;
+pub use utils::operator_namespace;
 
 pub(super) const OPERATOR_MANAGER: &'static str = "cloudflare-tunnels-operator";
 
-pub(super) fn error_policy<K>(_obj: Arc<K>, err: &Error, _ctx: Arc<Context>) -> Action {
-    error!("reason: {}", err);
-    Action::requeue(Duration::from_secs(15))
+const MAX_BACKOFF_EXPONENT: u32 = 6;
+
+fn base_delay(err: &Error, ctx: &Context) -> Duration {
+    match err {
+        Error::FinalizerError(err) => match err.as_ref() {
+            finalizer::Error::ApplyFailed(err) | finalizer::Error::CleanupFailed(err) => {
+                base_delay(err, ctx)
+            }
+            _ => ctx.error_requeue_interval,
+        },
+        Error::CloudflareApiErr(..) | Error::CloudflarePermissionDenied(..) => {
+            Duration::from_secs(30)
+        }
+        // cloudflare counts requests over five minute windows
+        Error::CloudflareRateLimit(..) => Duration::from_secs(300),
+        // retrying won't help until someone fixes the credentials or the spec,
+        // and a spec change triggers a reconcile anyway
+        Error::CloudflareAuthFailed(..) | Error::InvalidSpec(_) | Error::TunnelTokenRoutes(_) => {
+            ctx.max_error_requeue_interval
+        }
+        Error::KubeError(_) => Duration::from_secs(5),
+        Error::TunnelNotReady(_) | Error::TunnelNotFound { .. } => Duration::from_secs(30),
+        Error::TunnelAlreadyExists(_) => Duration::from_secs(30),
+        Error::SecretNotFound { .. }
+        | Error::SecretKeyNotFound { .. }
+        | Error::ConfigMapNotFound { .. }
+        | Error::TunnelPolicyNotFound { .. } => Duration::from_secs(30),
+        _ => ctx.error_requeue_interval,
+    }
+}
+
+pub(super) fn error_policy<K>(obj: Arc<K>, err: &Error, ctx: Arc<Context>) -> Action
+where
+    K: Resource<DynamicType = ()>,
+{
+    error!(
+        kind = %K::kind(&()),
+        name = %obj.name_any(),
+        namespace = ?obj.namespace(),
+        error = %err,
+        "reconcile failed"
+    );
+    ctx.metrics.reconcile_error(&K::kind(&()));
+
+    let failures = ctx
+        .failures
+        .record(failure_key(&ObjectRef::from_obj(obj.as_ref())));
+    let backoff = base_delay(err, &ctx)
+        .saturating_mul(1 << failures.min(MAX_BACKOFF_EXPONENT))
+        .mul_f64(rand::thread_rng().gen_range(0.75..=1.25))
+        .min(ctx.max_error_requeue_interval);
+
+    Action::requeue(backoff)
+}
+
+pub(super) fn reset_failures<K>(ctx: &Context, obj_ref: &ObjectRef<K>)
+where
+    K: Resource<DynamicType = ()>,
+{
+    ctx.failures.reset(&failure_key(obj_ref));
+}
+
+fn failure_key<K>(obj_ref: &ObjectRef<K>) -> String
+where
+    K: Resource<DynamicType = ()>,
+{
+    match &obj_ref.namespace {
+        Some(ns) => format!("{}/{}/{}", K::kind(&()), ns, obj_ref.name),
+        None => format!("{}/{}", K::kind(&()), obj_ref.name),
+    }
 }
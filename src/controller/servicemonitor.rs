@@ -0,0 +1,88 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, OwnerReference};
+use kube::{
+    api::{Patch, PatchParams},
+    Api, CustomResource,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{context::Context, error::Error};
+
+use super::OPERATOR_MANAGER;
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "ServiceMonitor",
+    group = "monitoring.coreos.com",
+    version = "v1",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceMonitorSpec {
+    pub selector: LabelSelector,
+    pub endpoints: Vec<ServiceMonitorEndpoint>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceMonitorEndpoint {
+    pub port: String,
+    pub path: String,
+    pub interval: String,
+}
+
+// The prometheus operator is optional, so its CRD is looked up before use.
+pub async fn service_monitor_available(ctx: Arc<Context>) -> Result<bool, Error> {
+    match ctx
+        .kube_cli
+        .list_api_group_resources("monitoring.coreos.com/v1")
+        .await
+    {
+        Ok(resources) => Ok(resources
+            .resources
+            .iter()
+            .any(|resource| resource.kind == "ServiceMonitor")),
+        Err(kube::Error::Api(err)) if err.code == 404 => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn apply_service_monitor(
+    ctx: Arc<Context>,
+    ns: &str,
+    name: &str,
+    port: &str,
+    labels: &BTreeMap<String, String>,
+    owner_references: Vec<OwnerReference>,
+) -> Result<(), Error> {
+    let api: Api<ServiceMonitor> = Api::namespaced(ctx.kube_cli.clone(), ns);
+
+    let mut service_monitor = ServiceMonitor::new(
+        name,
+        ServiceMonitorSpec {
+            selector: LabelSelector {
+                match_labels: Some(labels.clone()),
+                ..LabelSelector::default()
+            },
+            endpoints: vec![ServiceMonitorEndpoint {
+                port: port.to_string(),
+                path: "/metrics".to_string(),
+                interval: "30s".to_string(),
+            }],
+        },
+    );
+    service_monitor.metadata.namespace = Some(ns.to_string());
+    service_monitor.metadata.labels = Some(labels.clone());
+    service_monitor.metadata.owner_references = Some(owner_references);
+
+    api.patch(
+        name,
+        &PatchParams::apply(OPERATOR_MANAGER),
+        &Patch::Apply(&service_monitor),
+    )
+    .await?;
+
+    Ok(())
+}
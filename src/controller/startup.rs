@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use kube::{api::ListParams, Api, ResourceExt};
+use tracing::{info, warn};
+
+use crate::{context::Context, error::Error, ClusterTunnel};
+
+// Bad Cloudflare credentials would otherwise only show up as failing
+// reconciles, so the first ClusterTunnel's token is tried before starting.
+// Tunnel tokens only let cloudflared connect, so those tunnels are skipped.
+pub async fn validate_startup(ctx: Arc<Context>) -> Result<(), Error> {
+    let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+    let clustertunnels = ct_api.list(&ListParams::default()).await?;
+    let Some(clustertunnel) = clustertunnels
+        .items
+        .iter()
+        .find(|clustertunnel| !clustertunnel.uses_tunnel_token())
+    else {
+        info!("no clustertunnel with api credentials found, skipping startup validation");
+        return Ok(());
+    };
+
+    let credentials = clustertunnel.get_credentials(ctx.clone()).await?;
+    let cf_cli = ctx.cloudflare_client(
+        clustertunnel.spec.cloudflare.account_id.clone(),
+        credentials,
+    )?;
+
+    let tunnel_name = clustertunnel
+        .spec
+        .name
+        .clone()
+        .unwrap_or_else(|| clustertunnel.name_any());
+    match cf_cli.find_tunnel(&tunnel_name).await {
+        Ok(_) => {
+            info!(
+                "cloudflare api credentials of {} are valid",
+                clustertunnel.name_any()
+            );
+            Ok(())
+        }
+        Err(err @ Error::CloudflareAuthFailed(..)) => Err(anyhow!(
+            "credentials of clustertunnel {} are invalid: {err}",
+            clustertunnel.name_any()
+        )
+        .into()),
+        // only authentication problems are fatal, anything else may be transient
+        Err(err) => {
+            warn!("startup validation failed: {err}");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{cloudflare::OriginRequest, context::Context, error::Error};
+
+use super::{utils::*, OPERATOR_MANAGER};
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "TunnelPolicy",
+    group = "cloudflare-tunnels-operator.io",
+    version = "v1alpha1",
+    namespaced,
+    status = "TunnelPolicyStatus"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelPolicySpec {
+    pub selector: Option<LabelSelector>,
+    pub origin_request: OriginRequest,
+    // who the Access policies named after this policy admit, see the
+    // cloudflare-tunnels.io/access-policy annotation
+    pub access_include: Option<Vec<AccessRule>>,
+}
+
+// Each set field becomes its own include rule of the Access policy.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessRule {
+    pub email: Option<String>,
+    pub email_domain: Option<String>,
+    pub group_id: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl AccessRule {
+    pub fn to_cloudflare(&self) -> Vec<serde_json::Value> {
+        let mut rules = vec![];
+        if let Some(email) = &self.email {
+            rules.push(serde_json::json!({ "email": { "email": email } }));
+        }
+        if let Some(domain) = &self.email_domain {
+            rules.push(serde_json::json!({ "email_domain": { "domain": domain } }));
+        }
+        if let Some(id) = &self.group_id {
+            rules.push(serde_json::json!({ "group": { "id": id } }));
+        }
+        if let Some(ip) = &self.ip {
+            rules.push(serde_json::json!({ "ip": { "ip": ip } }));
+        }
+        rules
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelPolicyStatus {
+    #[serde(default)]
+    pub affected_ingresses: Vec<String>,
+}
+
+impl TunnelPolicy {
+    fn specificity(&self) -> usize {
+        self.spec.selector.as_ref().map_or(0, |selector| {
+            selector.match_labels.as_ref().map_or(0, BTreeMap::len)
+                + selector.match_expressions.as_ref().map_or(0, Vec::len)
+        })
+    }
+
+    pub fn access_include(&self) -> Vec<serde_json::Value> {
+        self.spec
+            .access_include
+            .iter()
+            .flatten()
+            .flat_map(AccessRule::to_cloudflare)
+            .collect()
+    }
+
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.spec
+            .selector
+            .as_ref()
+            .map_or(true, |selector| selector_matches(selector, labels))
+    }
+
+    // every ingress matching a policy writes the same list, so the write is
+    // guarded by the resourceVersion it was computed from and redone on top
+    // of the concurrent update on a conflict
+    async fn set_affected(
+        &self,
+        api: &Api<TunnelPolicy>,
+        ingress_key: &str,
+        affected: bool,
+    ) -> Result<(), Error> {
+        let mut policy = self.clone();
+        loop {
+            let mut affected_ingresses = policy
+                .status
+                .as_ref()
+                .map(|status| status.affected_ingresses.clone())
+                .unwrap_or_default();
+
+            let present = affected_ingresses.iter().any(|key| key == ingress_key);
+            match (present, affected) {
+                (false, true) => affected_ingresses.push(ingress_key.to_string()),
+                (true, false) => affected_ingresses.retain(|key| key != ingress_key),
+                _ => return Ok(()),
+            }
+
+            let status = serde_json::json!({
+                "metadata": { "resourceVersion": policy.resource_version() },
+                "status": TunnelPolicyStatus { affected_ingresses }
+            });
+
+            match api
+                .patch_status(
+                    &policy.name_any(),
+                    &PatchParams::apply(OPERATOR_MANAGER),
+                    &Patch::Merge(&status),
+                )
+                .await
+            {
+                Err(kube::Error::Api(err)) if err.code == 409 => {
+                    policy = api.get(&policy.name_any()).await?;
+                }
+                result => {
+                    result?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub async fn get_policy(
+    ctx: Arc<Context>,
+    ns: &str,
+    name: &str,
+) -> Result<Option<TunnelPolicy>, Error> {
+    let policy_api: Api<TunnelPolicy> = Api::namespaced(ctx.kube_cli.clone(), ns);
+
+    Ok(policy_api.get_opt(name).await?)
+}
+
+// Resolves the origin request of every policy in `ns` selecting `labels`, with
+// more specific selectors overriding less specific ones, and records the
+// ingress in the status of the policies it is (no longer) affected by.
+pub async fn apply_policies(
+    ctx: Arc<Context>,
+    ns: &str,
+    ingress_key: &str,
+    labels: &BTreeMap<String, String>,
+) -> Result<Option<OriginRequest>, Error> {
+    let policy_api: Api<TunnelPolicy> = Api::namespaced(ctx.kube_cli.clone(), ns);
+    let policies = policy_api.list(&ListParams::default()).await?.items;
+
+    let mut matched = vec![];
+    for policy in policies {
+        let affected = policy.matches(labels);
+        policy
+            .set_affected(&policy_api, ingress_key, affected)
+            .await?;
+
+        if affected {
+            matched.push(policy);
+        }
+    }
+
+    matched.sort_by(|a, b| {
+        a.specificity()
+            .cmp(&b.specificity())
+            .then_with(|| a.name_any().cmp(&b.name_any()))
+    });
+
+    Ok(matched.iter().fold(None, |acc, policy| {
+        Some(acc.unwrap_or_default().merge(&policy.spec.origin_request))
+    }))
+}
+
+pub async fn release_policies(ctx: Arc<Context>, ns: &str, ingress_key: &str) -> Result<(), Error> {
+    let policy_api: Api<TunnelPolicy> = Api::namespaced(ctx.kube_cli.clone(), ns);
+
+    for policy in policy_api.list(&ListParams::default()).await?.items {
+        policy.set_affected(&policy_api, ingress_key, false).await?;
+    }
+
+    Ok(())
+}
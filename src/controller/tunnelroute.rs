@@ -0,0 +1,327 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, finalizer, Controller},
+    Api, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    cloudflare::{
+        dns::DnsContent, validate_service_url, with_correlation_id, CloudflareApi, OriginRequest,
+        TunnelIngress,
+    },
+    context::Context,
+    error::Error,
+    ClusterTunnel,
+};
+
+use super::{
+    clustertunnel::DnsCleanupPolicy,
+    error_policy,
+    ingress::{insert_ingress, update_config},
+    reset_failures,
+    utils::*,
+    OPERATOR_MANAGER,
+};
+
+const TUNNEL_ROUTE_FINALIZER: &'static str = "tunnelroute.cloudflare-tunnels-operator.io/finalizer";
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    kind = "TunnelRoute",
+    group = "cloudflare-tunnels-operator.io",
+    version = "v1alpha1",
+    namespaced,
+    printcolumn = r#"{"name":"Hostname","type":"string","jsonPath":".spec.hostname"}"#,
+    printcolumn = r#"{"name":"Service","type":"string","jsonPath":".spec.service"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelRouteSpec {
+    pub hostname: String,
+    pub service: String,
+    pub cluster_tunnel_ref: String,
+    pub origin_request: Option<OriginRequest>,
+}
+
+impl TunnelRoute {
+    fn tunnel_ingress(&self) -> Result<TunnelIngress, Error> {
+        let service = &self.spec.service;
+        if !service.starts_with("tcp://") && !service.starts_with("udp://") {
+            return Err(anyhow!("unsupported service {service}, expected tcp:// or udp://").into());
+        }
+        validate_service_url(service)?;
+
+        Ok(TunnelIngress {
+            hostname: Some(self.spec.hostname.clone()),
+            path: None,
+            service: service.clone(),
+            origin_request: self.spec.origin_request.clone(),
+        })
+    }
+
+    async fn clustertunnel(&self, ctx: Arc<Context>) -> Result<Option<ClusterTunnel>, Error> {
+        let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+
+        Ok(ct_api.get_opt(&self.spec.cluster_tunnel_ref).await?)
+    }
+
+    async fn cloudflare_client(
+        &self,
+        ctx: Arc<Context>,
+        clustertunnel: &ClusterTunnel,
+    ) -> Result<Arc<dyn CloudflareApi>, Error> {
+        let ns = ctx.operator_namespace.clone();
+        let cloudflare_creds =
+            get_credentials(ctx.clone(), &ns, &clustertunnel.spec.cloudflare).await?;
+
+        ctx.cloudflare_client(
+            clustertunnel.spec.cloudflare.account_id.clone(),
+            cloudflare_creds,
+        )
+    }
+
+    fn dns_cleanup_policy(&self, clustertunnel: &ClusterTunnel) -> Result<DnsCleanupPolicy, Error> {
+        Ok(self
+            .annotations()
+            .get(ANNOTATION_DNS_CLEANUP_POLICY)
+            .map(|policy| policy.parse::<DnsCleanupPolicy>())
+            .transpose()?
+            .or(clustertunnel.spec.default_dns_cleanup_policy)
+            .unwrap_or_default())
+    }
+
+    pub async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let Some(clustertunnel) = self.clustertunnel(ctx.clone()).await? else {
+            return Err(Error::TunnelNotReady(self.spec.cluster_tunnel_ref.clone()));
+        };
+
+        if clustertunnel.metadata.deletion_timestamp.is_some() {
+            warn!(
+                "clustertunnel {} is being deleted; requeueing",
+                self.spec.cluster_tunnel_ref
+            );
+            return Ok(Action::requeue(Duration::from_secs(30)));
+        }
+
+        // see the ingress controller, token tunnels are routed in the dashboard
+        if clustertunnel.uses_tunnel_token() {
+            return Err(Error::TunnelTokenRoutes(
+                self.spec.cluster_tunnel_ref.clone(),
+            ));
+        }
+
+        let tunnel_name = clustertunnel
+            .spec
+            .name
+            .clone()
+            .unwrap_or_else(|| clustertunnel.name_any());
+
+        let config_lock = ctx.config_lock(&tunnel_name);
+        let _config_guard = config_lock.lock().await;
+
+        let ing = self.tunnel_ingress()?;
+        let entries = vec![RouteEntry::from(&ing)];
+        // the entry written before the hostname or service was edited
+        let previous = route_entries(self.annotations());
+        let stale: Vec<&RouteEntry> = previous
+            .iter()
+            .filter(|entry| !entries.contains(entry))
+            .collect();
+        let config = update_config(ctx.clone(), &clustertunnel, &tunnel_name, |config| {
+            config
+                .ingress
+                .retain(|existing| !stale.iter().any(|entry| entry.matches(existing)));
+            insert_ingress(config, ing);
+        })
+        .await?;
+
+        let cloudflare_client = self.cloudflare_client(ctx.clone(), &clustertunnel).await?;
+        let hostname = &self.spec.hostname;
+        let cname = format!("{}.cfargotunnel.com", config.tunnel);
+
+        let stale_hostnames: HashSet<&String> = stale
+            .iter()
+            .filter_map(|entry| entry.hostname.as_ref())
+            .filter(|stale_hostname| *stale_hostname != hostname)
+            .collect();
+        if self.dns_cleanup_policy(&clustertunnel)? != DnsCleanupPolicy::Retain {
+            for stale_hostname in stale_hostnames {
+                let zone_id = clustertunnel
+                    .zone_id(ctx.clone(), &cloudflare_client, stale_hostname)
+                    .await?;
+
+                // only the tunnel's own record, the name may have been reused
+                let Some(record) = cloudflare_client
+                    .find_dns_record(&zone_id, stale_hostname)
+                    .await?
+                    .filter(|record| match &record.content {
+                        DnsContent::CNAME { content } => *content == cname,
+                        _ => false,
+                    })
+                else {
+                    continue;
+                };
+
+                cloudflare_client
+                    .delete_dns_record(&zone_id, &record.id)
+                    .await?;
+            }
+        }
+
+        let zone_id = clustertunnel
+            .zone_id(ctx.clone(), &cloudflare_client, hostname)
+            .await?;
+
+        match cloudflare_client
+            .find_dns_record(&zone_id, hostname)
+            .await?
+        {
+            Some(record) => match &record.content {
+                DnsContent::CNAME { content } if *content == cname => {}
+                _ => {
+                    cloudflare_client
+                        .update_dns_record(&zone_id, &record.id, hostname, &config.tunnel, None)
+                        .await?;
+                    ctx.metrics.dns_records_managed_total.inc();
+                }
+            },
+            None => {
+                cloudflare_client
+                    .create_dns_record(&zone_id, hostname, DnsContent::CNAME { content: cname })
+                    .await?;
+                ctx.metrics.dns_records_managed_total.inc();
+            }
+        }
+
+        if entries != previous {
+            let api: Api<TunnelRoute> = Api::namespaced(
+                ctx.kube_cli.clone(),
+                &self.namespace().unwrap_or_else(|| "default".to_string()),
+            );
+            let patch = serde_json::json!({
+                "metadata": {
+                    "annotations": {
+                        ANNOTATION_ROUTE_ENTRIES: serde_json::to_string(&entries)?,
+                    }
+                }
+            });
+            api.patch(
+                &self.name_any(),
+                &PatchParams::apply(OPERATOR_MANAGER),
+                &Patch::Merge(&patch),
+            )
+            .await?;
+        }
+
+        Ok(Action::requeue(ctx.requeue_interval))
+    }
+
+    pub async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action, Error> {
+        let Some(clustertunnel) = self.clustertunnel(ctx.clone()).await? else {
+            return Ok(Action::await_change());
+        };
+
+        let tunnel_name = clustertunnel
+            .spec
+            .name
+            .clone()
+            .unwrap_or_else(|| clustertunnel.name_any());
+
+        let config_lock = ctx.config_lock(&tunnel_name);
+        let _config_guard = config_lock.lock().await;
+
+        let entries = route_entries(self.annotations());
+        update_config(ctx.clone(), &clustertunnel, &tunnel_name, |config| {
+            config.ingress.retain(|existing| {
+                (existing.hostname.as_ref() != Some(&self.spec.hostname)
+                    || existing.service != self.spec.service)
+                    && !entries.iter().any(|entry| entry.matches(existing))
+            });
+        })
+        .await?;
+
+        let dns_cleanup_policy = self.dns_cleanup_policy(&clustertunnel)?;
+        // token tunnels never got records, the api can't be called for them
+        if dns_cleanup_policy == DnsCleanupPolicy::Retain || clustertunnel.uses_tunnel_token() {
+            return Ok(Action::await_change());
+        }
+
+        let cloudflare_client = self.cloudflare_client(ctx.clone(), &clustertunnel).await?;
+        let zone_id = clustertunnel
+            .zone_id(ctx.clone(), &cloudflare_client, &self.spec.hostname)
+            .await?;
+
+        if let Some(dns_record) = cloudflare_client
+            .find_dns_record(&zone_id, &self.spec.hostname)
+            .await?
+        {
+            cloudflare_client
+                .delete_dns_record(&zone_id, &dns_record.id)
+                .await?;
+        }
+
+        Ok(Action::await_change())
+    }
+}
+
+#[instrument(skip(obj, ctx), fields(
+    name = %obj.name_any(),
+    namespace = ?obj.namespace(),
+    resource_version = ?obj.resource_version(),
+    k8s.resource.kind = "TunnelRoute",
+    k8s.resource.name = %obj.name_any(),
+    k8s.resource.namespace = ?obj.namespace(),
+))]
+pub async fn reconcile(obj: Arc<TunnelRoute>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let _reconcile = ctx.track_reconcile();
+
+    let api: Api<TunnelRoute> = Api::namespaced(
+        ctx.kube_cli.clone(),
+        &obj.namespace().unwrap_or_else(|| "default".to_string()),
+    );
+
+    finalizer(&api, TUNNEL_ROUTE_FINALIZER, obj, |event| async {
+        match event {
+            finalizer::Event::Apply(obj) => obj.reconcile(ctx.clone()).await,
+            finalizer::Event::Cleanup(obj) => obj.cleanup(ctx.clone()).await,
+        }
+    })
+    .await
+    .map_err(|e| Error::FinalizerError(Box::new(e)))
+}
+
+pub async fn run(ctx: Arc<Context>) -> anyhow::Result<()> {
+    let cfg = ctx.watcher_config();
+    let api: Api<TunnelRoute> = ctx.watched_api();
+
+    Controller::new(api, cfg)
+        .shutdown_on_signal()
+        .run(
+            |obj, ctx| with_correlation_id(reconcile(obj, ctx)),
+            error_policy,
+            ctx.clone(),
+        )
+        .for_each(|res| {
+            let ctx = ctx.clone();
+            async move {
+                match res {
+                    Ok(o) => {
+                        ctx.metrics.reconcile_success("TunnelRoute");
+                        reset_failures(&ctx, &o.0);
+                        info!("reconciled tunnel route {o:?}")
+                    }
+                    Err(e) => warn!("reconcile tunnel route failed: {e:?}"),
+                }
+            }
+        })
+        .await;
+
+    Ok(())
+}
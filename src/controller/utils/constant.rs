@@ -1,2 +1,33 @@
+pub const DEFAULT_RESOURCE_PREFIX: &'static str = "cloudflared";
 pub const ANNOTATION_CONFIG_HASH: &'static str = "cloudflare-tunnels-operator.io/config-hash";
+pub const ANNOTATION_CREDENTIALS_HASH: &'static str =
+    "cloudflare-tunnels-operator.io/credentials-hash";
 pub const ANNOTATION_TUNNEL_NAME: &'static str = "cloudflare-tunnels-operator.io/tunnel-name";
+pub const ANNOTATION_ORIGIN_PREFIX: &'static str = "cloudflare-tunnels.io/origin-";
+pub const ANNOTATION_ORIGIN_CA_SECRET: &'static str = "cloudflare-tunnels.io/origin-ca-secret";
+pub const ANNOTATION_DNS_CLEANUP_POLICY: &'static str = "cloudflare-tunnels.io/dns-cleanup-policy";
+pub const ANNOTATION_BACKEND_PROTOCOL: &'static str = "cloudflare-tunnels.io/backend-protocol";
+pub const ANNOTATION_CLUSTER_TUNNEL: &'static str = "cloudflare-tunnels.io/cluster-tunnel";
+pub const ANNOTATION_CLUSTER_TUNNEL_UID: &'static str = "cloudflare-tunnels.io/cluster-tunnel-uid";
+pub const ANNOTATION_RECONCILE_PAUSE: &'static str = "cloudflare-tunnels.io/reconcile-pause";
+pub const ANNOTATION_ACCESS_POLICY: &'static str = "cloudflare-tunnels.io/access-policy";
+pub const ANNOTATION_ACCESS_PROTECTED: &'static str = "cloudflare-tunnels.io/access-protected";
+pub const ANNOTATION_ACCESS_AUD_TAG: &'static str = "cloudflare-tunnels.io/access-aud-tag";
+pub const ANNOTATION_REWRITE_TARGET: &'static str = "cloudflare-tunnels.io/rewrite-target";
+pub const ANNOTATION_UPSTREAM_HOST_HEADER: &'static str =
+    "cloudflare-tunnels.io/upstream-host-header";
+pub const ANNOTATION_ROTATE_SECRET: &'static str = "cloudflare-tunnels.io/rotate-secret";
+pub const ANNOTATION_LOAD_BALANCER_HEALTH_MONITOR: &'static str =
+    "cloudflare-tunnels.io/load-balancer-health-monitor-ref";
+pub const ANNOTATION_ZONE_SETTING_PREFIX: &'static str = "cloudflare-tunnels.io/zone-setting-";
+pub const ANNOTATION_STATUS: &'static str = "cloudflare-tunnels.io/status";
+pub const STATUS_SERVICE_NOT_FOUND: &'static str = "service-not-found";
+pub const ANNOTATION_MANAGED_INGRESSES: &'static str = "cloudflare-tunnels.io/managed-ingresses";
+// the ingress whose default backend replaced the tunnel's terminal 404 rule
+pub const ANNOTATION_DEFAULT_BACKEND_OWNER: &'static str =
+    "cloudflare-tunnels.io/default-backend-owner";
+// <zone id>/<app id> of every Access application the operator created for the ingress
+pub const ANNOTATION_ACCESS_APPLICATION_ID: &'static str =
+    "cloudflare-tunnels.io/access-application-id";
+// hostname, path and service of every config entry a route wrote, as json
+pub const ANNOTATION_ROUTE_ENTRIES: &'static str = "cloudflare-tunnels.io/route-entries";
@@ -1,45 +1,290 @@
 use anyhow::anyhow;
-use k8s_openapi::api::core::v1::Secret;
-use kube::Api;
-use std::sync::Arc;
+use k8s_openapi::{
+    api::core::v1::{ConfigMap, Secret, ServicePort},
+    apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector, Time},
+    chrono::Utc,
+};
+use kube::{api::DeleteParams, Api};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Debug,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{
-    cloudflare::Credentials,
+    cloudflare::{Credentials, OriginRequest, TunnelConfig, TunnelIngress},
     context::Context,
-    controller::clustertunnel::{CloudflareCredentials, CloudflareSecretRef},
+    controller::clustertunnel::{CloudflareCredentials, CloudflareSecretRef, SecretRef},
     Error,
 };
 
-pub async fn get_credentials(
+use super::{
+    ANNOTATION_DEFAULT_BACKEND_OWNER, ANNOTATION_MANAGED_INGRESSES, ANNOTATION_ORIGIN_CA_SECRET,
+    ANNOTATION_ORIGIN_PREFIX, ANNOTATION_RECONCILE_PAUSE, ANNOTATION_ROUTE_ENTRIES,
+};
+
+pub fn condition(
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: &str,
+    generation: Option<i64>,
+) -> Condition {
+    Condition {
+        type_: type_.to_string(),
+        status: if status { "True" } else { "False" }.to_string(),
+        reason: reason.to_string(),
+        message: message.to_string(),
+        observed_generation: generation,
+        last_transition_time: Time(Utc::now()),
+    }
+}
+
+// Keeps the transition time of an existing condition whose status didn't change.
+pub fn merge_condition(conditions: &[Condition], mut condition: Condition) -> Vec<Condition> {
+    if let Some(existing) = conditions
+        .iter()
+        .find(|existing| existing.type_ == condition.type_)
+    {
+        if existing.status == condition.status {
+            condition.last_transition_time = existing.last_transition_time.clone();
+        }
+    }
+
+    conditions
+        .iter()
+        .filter(|existing| existing.type_ != condition.type_)
+        .cloned()
+        .chain(std::iter::once(condition))
+        .collect()
+}
+
+pub fn reconcile_paused(annotations: &BTreeMap<String, String>) -> bool {
+    annotations
+        .get(ANNOTATION_RECONCILE_PAUSE)
+        .is_some_and(|paused| paused == "true")
+}
+
+// Managed resources and credential secrets live in the operator's namespace.
+pub fn operator_namespace() -> String {
+    std::env::var("POD_NAMESPACE").unwrap_or_else(|_| "default".to_string())
+}
+
+pub fn deployment_name(prefix: &str, tunnel_name: &str) -> String {
+    format!("{prefix}-{tunnel_name}")
+}
+
+pub fn config_map_name(prefix: &str, tunnel_name: &str) -> String {
+    format!("{prefix}-{tunnel_name}-config")
+}
+
+pub fn credentials_secret_name(prefix: &str, tunnel_name: &str) -> String {
+    format!("{prefix}-{tunnel_name}-credentials")
+}
+
+pub fn token_secret_name(prefix: &str, tunnel_name: &str) -> String {
+    format!("{prefix}-{tunnel_name}-token")
+}
+
+pub fn compute_config_hash(config: &TunnelConfig) -> Result<String, Error> {
+    Ok(sha256::digest(config.to_yaml()?))
+}
+
+// User supplied labels win, except over the operator's own app.kubernetes.io/
+// labels which the deployment selector depends on.
+pub fn merge_labels(
+    labels: &BTreeMap<String, String>,
+    overrides: Option<&BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+    let mut merged = labels.clone();
+    for (key, value) in overrides.into_iter().flatten() {
+        if key.starts_with("app.kubernetes.io/") && labels.contains_key(key) {
+            continue;
+        }
+        merged.insert(key.clone(), value.clone());
+    }
+
+    merged
+}
+
+// Origin CA files live next to config.yaml and are kept for as long as the
+// config still points a caPool at them.
+pub fn config_map_data(
+    config: &TunnelConfig,
+    config_yaml: String,
+    existing: Option<&BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+    let ca_pools: Vec<&String> = config
+        .origin_request
+        .iter()
+        .chain(
+            config
+                .ingress
+                .iter()
+                .filter_map(|ing| ing.origin_request.as_ref()),
+        )
+        .filter_map(|origin_request| origin_request.ca_pool.as_ref())
+        .collect();
+
+    let mut data: BTreeMap<String, String> = existing
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| {
+            ca_pools
+                .iter()
+                .any(|ca_pool| **ca_pool == format!("/config/{key}"))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    data.insert("config.yaml".to_string(), config_yaml);
+
+    data
+}
+
+// Ingresses that contributed rules to a tunnel config, as "namespace/name".
+pub fn managed_ingresses(cm: &ConfigMap) -> HashSet<String> {
+    cm.metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(ANNOTATION_MANAGED_INGRESSES))
+        .map(|ingresses| {
+            ingresses
+                .split(',')
+                .map(str::trim)
+                .filter(|ingress| !ingress.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn default_backend_owner(cm: &ConfigMap) -> Option<String> {
+    cm.metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(ANNOTATION_DEFAULT_BACKEND_OWNER))
+        .cloned()
+}
+
+pub fn managed_ingresses_annotations(
+    ingresses: &HashSet<String>,
+    default_backend_owner: Option<&String>,
+) -> BTreeMap<String, String> {
+    let mut ingresses: Vec<&String> = ingresses.iter().collect();
+    ingresses.sort();
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert(
+        ANNOTATION_MANAGED_INGRESSES.to_string(),
+        ingresses
+            .into_iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    if let Some(owner) = default_backend_owner {
+        annotations.insert(ANNOTATION_DEFAULT_BACKEND_OWNER.to_string(), owner.clone());
+    }
+    annotations
+}
+
+// A config entry written for a route, kept so an edited route can find and
+// drop the entries it no longer produces.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RouteEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub service: String,
+}
+
+impl RouteEntry {
+    pub fn matches(&self, ing: &TunnelIngress) -> bool {
+        self.hostname == ing.hostname && self.path == ing.path && self.service == ing.service
+    }
+}
+
+impl From<&TunnelIngress> for RouteEntry {
+    fn from(ing: &TunnelIngress) -> Self {
+        Self {
+            hostname: ing.hostname.clone(),
+            path: ing.path.clone(),
+            service: ing.service.clone(),
+        }
+    }
+}
+
+pub fn route_entries(annotations: &BTreeMap<String, String>) -> Vec<RouteEntry> {
+    annotations
+        .get(ANNOTATION_ROUTE_ENTRIES)
+        .and_then(|entries| serde_json::from_str(entries).ok())
+        .unwrap_or_default()
+}
+
+pub async fn get_secret_value(
     ctx: Arc<Context>,
     ns: &str,
-    creds: &CloudflareCredentials,
-) -> Result<Credentials, Error> {
-    let value = {
-        let kube_cli = ctx.kube_cli.clone();
+    secret_ref: &SecretRef,
+) -> Result<String, Error> {
+    let kube_cli = ctx.kube_cli.clone();
 
-        let secret_api: Api<Secret> = Api::namespaced(kube_cli.clone(), ns);
+    let ns = secret_ref.namespace_or(ns);
+    let secret_api: Api<Secret> = Api::namespaced(kube_cli.clone(), ns);
 
-        let secret_ref = creds.secret_ref.secret_ref();
+    let name = format!("{ns}/{}", secret_ref.name);
 
-        let secret = secret_api.get(&secret_ref.name).await?;
-        let data = secret.data.ok_or_else(|| anyhow!("no data"))?;
-
-        let value = data.get(&secret_ref.key).ok_or_else(|| {
-            anyhow!(
-                "key {} not found or invalid in {}",
-                secret_ref.key,
-                secret_ref.name
-            )
+    let secret = secret_api
+        .get_opt(&secret_ref.name)
+        .await?
+        .ok_or_else(|| Error::SecretNotFound { name: name.clone() })?;
+    let value = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(&secret_ref.key))
+        .ok_or_else(|| Error::SecretKeyNotFound {
+            name: name.clone(),
+            key: secret_ref.key.clone(),
         })?;
 
-        String::from_utf8(value.clone().0).map_err(|err| anyhow!("value not a string: {err:?}"))?
+    let value = String::from_utf8(value.clone().0).map_err(|_| Error::SecretValueInvalid {
+        name,
+        key: secret_ref.key.clone(),
+    })?;
+
+    Ok(value)
+}
+
+pub async fn read_mounted_credentials(path: &str) -> Result<Credentials, Error> {
+    let token = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| anyhow!("failed to read credentials from {path}: {err}"))?;
+
+    Ok(Credentials::UserAuthToken {
+        token: token.trim().to_string(),
+    })
+}
+
+pub async fn get_credentials(
+    ctx: Arc<Context>,
+    ns: &str,
+    creds: &CloudflareCredentials,
+) -> Result<Credentials, Error> {
+    let Some(secret_ref) = creds.secret_ref.secret_ref() else {
+        return match creds.secret_ref.mounted_file() {
+            Some(path) => read_mounted_credentials(path).await,
+            None => Err(Error::InvalidSpec("no credentials configured".into())),
+        };
     };
+    let value = get_secret_value(ctx, ns, secret_ref).await?;
 
     let creds = match &creds.secret_ref {
         &CloudflareSecretRef::ApiKey(_) => {
             let Some(email) = &creds.email else {
-                return Err(anyhow!("api key requires email").into());
+                return Err(Error::InvalidSpec("api key requires email".into()));
             };
 
             Credentials::UserAuthKey {
@@ -47,8 +292,202 @@ pub async fn get_credentials(
                 key: value,
             }
         }
-        &CloudflareSecretRef::ApiToken(_) => Credentials::UserAuthToken { token: value },
+        &CloudflareSecretRef::ApiToken(_)
+        | &CloudflareSecretRef::ZoneScopedToken(_)
+        | &CloudflareSecretRef::MountedFile { .. } => Credentials::UserAuthToken { token: value },
+        // the token only lets cloudflared connect, the api doesn't accept it
+        &CloudflareSecretRef::TunnelToken(_) => {
+            return Err(Error::InvalidSpec(
+                "a tunnel token can't be used with the cloudflare api".into(),
+            ))
+        }
     };
 
     Ok(creds)
 }
+
+pub fn selector_matches(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let match_labels = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+
+    let match_expressions = selector.match_expressions.iter().flatten().all(|expr| {
+        let values = expr.values.clone().unwrap_or_default();
+        match expr.operator.as_str() {
+            "In" => labels.get(&expr.key).is_some_and(|v| values.contains(v)),
+            "NotIn" => labels.get(&expr.key).map_or(true, |v| !values.contains(v)),
+            "Exists" => labels.contains_key(&expr.key),
+            "DoesNotExist" => !labels.contains_key(&expr.key),
+            _ => false,
+        }
+    });
+
+    match_labels && match_expressions
+}
+
+fn parse_origin_annotation(
+    origin_request: &mut OriginRequest,
+    key: &str,
+    value: &str,
+) -> Result<(), String> {
+    fn parse<T: FromStr>(key: &str, value: &str) -> Result<Option<T>, String> {
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid value {value:?} for {ANNOTATION_ORIGIN_PREFIX}{key}"))
+    }
+
+    fn parse_duration(key: &str, value: &str) -> Result<Option<Duration>, String> {
+        humantime::parse_duration(value).map(Some).map_err(|err| {
+            format!("invalid duration {value:?} for {ANNOTATION_ORIGIN_PREFIX}{key}: {err}")
+        })
+    }
+
+    match key {
+        "origin-server-name" => origin_request.origin_server_name = Some(value.to_string()),
+        "ca-pool" => origin_request.ca_pool = Some(value.to_string()),
+        "no-tls-verify" => origin_request.no_tls_verify = parse(key, value)?,
+        "tls-timeout" => origin_request.tls_timeout = parse_duration(key, value)?,
+        "http2" | "http2-origin" => origin_request.http_2_origin = parse(key, value)?,
+        "http-host-header" => origin_request.http_host_header = Some(value.to_string()),
+        "disable-chunked-encoding" => origin_request.disable_chunked_encoding = parse(key, value)?,
+        "connect-timeout" => origin_request.connect_timeout = parse_duration(key, value)?,
+        "no-happy-eyeballs" => origin_request.no_happy_eyeball = parse(key, value)?,
+        "proxy-type" => origin_request.proxy_type = Some(value.to_string()),
+        "proxy-address" => origin_request.proxy_address = Some(value.to_string()),
+        "proxy-port" => origin_request.proxy_port = parse(key, value)?,
+        "keep-alive-timeout" => origin_request.keep_alive_timeout = parse_duration(key, value)?,
+        "keep-alive-connections" => origin_request.keep_alive_connection = parse(key, value)?,
+        "tcp-keep-alive" => origin_request.tcp_keep_alive = parse_duration(key, value)?,
+        _ => {
+            return Err(format!(
+                "unknown annotation {ANNOTATION_ORIGIN_PREFIX}{key}"
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn origin_annotations(
+    annotations: &BTreeMap<String, String>,
+) -> impl Iterator<Item = (&str, &str)> {
+    annotations
+        .iter()
+        .filter(|(key, _)| *key != ANNOTATION_ORIGIN_CA_SECRET)
+        .filter_map(|(key, value)| {
+            key.strip_prefix(ANNOTATION_ORIGIN_PREFIX)
+                .map(|key| (key, value.as_str()))
+        })
+}
+
+pub fn origin_request_from_annotations(
+    annotations: &BTreeMap<String, String>,
+) -> Option<OriginRequest> {
+    let mut origin_request = OriginRequest::default();
+    let mut found = false;
+
+    for (key, value) in origin_annotations(annotations) {
+        found |= parse_origin_annotation(&mut origin_request, key, value).is_ok();
+    }
+
+    found.then_some(origin_request)
+}
+
+pub fn origin_annotation_warnings(annotations: &BTreeMap<String, String>) -> Vec<String> {
+    origin_annotations(annotations)
+        .filter_map(|(key, value)| {
+            parse_origin_annotation(&mut OriginRequest::default(), key, value).err()
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackendProtocol {
+    #[default]
+    Http,
+    Https,
+    H2,
+    H2c,
+    Grpc,
+    Tcp,
+    Udp,
+}
+
+impl BackendProtocol {
+    // follows the appProtocol conventions of kubernetes services
+    pub fn from_service_port(port: &ServicePort) -> Option<Self> {
+        match port.app_protocol.as_deref() {
+            Some("kubernetes.io/https" | "https") => Some(BackendProtocol::Https),
+            Some("kubernetes.io/h2c") => Some(BackendProtocol::H2c),
+            Some("grpc") => Some(BackendProtocol::Grpc),
+            _ if port.name.as_deref() == Some("https") => Some(BackendProtocol::Https),
+            _ if port.name.as_deref() == Some("grpc") => Some(BackendProtocol::Grpc),
+            _ => None,
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            BackendProtocol::Http | BackendProtocol::H2c => "http",
+            // cloudflared only speaks http/2 to tls origins
+            BackendProtocol::Https | BackendProtocol::H2 | BackendProtocol::Grpc => "https",
+            BackendProtocol::Tcp => "tcp",
+            BackendProtocol::Udp => "udp",
+        }
+    }
+
+    // Cluster services usually serve self-signed certificates, so TLS
+    // verification is disabled unless the user configured it explicitly.
+    pub fn apply_defaults(&self, origin_request: Option<OriginRequest>) -> Option<OriginRequest> {
+        match self {
+            BackendProtocol::Https | BackendProtocol::H2 | BackendProtocol::Grpc => {
+                let mut origin_request = origin_request.unwrap_or_default();
+                origin_request.no_tls_verify.get_or_insert(true);
+                if matches!(self, BackendProtocol::H2 | BackendProtocol::Grpc) {
+                    origin_request.http_2_origin.get_or_insert(true);
+                }
+
+                Some(origin_request)
+            }
+            // plaintext http/2 is only attempted when asked for
+            BackendProtocol::H2c => {
+                let mut origin_request = origin_request.unwrap_or_default();
+                origin_request.http_2_origin.get_or_insert(true);
+
+                Some(origin_request)
+            }
+            _ => origin_request,
+        }
+    }
+}
+
+impl FromStr for BackendProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "http" => Ok(BackendProtocol::Http),
+            "https" => Ok(BackendProtocol::Https),
+            "h2" => Ok(BackendProtocol::H2),
+            "h2c" => Ok(BackendProtocol::H2c),
+            "grpc" => Ok(BackendProtocol::Grpc),
+            "tcp" => Ok(BackendProtocol::Tcp),
+            "udp" => Ok(BackendProtocol::Udp),
+            _ => Err(anyhow!("unsupported backend protocol {s}").into()),
+        }
+    }
+}
+
+pub async fn delete_if_exists<K>(api: &Api<K>, name: &str) -> Result<(), Error>
+where
+    K: Clone + DeserializeOwned + Debug,
+{
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(err)) if err.code == 404 => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -3,5 +3,20 @@ fn main() {
     print!(
         "{}",
         serde_yaml::to_string(&cloudflare_tunnels_operator::ClusterTunnel::crd()).unwrap()
-    )
+    );
+    println!("---");
+    print!(
+        "{}",
+        serde_yaml::to_string(&cloudflare_tunnels_operator::TunnelPolicy::crd()).unwrap()
+    );
+    println!("---");
+    print!(
+        "{}",
+        serde_yaml::to_string(&cloudflare_tunnels_operator::CloudflareDNS::crd()).unwrap()
+    );
+    println!("---");
+    print!(
+        "{}",
+        serde_yaml::to_string(&cloudflare_tunnels_operator::TunnelRoute::crd()).unwrap()
+    );
 }
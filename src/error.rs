@@ -1,3 +1,4 @@
+use cloudflare::framework::response::ApiFailure;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,8 +11,71 @@ pub enum Error {
     FinalizerError(#[source] Box<kube::runtime::finalizer::Error<Error>>),
     #[error("Cloudflare Error: {0}")]
     CloudflareErr(#[from] cloudflare::framework::Error),
-    #[error("Cloudflare Api Error: {0}")]
-    CloudflareApiErr(#[from] cloudflare::framework::response::ApiFailure),
+    #[error(
+        "Cloudflare Api Error: {0}{}",
+        .1.as_ref().map(|id| format!(" (correlation id {id})")).unwrap_or_default()
+    )]
+    CloudflareApiErr(ApiFailure, Option<String>),
+    #[error("deletion protection is enabled; set spec.deletionProtection=false before deleting")]
+    DeletionProtected,
+    #[error("cluster tunnel {0} is not ready")]
+    TunnelNotReady(String),
+    #[error("tunnel {0} already exists")]
+    TunnelAlreadyExists(String),
+    #[error("tunnel {name} not found")]
+    TunnelNotFound { name: String },
+    #[error("secret {name} not found")]
+    SecretNotFound { name: String },
+    #[error("key {key} not found in secret {name}")]
+    SecretKeyNotFound { name: String, key: String },
+    #[error("value of key {key} in secret {name} is not valid utf-8")]
+    SecretValueInvalid { name: String, key: String },
+    #[error("config map {name} not found")]
+    ConfigMapNotFound { name: String },
+    #[error("service {name} not found")]
+    ServiceNotFound { name: String },
+    #[error("tunnel policy {name} not found")]
+    TunnelPolicyNotFound { name: String },
+    #[error("no zone found for {hostname}")]
+    ZoneNotFound { hostname: String },
+    #[error("{0}")]
+    InvalidSpec(String),
+    #[error("cluster tunnel {0} runs on a tunnel token, its routes are managed in the dashboard")]
+    TunnelTokenRoutes(String),
+    #[error("Serialization Error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error(
+        "Cloudflare api rate limit exceeded: {0}{}",
+        .1.as_ref().map(|id| format!(" (correlation id {id})")).unwrap_or_default()
+    )]
+    CloudflareRateLimit(ApiFailure, Option<String>),
+    #[error(
+        "Cloudflare api rejected the credentials: {0}{}",
+        .1.as_ref().map(|id| format!(" (correlation id {id})")).unwrap_or_default()
+    )]
+    CloudflareAuthFailed(ApiFailure, Option<String>),
+    #[error(
+        "Cloudflare api denied the request: {0}{}",
+        .1.as_ref().map(|id| format!(" (correlation id {id})")).unwrap_or_default()
+    )]
+    CloudflarePermissionDenied(ApiFailure, Option<String>),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+// api failures are converted while reconciling, so they pick up its correlation id
+impl From<ApiFailure> for Error {
+    fn from(err: ApiFailure) -> Self {
+        let correlation_id = crate::cloudflare::correlation_id();
+        match &err {
+            ApiFailure::Error(status, _) => match status.as_u16() {
+                429 => Error::CloudflareRateLimit(err, correlation_id),
+                401 => Error::CloudflareAuthFailed(err, correlation_id),
+                // valid credentials that lack a permission for this one call
+                403 => Error::CloudflarePermissionDenied(err, correlation_id),
+                _ => Error::CloudflareApiErr(err, correlation_id),
+            },
+            _ => Error::CloudflareApiErr(err, correlation_id),
+        }
+    }
+}
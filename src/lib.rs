@@ -1,10 +1,17 @@
 mod context;
-pub use context::Context;
+pub use context::{cloudflare_client_factory, CloudflareClientFactory, Context, ReconcileGuard};
 
 pub mod controller;
-pub use controller::ClusterTunnel;
+pub use controller::{CloudflareDNS, ClusterTunnel, TunnelPolicy, TunnelRoute};
 
 mod error;
 pub use crate::error::*;
 
-mod cloudflare;
+pub mod cloudflare;
+pub use cloudflare::ApiCache;
+
+pub mod metrics;
+
+pub mod telemetry;
+
+pub mod webhook;
@@ -1,14 +1,85 @@
-use std::sync::Arc;
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use actix_web::{get, middleware, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use anyhow::anyhow;
 use clap::Parser;
-use cloudflare_tunnels_operator::{controller, Context};
-use log::info;
+use cloudflare_tunnels_operator::{
+    cloudflare::CloudflareEnvironment,
+    cloudflare_client_factory,
+    controller::{self, FailureTracker},
+    metrics, telemetry, webhook, ApiCache, ClusterTunnel, Context,
+};
+use dashmap::{DashMap, DashSet};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    core::{
+        admission::{AdmissionRequest, AdmissionReview},
+        conversion::ConversionReview,
+    },
+    runtime::events::{Recorder, Reporter},
+    Api,
+};
+use tokio::signal::unix::SignalKind;
+use tracing::{error, info, warn};
 
 #[derive(Parser, Debug)]
 struct Args {
+    #[arg(long, value_delimiter = ',')]
+    ingress_class: Option<Vec<String>>,
     #[arg(long)]
-    ingress_class: Option<String>,
+    namespace: Option<String>,
+    #[arg(long)]
+    watch_label_selector: Option<String>,
+    #[arg(long, default_value_t = 3600)]
+    requeue_interval: u64,
+    #[arg(long, default_value_t = 15)]
+    error_requeue_interval: u64,
+    #[arg(long, default_value_t = 600)]
+    max_error_requeue_interval: u64,
+    #[arg(long, default_value_t = 30)]
+    cloudflare_timeout: u64,
+    #[arg(long, default_value_t = 3)]
+    cloudflare_retries: u32,
+    #[arg(long, default_value = "production")]
+    cloudflare_environment: CloudflareEnvironment,
+    #[arg(long, default_value = "https://api.cloudflare.com/client/v4")]
+    cloudflare_api_url: String,
+    #[arg(long)]
+    dry_run: bool,
+    #[arg(long)]
+    gateway_api: bool,
+    #[arg(long)]
+    webhook_cert: Option<String>,
+    #[arg(long)]
+    webhook_key: Option<String>,
+    #[arg(long)]
+    skip_startup_validation: bool,
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout: u64,
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+    #[arg(long, default_value_t = 1.0, value_parser = parse_trace_ratio)]
+    trace_ratio: f64,
+}
+
+// the share of traces that is sampled
+fn parse_trace_ratio(value: &str) -> Result<f64, String> {
+    let ratio: f64 = value.parse().map_err(|err| format!("{err}"))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("{ratio} is not between 0 and 1"));
+    }
+
+    Ok(ratio)
 }
 
 #[get("/health")]
@@ -16,34 +87,272 @@ async fn health(_: HttpRequest) -> impl Responder {
     HttpResponse::Ok()
 }
 
+#[get("/readyz")]
+async fn readyz(ready: web::Data<AtomicBool>) -> impl Responder {
+    if ready.load(Ordering::Relaxed) {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    }
+}
+
+#[get("/drain")]
+async fn drain(active_reconciles: web::Data<AtomicU32>) -> impl Responder {
+    HttpResponse::Ok().body(active_reconciles.load(Ordering::SeqCst).to_string())
+}
+
+#[get("/metrics")]
+async fn metrics_handler(_: HttpRequest) -> impl Responder {
+    match metrics::encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[post("/validate-clustertunnel")]
+async fn validate_clustertunnel(
+    ctx: web::Data<Context>,
+    review: web::Json<AdmissionReview<ClusterTunnel>>,
+) -> impl Responder {
+    let req: AdmissionRequest<ClusterTunnel> = match review.into_inner().try_into() {
+        Ok(req) => req,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+
+    let mut res = webhook::validate_clustertunnel(&req);
+    if let Some(tunnel) = req.object.as_ref().filter(|_| res.allowed) {
+        let warnings =
+            webhook::secret_access_warnings(&ctx.kube_cli, &ctx.operator_namespace, tunnel).await;
+        if !warnings.is_empty() {
+            res.warnings = Some(warnings);
+        }
+    }
+
+    HttpResponse::Ok().json(res.into_review())
+}
+
+#[post("/convert")]
+async fn convert(review: web::Json<ConversionReview>) -> impl Responder {
+    HttpResponse::Ok().json(webhook::conversion::convert(review.into_inner()))
+}
+
+fn load_tls_config(cert: &str, key: &str) -> anyhow::Result<rustls::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or_else(|| anyhow!("no private key found in {key}"))?;
+
+    Ok(rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?)
+}
+
+async fn shutdown_signal() {
+    let mut sigterm = match tokio::signal::unix::signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            warn!("failed to listen for SIGTERM: {err}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+// the api server only talks to admission and conversion webhooks over tls
+async fn run_webhook(
+    ctx: Arc<Context>,
+    cert: Option<String>,
+    key: Option<String>,
+) -> anyhow::Result<()> {
+    let (Some(cert), Some(key)) = (cert, key) else {
+        info!("webhook certificate not configured, admission webhook disabled");
+        return Ok(());
+    };
+
+    let ctx = web::Data::from(ctx);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(ctx.clone())
+            .service(validate_clustertunnel)
+            .service(convert)
+    })
+    .bind_rustls_0_23("0.0.0.0:8443", load_tls_config(&cert, &key)?)?
+    .shutdown_timeout(5)
+    .run()
+    .await?;
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    env_logger::init();
+    let args: Args = Args::parse();
 
-    info!("starting cloudflare tunnels operator");
+    telemetry::init(args.otlp_endpoint.as_deref(), args.trace_ratio)?;
 
-    let args: Args = Args::parse();
+    info!("starting cloudflare tunnels operator");
 
     let kube_cli = kube::Client::try_default().await?;
 
+    let operator_namespace = controller::operator_namespace();
+    if Api::<Namespace>::all(kube_cli.clone())
+        .get_opt(&operator_namespace)
+        .await?
+        .is_none()
+    {
+        return Err(anyhow!(
+            "operator namespace {operator_namespace} does not exist, set POD_NAMESPACE to the namespace the operator runs in"
+        ));
+    }
+
+    let recorder = Recorder::new(
+        kube_cli.clone(),
+        Reporter {
+            controller: "cloudflare-tunnels-operator".to_string(),
+            instance: std::env::var("POD_NAME").ok(),
+        },
+    );
+
     let ctx = Arc::new(Context {
         kube_cli,
+        recorder,
         ingress_class: args.ingress_class.clone(),
+        ingress_classes: DashSet::new(),
+        ingress_classes_changed: tokio::sync::watch::channel(()).0,
+        watch_namespace: args.namespace.clone(),
+        watch_label_selector: args.watch_label_selector.clone(),
+        operator_namespace,
+        metrics: metrics::Metrics::new()?,
+        requeue_interval: Duration::from_secs(args.requeue_interval),
+        error_requeue_interval: Duration::from_secs(args.error_requeue_interval),
+        max_error_requeue_interval: Duration::from_secs(args.max_error_requeue_interval),
+        failures: FailureTracker::default(),
+        config_locks: DashMap::new(),
+        cloudflare_cache: ApiCache::default(),
+        cloudflare_timeout: Duration::from_secs(args.cloudflare_timeout),
+        cloudflare_retries: args.cloudflare_retries,
+        cloudflare_environment: args.cloudflare_environment,
+        cloudflare_api_url: args.cloudflare_api_url.clone(),
+        cloudflare_dry_run: args.dry_run,
+        cloudflare_client_factory: cloudflare_client_factory(),
+        active_reconciles: Arc::default(),
+        tracer: telemetry::tracer(),
     });
 
-    let clustertunnel = controller::clustertunnel::run(ctx.clone());
-    let ingress = controller::ingress::run(ctx.clone());
-
+    let ready = web::Data::new(AtomicBool::new(false));
+    let server_ready = ready.clone();
+    let active_reconciles = web::Data::from(ctx.active_reconciles.clone());
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(middleware::Logger::default().exclude("/health"))
+            .app_data(server_ready.clone())
+            .app_data(active_reconciles.clone())
+            .wrap(
+                middleware::Logger::default()
+                    .exclude("/health")
+                    .exclude("/readyz")
+                    .exclude("/metrics"),
+            )
             .service(health)
+            .service(readyz)
+            .service(drain)
+            .service(metrics_handler)
     })
     .bind("0.0.0.0:2000")?
     .shutdown_timeout(5)
+    .disable_signals()
     .run();
+    let server_handle = server.handle();
+    // serves /readyz while the startup validation runs
+    let server = tokio::spawn(server);
+
+    if args.skip_startup_validation {
+        info!("skipping startup validation");
+    } else {
+        controller::validate_startup(ctx.clone()).await?;
+    }
+    ready.store(true, Ordering::Relaxed);
+
+    let clustertunnel = controller::clustertunnel::run(ctx.clone());
+    let ingress = controller::ingress::run(ctx.clone());
+    let ingressclass = controller::ingressclass::run(ctx.clone());
+    let cloudflaredns = controller::cloudflareddns::run(ctx.clone());
+    let tunnelroute = controller::tunnelroute::run(ctx.clone());
+    let gateway_api = args.gateway_api;
+    let httproute = async {
+        if gateway_api {
+            controller::httproute::run(ctx.clone()).await
+        } else {
+            Ok(())
+        }
+    };
+
+    let webhook_server = async {
+        run_webhook(ctx.clone(), args.webhook_cert, args.webhook_key).await?;
+        // a disabled webhook returns right away, which mustn't stop the operator
+        std::future::pending::<anyhow::Result<()>>().await
+    };
+
+    let reconcilers = async {
+        tokio::join!(
+            clustertunnel,
+            ingress,
+            cloudflaredns,
+            tunnelroute,
+            httproute
+        )
+    };
+    tokio::pin!(reconcilers);
+
+    let mut webhook_error = None;
+    let reconcilers_done = tokio::select! {
+        _ = &mut reconcilers => true,
+        _ = ingressclass => false,
+        res = webhook_server => {
+            if let Err(err) = res {
+                error!("admission webhook server failed: {err:?}");
+                webhook_error = Some(err);
+            }
+            false
+        }
+        _ = shutdown_signal() => false,
+    };
+
+    info!("shutting down");
+    server_handle.stop(false).await;
+    let _ = server.await;
+
+    // the controllers stop taking new work on the same signal, but the
+    // reconciles in flight are only driven while the controllers are polled
+    let drain = async {
+        if !reconcilers_done {
+            let _ = (&mut reconcilers).await;
+        }
+        while ctx.active_reconciles.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(args.shutdown_timeout), drain)
+        .await
+        .is_err()
+    {
+        warn!(
+            "shutdown timeout reached with {} reconciles in flight",
+            ctx.active_reconciles.load(Ordering::SeqCst)
+        );
+    }
+
+    telemetry::shutdown();
 
-    let _ = tokio::join!(clustertunnel, ingress, server);
+    if let Some(err) = webhook_error {
+        return Err(err);
+    }
 
     Ok(())
 }
@@ -0,0 +1,118 @@
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+
+#[derive(Clone)]
+pub struct Metrics {
+    pub clustertunnel_reconcile_total: IntCounterVec,
+    pub ingress_reconcile_total: IntCounterVec,
+    pub httproute_reconcile_total: IntCounterVec,
+    pub cloudflare_api_request_duration_seconds: HistogramVec,
+    pub cloudflare_api_timeout_total: IntCounterVec,
+    pub tunnel_active_count: IntGauge,
+    pub dns_records_managed_total: IntCounter,
+    pub cloudflare_dns_create_total: IntCounterVec,
+    pub cloudflare_dns_update_total: IntCounterVec,
+    pub cloudflare_dns_delete_total: IntCounterVec,
+    pub cloudflare_dns_skip_total: IntCounterVec,
+    pub configmap_write_total: IntCounterVec,
+    pub configmap_write_skipped_total: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        Ok(Self {
+            clustertunnel_reconcile_total: register_int_counter_vec!(
+                "clustertunnel_reconcile_total",
+                "Number of cluster tunnel reconciliations",
+                &["result"]
+            )?,
+            ingress_reconcile_total: register_int_counter_vec!(
+                "ingress_reconcile_total",
+                "Number of ingress reconciliations",
+                &["result"]
+            )?,
+            httproute_reconcile_total: register_int_counter_vec!(
+                "httproute_reconcile_total",
+                "Number of httproute reconciliations",
+                &["result"]
+            )?,
+            cloudflare_api_request_duration_seconds: register_histogram_vec!(
+                "cloudflare_api_request_duration_seconds",
+                "Duration of cloudflare api requests",
+                &["endpoint"]
+            )?,
+            cloudflare_api_timeout_total: register_int_counter_vec!(
+                "cloudflare_api_timeout_total",
+                "Number of cloudflare api requests that timed out",
+                &["endpoint"]
+            )?,
+            tunnel_active_count: register_int_gauge!(
+                "tunnel_active_count",
+                "Number of cluster tunnels managed by the operator"
+            )?,
+            dns_records_managed_total: register_int_counter!(
+                "dns_records_managed_total",
+                "Number of dns records created or updated by the operator"
+            )?,
+            cloudflare_dns_create_total: register_int_counter_vec!(
+                "cloudflare_dns_create_total",
+                "Number of dns records created for ingress hosts",
+                &["tunnel_name", "zone_id"]
+            )?,
+            cloudflare_dns_update_total: register_int_counter_vec!(
+                "cloudflare_dns_update_total",
+                "Number of dns records updated to point at a tunnel",
+                &["tunnel_name", "zone_id"]
+            )?,
+            cloudflare_dns_delete_total: register_int_counter_vec!(
+                "cloudflare_dns_delete_total",
+                "Number of dns records deleted with their ingress",
+                &["tunnel_name", "zone_id"]
+            )?,
+            cloudflare_dns_skip_total: register_int_counter_vec!(
+                "cloudflare_dns_skip_total",
+                "Number of dns records already pointing at their tunnel",
+                &["tunnel_name", "zone_id"]
+            )?,
+            configmap_write_total: register_int_counter_vec!(
+                "configmap_write_total",
+                "Number of tunnel config map writes",
+                &["tunnel_name"]
+            )?,
+            configmap_write_skipped_total: register_int_counter_vec!(
+                "configmap_write_skipped_total",
+                "Number of tunnel config map writes skipped because nothing changed",
+                &["tunnel_name"]
+            )?,
+        })
+    }
+
+    pub fn reconcile_success(&self, kind: &str) {
+        if let Some(counter) = self.reconcile_total(kind) {
+            counter.with_label_values(&["success"]).inc();
+        }
+    }
+
+    pub fn reconcile_error(&self, kind: &str) {
+        if let Some(counter) = self.reconcile_total(kind) {
+            counter.with_label_values(&["error"]).inc();
+        }
+    }
+
+    fn reconcile_total(&self, kind: &str) -> Option<&IntCounterVec> {
+        match kind {
+            "ClusterTunnel" => Some(&self.clustertunnel_reconcile_total),
+            "Ingress" => Some(&self.ingress_reconcile_total),
+            "HTTPRoute" => Some(&self.httproute_reconcile_total),
+            _ => None,
+        }
+    }
+}
+
+pub fn encode() -> Result<String, prometheus::Error> {
+    let encoder = prometheus::TextEncoder::new();
+
+    encoder.encode_to_string(&prometheus::gather())
+}
@@ -0,0 +1,77 @@
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Config, Sampler},
+    Resource,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer as _};
+
+const TRACER_NAME: &'static str = "cloudflare-tunnels-operator";
+
+// Sets up the json logs and, with an otlp endpoint, exports the spans of the
+// reconciles and their Cloudflare and Kubernetes api calls over grpc.
+pub fn init(otlp_endpoint: Option<&str>, trace_ratio: f64) -> anyhow::Result<()> {
+    let tracer = otlp_endpoint
+        .map(|endpoint| {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+
+            let provider = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(
+                    Config::default()
+                        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                            trace_ratio,
+                        ))))
+                        .with_resource(Resource::new([KeyValue::new(
+                            "service.name",
+                            "cloudflare-tunnels-operator",
+                        )])),
+                )
+                .install_batch(runtime::Tokio)?;
+            global::set_tracer_provider(provider.clone());
+
+            Ok::<_, anyhow::Error>(provider.tracer(TRACER_NAME))
+        })
+        .transpose()?;
+
+    // the kube client opens a debug span per request, those are exported
+    // without turning on its debug logs
+    let trace_filter = EnvFilter::from_default_env().add_directive("kube_client=debug".parse()?);
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_filter(EnvFilter::from_default_env()),
+        )
+        .with(tracer.map(|tracer| {
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(trace_filter)
+        }))
+        .init();
+
+    Ok(())
+}
+
+// the operator's tracer from the global provider, a no-op one until init
+// installed an exporter
+pub fn tracer() -> BoxedTracer {
+    global::tracer(TRACER_NAME)
+}
+
+// flushes the spans still buffered by the batch exporter
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
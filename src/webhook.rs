@@ -0,0 +1,175 @@
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, Operation};
+use kube::{api::PostParams, Api, ResourceExt};
+
+use crate::ClusterTunnel;
+
+pub mod conversion;
+
+fn tunnel_name(tunnel: &ClusterTunnel) -> String {
+    tunnel
+        .spec
+        .name
+        .clone()
+        .unwrap_or_else(|| tunnel.name_any())
+}
+
+pub fn validate_clustertunnel(req: &AdmissionRequest<ClusterTunnel>) -> AdmissionResponse {
+    let res = AdmissionResponse::from(req);
+
+    if req.operation != Operation::Update {
+        return res;
+    }
+
+    let (Some(old), Some(new)) = (req.old_object.as_ref(), req.object.as_ref()) else {
+        return res;
+    };
+
+    // renaming a created tunnel would orphan it on cloudflare's side
+    let created = old
+        .status
+        .as_ref()
+        .and_then(|status| status.tunnel_id.as_ref())
+        .is_some_and(|tunnel_id| !tunnel_id.is_empty());
+
+    if created && tunnel_name(old) != tunnel_name(new) {
+        return res.deny(
+            "spec.name is immutable after tunnel creation; delete and recreate the ClusterTunnel to rename",
+        );
+    }
+
+    res
+}
+
+// secrets outside the operator namespace need rbac the chart doesn't grant,
+// so the operator checks its own service account and warns on apply
+pub async fn secret_access_warnings(
+    client: &kube::Client,
+    operator_ns: &str,
+    tunnel: &ClusterTunnel,
+) -> Vec<String> {
+    let api: Api<SelfSubjectAccessReview> = Api::all(client.clone());
+    let mut warnings = vec![];
+
+    for secret_ref in tunnel.secret_refs() {
+        let ns = secret_ref.namespace_or(operator_ns);
+        if ns == operator_ns {
+            continue;
+        }
+
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    namespace: Some(ns.to_string()),
+                    verb: Some("get".to_string()),
+                    resource: Some("secrets".to_string()),
+                    name: Some(secret_ref.name.clone()),
+                    ..ResourceAttributes::default()
+                }),
+                ..SelfSubjectAccessReviewSpec::default()
+            },
+            ..SelfSubjectAccessReview::default()
+        };
+
+        let allowed = match api.create(&PostParams::default(), &review).await {
+            Ok(review) => review.status.is_some_and(|status| status.allowed),
+            Err(err) => {
+                warnings.push(format!(
+                    "could not check access to secret {ns}/{}: {err}",
+                    secret_ref.name
+                ));
+                continue;
+            }
+        };
+        if !allowed {
+            warnings.push(format!(
+                "operator can't read secret {ns}/{}, it needs get on secrets in {ns}",
+                secret_ref.name
+            ));
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::admission::{AdmissionRequest, AdmissionReview};
+    use serde_json::json;
+
+    use super::validate_clustertunnel;
+    use crate::ClusterTunnel;
+
+    fn clustertunnel(name: &str, tunnel_id: Option<&str>) -> serde_json::Value {
+        json!({
+            "apiVersion": "cloudflare-tunnels-operator.io/v1alpha1",
+            "kind": "ClusterTunnel",
+            "metadata": { "name": "tunnel" },
+            "spec": {
+                "name": name,
+                "cloudflare": {
+                    "accountId": "account",
+                    "apiTokenSecretRef": { "name": "cloudflare-api-token", "key": "token" },
+                },
+            },
+            "status": { "tunnelId": tunnel_id },
+        })
+    }
+
+    fn update(old: serde_json::Value, new: serde_json::Value) -> AdmissionRequest<ClusterTunnel> {
+        let review: AdmissionReview<ClusterTunnel> = serde_json::from_value(json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "uid",
+                "kind": {
+                    "group": "cloudflare-tunnels-operator.io",
+                    "version": "v1alpha1",
+                    "kind": "ClusterTunnel",
+                },
+                "resource": {
+                    "group": "cloudflare-tunnels-operator.io",
+                    "version": "v1alpha1",
+                    "resource": "clustertunnels",
+                },
+                "name": "tunnel",
+                "operation": "UPDATE",
+                "userInfo": {},
+                "object": new,
+                "oldObject": old,
+            },
+        }))
+        .unwrap();
+
+        review.try_into().unwrap()
+    }
+
+    #[test]
+    fn rename_is_denied_after_creation() {
+        let req = update(
+            clustertunnel("old", Some("tunnel-id")),
+            clustertunnel("new", Some("tunnel-id")),
+        );
+
+        assert!(!validate_clustertunnel(&req).allowed);
+    }
+
+    #[test]
+    fn rename_is_allowed_before_creation() {
+        let req = update(clustertunnel("old", None), clustertunnel("new", None));
+
+        assert!(validate_clustertunnel(&req).allowed);
+    }
+
+    #[test]
+    fn unchanged_name_is_allowed_after_creation() {
+        let req = update(
+            clustertunnel("tunnel", Some("tunnel-id")),
+            clustertunnel("tunnel", Some("tunnel-id")),
+        );
+
+        assert!(validate_clustertunnel(&req).allowed);
+    }
+}
@@ -0,0 +1,44 @@
+use kube::core::{
+    conversion::{ConversionRequest, ConversionResponse, ConversionReview},
+    Status,
+};
+
+const SERVED_VERSIONS: [&'static str; 1] = ["cloudflare-tunnels-operator.io/v1alpha1"];
+
+// only v1alpha1 is served so far, so converting is just rewriting the api
+// version; new versions add their field mapping here
+pub fn convert(review: ConversionReview) -> ConversionReview {
+    let req = match ConversionRequest::from_review(review) {
+        Ok(req) => req,
+        Err(err) => {
+            return ConversionResponse::invalid(Status::failure(&err.to_string(), "BadRequest"))
+                .into_review()
+        }
+    };
+
+    if !SERVED_VERSIONS.contains(&req.desired_api_version.as_str()) {
+        let message = format!("unsupported api version {}", req.desired_api_version);
+        return ConversionResponse::for_request(req)
+            .failure(Status::failure(&message, "BadRequest"))
+            .into_review();
+    }
+
+    let objects = req
+        .objects
+        .iter()
+        .cloned()
+        .map(|mut object| {
+            if let Some(fields) = object.as_object_mut() {
+                fields.insert(
+                    "apiVersion".to_string(),
+                    req.desired_api_version.clone().into(),
+                );
+            }
+            object
+        })
+        .collect();
+
+    ConversionResponse::for_request(req)
+        .success(objects)
+        .into_review()
+}
@@ -0,0 +1,922 @@
+// Runs the reconcilers against a real API server (e.g. a throwaway kind or
+// k3d cluster) with the Cloudflare API replaced by MockCloudflareClient. The
+// tests need a cluster, so they are ignored by default:
+//
+//   cargo test --test integration -- --ignored
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+use k8s_openapi::{
+    api::{
+        apps::v1::Deployment,
+        core::v1::{ConfigMap, Namespace, Secret, Service, ServiceAccount},
+        networking::v1::Ingress,
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+};
+use kube::{
+    api::{DeleteParams, Patch, PatchParams, PostParams},
+    runtime::{
+        events::{Recorder, Reporter},
+        wait::{await_condition, conditions},
+    },
+    Api, CustomResourceExt, ResourceExt,
+};
+use serde_json::json;
+
+use cloudflare_tunnels_operator::{
+    cloudflare::{
+        dns::{DnsContent, DnsRecord},
+        AccessApplication, ApiCache, CloudflareApi, CloudflareEnvironment, LoadBalancer,
+        LoadBalancerPool, TunnelConfig, TunnelConnection, TunnelCredentials, TunnelRoute,
+    },
+    controller::{self, FailureTracker},
+    metrics::Metrics,
+    telemetry, ClusterTunnel, Context, Error, TunnelPolicy,
+};
+
+const NAMESPACE: &'static str = "cloudflare-tunnels-operator-test";
+const MANAGER: &'static str = "cloudflare-tunnels-operator-test";
+
+#[derive(Default)]
+struct MockCloudflareClient {
+    tunnels: Mutex<BTreeMap<String, TunnelCredentials>>,
+    dns_records: Mutex<Vec<String>>,
+    load_balancer_pools: Mutex<BTreeMap<String, LoadBalancerPool>>,
+    // with the zone they were created in
+    load_balancers: Mutex<Vec<(String, LoadBalancer)>>,
+    // how often the context handed the client out
+    clients: AtomicUsize,
+}
+
+#[async_trait]
+impl CloudflareApi for MockCloudflareClient {
+    async fn create_tunnel(
+        &self,
+        tunnel_name: &str,
+        _remote_config: bool,
+    ) -> Result<TunnelCredentials, Error> {
+        let creds = TunnelCredentials {
+            account_tag: "account".to_string(),
+            tunnel_secret: "c2VjcmV0".to_string(),
+            tunnel_id: format!("{tunnel_name}-id"),
+        };
+        self.tunnels
+            .lock()
+            .unwrap()
+            .insert(tunnel_name.to_string(), creds.clone());
+
+        Ok(creds)
+    }
+
+    async fn find_tunnel(&self, tunnel_name: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .tunnels
+            .lock()
+            .unwrap()
+            .get(tunnel_name)
+            .map(|creds| creds.tunnel_id.clone()))
+    }
+
+    async fn get_tunnel(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        self.tunnels
+            .lock()
+            .unwrap()
+            .values()
+            .find(|creds| creds.tunnel_id == tunnel_id)
+            .cloned()
+            .ok_or_else(|| Error::TunnelNotFound {
+                name: tunnel_id.to_string(),
+            })
+    }
+
+    async fn get_tunnel_token(&self, tunnel_id: &str) -> Result<String, Error> {
+        Ok(format!("{tunnel_id}-token"))
+    }
+
+    async fn delete_tunnel(&self, tunnel_id: &str) -> Result<(), Error> {
+        self.tunnels
+            .lock()
+            .unwrap()
+            .retain(|_, creds| creds.tunnel_id != tunnel_id);
+
+        Ok(())
+    }
+
+    async fn rotate_tunnel_secret(&self, tunnel_id: &str) -> Result<TunnelCredentials, Error> {
+        let mut tunnels = self.tunnels.lock().unwrap();
+        let creds = tunnels
+            .values_mut()
+            .find(|creds| creds.tunnel_id == tunnel_id)
+            .ok_or_else(|| anyhow::anyhow!("tunnel {tunnel_id} not found"))?;
+        creds.tunnel_secret = "cm90YXRlZA==".to_string();
+
+        Ok(creds.clone())
+    }
+
+    async fn put_tunnel_configuration(
+        &self,
+        _tunnel_id: &str,
+        _config: &TunnelConfig,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn get_tunnel_connections(
+        &self,
+        _tunnel_id: &str,
+    ) -> Result<Vec<TunnelConnection>, Error> {
+        Ok(vec![])
+    }
+
+    async fn list_tunnel_routes(&self, _tunnel_id: &str) -> Result<Vec<TunnelRoute>, Error> {
+        Ok(vec![])
+    }
+
+    async fn add_tunnel_route(&self, _tunnel_id: &str, _network: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn delete_tunnel_route(&self, _route_id: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn create_dns_record(
+        &self,
+        _zone_id: &str,
+        hostname: &str,
+        _content: DnsContent,
+    ) -> Result<(), Error> {
+        self.dns_records.lock().unwrap().push(hostname.to_string());
+
+        Ok(())
+    }
+
+    async fn create_record(
+        &self,
+        _zone_id: &str,
+        _hostname: &str,
+        _content: DnsContent,
+        _ttl: Option<u32>,
+        _proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        Err(anyhow::anyhow!("not supported by the mock").into())
+    }
+
+    async fn update_dns_record(
+        &self,
+        _zone_id: &str,
+        _domain_id: &str,
+        _hostname: &str,
+        _tunnel_id: &str,
+        _content: Option<DnsContent>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn update_record(
+        &self,
+        _zone_id: &str,
+        _domain_id: &str,
+        _hostname: &str,
+        _content: DnsContent,
+        _ttl: Option<u32>,
+        _proxied: Option<bool>,
+    ) -> Result<DnsRecord, Error> {
+        Err(anyhow::anyhow!("not supported by the mock").into())
+    }
+
+    async fn list_dns_records(
+        &self,
+        _zone_id: &str,
+        _hostname: &str,
+    ) -> Result<Vec<Arc<DnsRecord>>, Error> {
+        Ok(Vec::new())
+    }
+
+    async fn find_zone_id(&self, _hostname: &str) -> Result<Option<String>, Error> {
+        Ok(Some("zone".to_string()))
+    }
+
+    async fn has_zone_access(&self, _zone_id: &str) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    async fn delete_dns_record(&self, _zone_id: &str, _domain_id: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn find_access_application(
+        &self,
+        _zone_id: &str,
+        _hostname: &str,
+    ) -> Result<Option<AccessApplication>, Error> {
+        Ok(None)
+    }
+
+    async fn create_access_application(
+        &self,
+        _zone_id: &str,
+        hostname: &str,
+    ) -> Result<AccessApplication, Error> {
+        Ok(AccessApplication {
+            id: format!("{hostname}-app"),
+            domain: hostname.to_string(),
+            aud: format!("{hostname}-aud"),
+        })
+    }
+
+    async fn create_access_policy(
+        &self,
+        _zone_id: &str,
+        _app_id: &str,
+        _policy_name: &str,
+        _include: &[serde_json::Value],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn delete_access_application(&self, _zone_id: &str, _app_id: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn get_load_balancer_pool(
+        &self,
+        pool_id: &str,
+    ) -> Result<Option<LoadBalancerPool>, Error> {
+        Ok(self
+            .load_balancer_pools
+            .lock()
+            .unwrap()
+            .get(pool_id)
+            .cloned())
+    }
+
+    async fn create_load_balancer_pool(
+        &self,
+        name: &str,
+        _origin: &str,
+    ) -> Result<LoadBalancerPool, Error> {
+        let pool = LoadBalancerPool {
+            id: format!("{name}-pool"),
+            name: name.to_string(),
+            monitor: None,
+        };
+        self.load_balancer_pools
+            .lock()
+            .unwrap()
+            .insert(pool.id.clone(), pool.clone());
+
+        Ok(pool)
+    }
+
+    async fn set_load_balancer_pool_monitor(
+        &self,
+        pool_id: &str,
+        monitor_id: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(pool) = self.load_balancer_pools.lock().unwrap().get_mut(pool_id) {
+            pool.monitor = monitor_id.map(str::to_string);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_load_balancer_pool(&self, pool_id: &str) -> Result<(), Error> {
+        self.load_balancer_pools.lock().unwrap().remove(pool_id);
+
+        Ok(())
+    }
+
+    async fn list_load_balancers(&self, zone_id: &str) -> Result<Vec<LoadBalancer>, Error> {
+        Ok(self
+            .load_balancers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(zone, _)| zone == zone_id)
+            .map(|(_, lb)| lb.clone())
+            .collect())
+    }
+
+    async fn create_load_balancer(
+        &self,
+        zone_id: &str,
+        hostname: &str,
+        pool_id: &str,
+    ) -> Result<LoadBalancer, Error> {
+        let lb = LoadBalancer {
+            id: format!("{hostname}-lb"),
+            name: hostname.to_string(),
+            default_pools: vec![pool_id.to_string()],
+        };
+        self.load_balancers
+            .lock()
+            .unwrap()
+            .push((zone_id.to_string(), lb.clone()));
+
+        Ok(lb)
+    }
+
+    async fn delete_load_balancer(
+        &self,
+        zone_id: &str,
+        load_balancer_id: &str,
+    ) -> Result<(), Error> {
+        self.load_balancers
+            .lock()
+            .unwrap()
+            .retain(|(zone, lb)| zone != zone_id || lb.id != load_balancer_id);
+
+        Ok(())
+    }
+
+    async fn set_zone_setting(
+        &self,
+        _zone_id: &str,
+        _name: &str,
+        _value: serde_json::Value,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// prometheus metrics are registered globally, so they can only be created once
+fn metrics() -> Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| Metrics::new().unwrap()).clone()
+}
+
+async fn apply_crd(client: &kube::Client, crd: CustomResourceDefinition) {
+    let crd_api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    crd_api
+        .patch(
+            &crd.name_any(),
+            &PatchParams::apply(MANAGER).force(),
+            &Patch::Apply(&crd),
+        )
+        .await
+        .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        await_condition(crd_api, &crd.name_any(), conditions::is_crd_established()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+async fn setup() -> (Arc<Context>, Arc<MockCloudflareClient>) {
+    let client = kube::Client::try_default().await.unwrap();
+
+    let ns_api: Api<Namespace> = Api::all(client.clone());
+    let ns: Namespace = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": { "name": NAMESPACE },
+    }))
+    .unwrap();
+    ns_api
+        .patch(NAMESPACE, &PatchParams::apply(MANAGER), &Patch::Apply(&ns))
+        .await
+        .unwrap();
+
+    apply_crd(&client, ClusterTunnel::crd()).await;
+    apply_crd(&client, TunnelPolicy::crd()).await;
+
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), NAMESPACE);
+    let secret: Secret = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": "cloudflare-api-token" },
+        "stringData": { "token": "token" },
+    }))
+    .unwrap();
+    secret_api
+        .patch(
+            "cloudflare-api-token",
+            &PatchParams::apply(MANAGER),
+            &Patch::Apply(&secret),
+        )
+        .await
+        .unwrap();
+
+    let mock = Arc::new(MockCloudflareClient::default());
+    let cloudflare = mock.clone();
+    let recorder = Recorder::new(
+        client.clone(),
+        Reporter {
+            controller: MANAGER.to_string(),
+            instance: None,
+        },
+    );
+
+    let ctx = Arc::new(Context {
+        kube_cli: client,
+        recorder,
+        ingress_class: None,
+        ingress_classes: DashSet::new(),
+        ingress_classes_changed: tokio::sync::watch::channel(()).0,
+        watch_namespace: None,
+        watch_label_selector: None,
+        operator_namespace: NAMESPACE.to_string(),
+        metrics: metrics(),
+        requeue_interval: Duration::from_secs(300),
+        error_requeue_interval: Duration::from_secs(5),
+        max_error_requeue_interval: Duration::from_secs(300),
+        failures: FailureTracker::default(),
+        config_locks: DashMap::new(),
+        cloudflare_cache: ApiCache::default(),
+        cloudflare_timeout: Duration::from_secs(30),
+        cloudflare_retries: 0,
+        cloudflare_environment: CloudflareEnvironment::Production,
+        cloudflare_api_url: String::new(),
+        cloudflare_dry_run: false,
+        cloudflare_client_factory: Arc::new(move |_, _, _| {
+            cloudflare.clients.fetch_add(1, Ordering::SeqCst);
+            Ok(cloudflare.clone() as Arc<dyn CloudflareApi>)
+        }),
+        active_reconciles: Arc::default(),
+        tracer: telemetry::tracer(),
+    });
+
+    (ctx, mock)
+}
+
+// the first reconciliation only adds the finalizer
+async fn reconcile_clustertunnel(ctx: Arc<Context>, name: &str) {
+    let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+    for _ in 0..2 {
+        let ct = ct_api.get(name).await.unwrap();
+        controller::clustertunnel::reconcile(Arc::new(ct), ctx.clone())
+            .await
+            .unwrap();
+    }
+}
+
+async fn reconcile_ingress(ctx: Arc<Context>, name: &str) {
+    let ing_api: Api<Ingress> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    for _ in 0..2 {
+        let ing = ing_api.get(name).await.unwrap();
+        controller::ingress::reconcile(Arc::new(ing), ctx.clone())
+            .await
+            .unwrap();
+    }
+}
+
+async fn create_clustertunnel(ctx: Arc<Context>, name: &str) {
+    create_clustertunnel_with(ctx, name, json!({})).await;
+}
+
+// extra is merged into the spec
+async fn create_clustertunnel_with(ctx: Arc<Context>, name: &str, extra: serde_json::Value) {
+    let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+    let mut spec = json!({
+        "cloudflare": {
+            "accountId": "account",
+            "zoneId": "zone",
+            "apiTokenSecretRef": { "name": "cloudflare-api-token", "key": "token" },
+        },
+    });
+    if let (Some(spec), Some(extra)) = (spec.as_object_mut(), extra.as_object()) {
+        spec.extend(extra.clone());
+    }
+    let ct: ClusterTunnel = serde_json::from_value(json!({
+        "apiVersion": "cloudflare-tunnels-operator.io/v1alpha1",
+        "kind": "ClusterTunnel",
+        "metadata": { "name": name },
+        "spec": spec,
+    }))
+    .unwrap();
+    ct_api.create(&PostParams::default(), &ct).await.unwrap();
+
+    reconcile_clustertunnel(ctx, name).await;
+}
+
+// backends whose service doesn't exist are skipped
+async fn create_service(ctx: Arc<Context>, name: &str) {
+    let svc_api: Api<Service> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let svc: Service = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": { "name": name },
+        "spec": { "ports": [{ "port": 80 }] },
+    }))
+    .unwrap();
+    svc_api.create(&PostParams::default(), &svc).await.unwrap();
+}
+
+async fn create_ingress(ctx: Arc<Context>, tunnel: &str, name: &str, host: &str) {
+    create_service(ctx.clone(), name).await;
+
+    let ing_api: Api<Ingress> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let ing: Ingress = serde_json::from_value(json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "Ingress",
+        "metadata": {
+            "name": name,
+            "annotations": { "cloudflare-tunnels.io/cluster-tunnel": tunnel },
+        },
+        "spec": {
+            "rules": [{
+                "host": host,
+                "http": {
+                    "paths": [{
+                        "path": "/",
+                        "pathType": "Prefix",
+                        "backend": { "service": { "name": name, "port": { "number": 80 } } },
+                    }],
+                },
+            }],
+        },
+    }))
+    .unwrap();
+    ing_api.create(&PostParams::default(), &ing).await.unwrap();
+}
+
+async fn tunnel_config(ctx: Arc<Context>, tunnel: &str) -> TunnelConfig {
+    let cm_api: Api<ConfigMap> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let cm = cm_api
+        .get(&format!("cloudflared-{tunnel}-config"))
+        .await
+        .unwrap();
+
+    serde_yaml::from_str(&cm.data.unwrap()["config.yaml"]).unwrap()
+}
+
+fn services(config: &TunnelConfig) -> Vec<String> {
+    config
+        .ingress
+        .iter()
+        .map(|ing| ing.service.clone())
+        .collect()
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn clustertunnel_creates_resources() {
+    let (ctx, mock) = setup().await;
+    create_clustertunnel(ctx.clone(), "it-resources").await;
+
+    assert!(mock.tunnels.lock().unwrap().contains_key("it-resources"));
+
+    let config = tunnel_config(ctx.clone(), "it-resources").await;
+    assert_eq!(config.tunnel, "it-resources-id");
+
+    let secret_api: Api<Secret> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let secret = secret_api
+        .get("cloudflared-it-resources-credentials")
+        .await
+        .unwrap();
+    let creds: TunnelCredentials =
+        serde_json::from_slice(&secret.data.unwrap()["credentials.json"].0).unwrap();
+    assert_eq!(creds.tunnel_id, "it-resources-id");
+
+    let deploy_api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let deploy = deploy_api.get("cloudflared-it-resources").await.unwrap();
+    let args = deploy.spec.unwrap().template.spec.unwrap().containers[0]
+        .args
+        .clone()
+        .unwrap();
+    assert!(args.contains(&"it-resources-id".to_string()));
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn clustertunnel_forwards_scheduling_constraints() {
+    let (ctx, _) = setup().await;
+    create_clustertunnel_with(
+        ctx.clone(),
+        "it-scheduling",
+        json!({
+            "nodeSelector": { "node-role.kubernetes.io/infra": "true" },
+            "tolerations": [{
+                "key": "dedicated",
+                "operator": "Equal",
+                "value": "infra",
+                "effect": "NoSchedule",
+            }],
+        }),
+    )
+    .await;
+
+    let deploy_api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let deploy = deploy_api.get("cloudflared-it-scheduling").await.unwrap();
+    let pod_spec = deploy.spec.unwrap().template.spec.unwrap();
+    assert_eq!(
+        pod_spec.node_selector,
+        Some(BTreeMap::from([(
+            "node-role.kubernetes.io/infra".to_string(),
+            "true".to_string()
+        )]))
+    );
+    let tolerations = pod_spec.tolerations.unwrap();
+    assert_eq!(tolerations.len(), 1);
+    assert_eq!(tolerations[0].key.as_deref(), Some("dedicated"));
+    assert_eq!(tolerations[0].operator.as_deref(), Some("Equal"));
+    assert_eq!(tolerations[0].value.as_deref(), Some("infra"));
+    assert_eq!(tolerations[0].effect.as_deref(), Some("NoSchedule"));
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn clustertunnel_uses_own_service_account() {
+    let (ctx, _) = setup().await;
+    create_clustertunnel_with(
+        ctx.clone(),
+        "it-service-account",
+        json!({
+            "serviceAccountAnnotations": {
+                "eks.amazonaws.com/role-arn": "arn:aws:iam::123456789012:role/cloudflared",
+            },
+        }),
+    )
+    .await;
+
+    let deploy_api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let deploy = deploy_api
+        .get("cloudflared-it-service-account")
+        .await
+        .unwrap();
+    let pod_spec = deploy.spec.unwrap().template.spec.unwrap();
+    assert_eq!(
+        pod_spec.service_account_name.as_deref(),
+        Some("cloudflared-it-service-account")
+    );
+
+    let sa_api: Api<ServiceAccount> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let sa = sa_api.get("cloudflared-it-service-account").await.unwrap();
+    assert_eq!(sa.automount_service_account_token, Some(false));
+    assert_eq!(
+        sa.annotations()
+            .get("eks.amazonaws.com/role-arn")
+            .map(String::as_str),
+        Some("arn:aws:iam::123456789012:role/cloudflared")
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn clustertunnel_token_mode_skips_api() {
+    let (ctx, mock) = setup().await;
+
+    // {"a":"account","t":"it-token-id","s":"c2VjcmV0"}
+    let secret_api: Api<Secret> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let secret: Secret = serde_json::from_value(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": "cloudflare-tunnel-token" },
+        "stringData": {
+            "token": "eyJhIjoiYWNjb3VudCIsInQiOiJpdC10b2tlbi1pZCIsInMiOiJjMlZqY21WMCJ9",
+        },
+    }))
+    .unwrap();
+    secret_api
+        .patch(
+            "cloudflare-tunnel-token",
+            &PatchParams::apply(MANAGER),
+            &Patch::Apply(&secret),
+        )
+        .await
+        .unwrap();
+
+    create_clustertunnel_with(
+        ctx.clone(),
+        "it-token",
+        json!({
+            "cloudflare": {
+                "accountId": "account",
+                "tunnelTokenSecretRef": { "name": "cloudflare-tunnel-token", "key": "token" },
+            },
+        }),
+    )
+    .await;
+
+    assert_eq!(mock.clients.load(Ordering::SeqCst), 0);
+
+    let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+    let status = ct_api.get("it-token").await.unwrap().status.unwrap();
+    assert_eq!(status.tunnel_id.as_deref(), Some("it-token-id"));
+
+    let deploy_api: Api<Deployment> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let deploy = deploy_api.get("cloudflared-it-token").await.unwrap();
+    let container = &deploy.spec.unwrap().template.spec.unwrap().containers[0];
+    assert!(container
+        .env
+        .iter()
+        .flatten()
+        .any(|var| var.name == "TUNNEL_TOKEN"));
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn ingress_updates_config() {
+    let (ctx, mock) = setup().await;
+    create_clustertunnel(ctx.clone(), "it-ingress").await;
+    create_ingress(
+        ctx.clone(),
+        "it-ingress",
+        "it-ingress-web",
+        "web.example.com",
+    )
+    .await;
+    reconcile_ingress(ctx.clone(), "it-ingress-web").await;
+
+    let config = tunnel_config(ctx.clone(), "it-ingress").await;
+    assert_eq!(
+        services(&config),
+        vec![
+            format!("http://it-ingress-web.{NAMESPACE}.svc:80"),
+            "http_status:404".to_string(),
+        ]
+    );
+    assert_eq!(
+        config.ingress[0].hostname.as_deref(),
+        Some("web.example.com")
+    );
+    assert!(mock
+        .dns_records
+        .lock()
+        .unwrap()
+        .contains(&"web.example.com".to_string()));
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn ingress_skips_missing_service() {
+    let (ctx, _) = setup().await;
+    create_clustertunnel(ctx.clone(), "it-missing").await;
+    create_ingress(
+        ctx.clone(),
+        "it-missing",
+        "it-missing-web",
+        "missing.example.com",
+    )
+    .await;
+
+    let svc_api: Api<Service> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    svc_api
+        .delete("it-missing-web", &DeleteParams::default())
+        .await
+        .unwrap();
+    reconcile_ingress(ctx.clone(), "it-missing-web").await;
+
+    let config = tunnel_config(ctx.clone(), "it-missing").await;
+    assert_eq!(services(&config), vec!["http_status:404".to_string()]);
+
+    let ing_api: Api<Ingress> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    let ing = ing_api.get("it-missing-web").await.unwrap();
+    assert_eq!(
+        ing.annotations()
+            .get("cloudflare-tunnels.io/status")
+            .map(String::as_str),
+        Some("service-not-found")
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn ingress_cleanup_removes_config() {
+    let (ctx, _) = setup().await;
+    create_clustertunnel(ctx.clone(), "it-cleanup").await;
+    create_ingress(
+        ctx.clone(),
+        "it-cleanup",
+        "it-cleanup-web",
+        "cleanup.example.com",
+    )
+    .await;
+    reconcile_ingress(ctx.clone(), "it-cleanup-web").await;
+
+    // the finalizer keeps the ingress around until it is reconciled again
+    let ing_api: Api<Ingress> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    ing_api
+        .delete("it-cleanup-web", &DeleteParams::default())
+        .await
+        .unwrap();
+    let ing = ing_api.get("it-cleanup-web").await.unwrap();
+    controller::ingress::reconcile(Arc::new(ing), ctx.clone())
+        .await
+        .unwrap();
+
+    let config = tunnel_config(ctx.clone(), "it-cleanup").await;
+    assert!(!services(&config)
+        .iter()
+        .any(|service| service.contains("it-cleanup-web")));
+    assert!(ing_api.get_opt("it-cleanup-web").await.unwrap().is_none());
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn concurrent_ingresses_keep_all_routes() {
+    let (ctx, _) = setup().await;
+    create_clustertunnel(ctx.clone(), "it-concurrent").await;
+    create_ingress(
+        ctx.clone(),
+        "it-concurrent",
+        "it-concurrent-a",
+        "a.example.com",
+    )
+    .await;
+    create_ingress(
+        ctx.clone(),
+        "it-concurrent",
+        "it-concurrent-b",
+        "b.example.com",
+    )
+    .await;
+
+    tokio::join!(
+        reconcile_ingress(ctx.clone(), "it-concurrent-a"),
+        reconcile_ingress(ctx.clone(), "it-concurrent-b"),
+    );
+
+    let services = services(&tunnel_config(ctx.clone(), "it-concurrent").await);
+    assert!(services.contains(&format!("http://it-concurrent-a.{NAMESPACE}.svc:80")));
+    assert!(services.contains(&format!("http://it-concurrent-b.{NAMESPACE}.svc:80")));
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn disabling_load_balancer_deletes_pool() {
+    let (ctx, mock) = setup().await;
+    create_clustertunnel_with(
+        ctx.clone(),
+        "it-lb-disable",
+        json!({ "createLoadBalancer": true }),
+    )
+    .await;
+    assert!(mock
+        .load_balancer_pools
+        .lock()
+        .unwrap()
+        .contains_key("it-lb-disable-pool"));
+
+    let ct_api: Api<ClusterTunnel> = Api::all(ctx.kube_cli.clone());
+    ct_api
+        .patch(
+            "it-lb-disable",
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "spec": { "createLoadBalancer": false } })),
+        )
+        .await
+        .unwrap();
+    reconcile_clustertunnel(ctx.clone(), "it-lb-disable").await;
+
+    assert!(mock.load_balancer_pools.lock().unwrap().is_empty());
+    let ct = ct_api.get("it-lb-disable").await.unwrap();
+    assert!(ct.status.unwrap().load_balancer_pool_id.is_none());
+}
+
+#[tokio::test]
+#[ignore = "needs a kubernetes cluster"]
+async fn shared_load_balancer_outlives_ingress() {
+    let (ctx, mock) = setup().await;
+    create_clustertunnel_with(
+        ctx.clone(),
+        "it-lb-shared",
+        json!({ "createLoadBalancer": true }),
+    )
+    .await;
+    create_ingress(
+        ctx.clone(),
+        "it-lb-shared",
+        "it-lb-shared-a",
+        "shared.example.com",
+    )
+    .await;
+    create_ingress(
+        ctx.clone(),
+        "it-lb-shared",
+        "it-lb-shared-b",
+        "shared.example.com",
+    )
+    .await;
+    reconcile_ingress(ctx.clone(), "it-lb-shared-a").await;
+    reconcile_ingress(ctx.clone(), "it-lb-shared-b").await;
+
+    let ing_api: Api<Ingress> = Api::namespaced(ctx.kube_cli.clone(), NAMESPACE);
+    ing_api
+        .delete("it-lb-shared-a", &DeleteParams::default())
+        .await
+        .unwrap();
+    let ing = ing_api.get("it-lb-shared-a").await.unwrap();
+    controller::ingress::reconcile(Arc::new(ing), ctx.clone())
+        .await
+        .unwrap();
+
+    let load_balancers = mock.load_balancers.lock().unwrap();
+    assert_eq!(load_balancers.len(), 1);
+    assert_eq!(load_balancers[0].1.name, "shared.example.com");
+}